@@ -0,0 +1,261 @@
+//! File transfer for `/upload` and `/download`. Files go to the server in
+//! chunks of the size it asks for, as Cap'n Web bytes, and come back one
+//! stored chunk per call.
+
+use crate::websocket_client::{Attachment, WebSocketClient};
+use capnweb_core::CapId;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// The server refuses anything larger, so we check before sending.
+pub const MAX_UPLOAD_BYTES: u64 = 8 * 1024 * 1024;
+
+// Enough of a file to recognize its type.
+const SNIFF_BYTES: usize = 512;
+
+/// Guess the MIME type from the first bytes of a file, then its extension.
+pub fn detect_mime(path: &Path, head: &[u8]) -> &'static str {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return mime;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let by_extension = match extension.as_deref() {
+        Some("txt" | "log") => Some("text/plain"),
+        Some("md") => Some("text/markdown"),
+        Some("csv") => Some("text/csv"),
+        Some("html" | "htm") => Some("text/html"),
+        Some("css") => Some("text/css"),
+        Some("js") => Some("text/javascript"),
+        Some("json") => Some("application/json"),
+        Some("toml") => Some("application/toml"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("mp4") => Some("video/mp4"),
+        Some("wav") => Some("audio/wav"),
+        _ => None,
+    };
+    if let Some(mime) = by_extension {
+        return mime;
+    }
+    // Unknown extensions holding readable text are still text
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => Some(text),
+        // The sniffed bytes may end inside a character
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    };
+    if text.is_some_and(|text| !text.contains('\0')) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// `512 B`, `1.5 KiB` or `3.2 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// Send the file at `path` to the server and return the stored attachment.
+pub async fn upload(
+    client: &WebSocketClient,
+    capability: CapId,
+    path: &Path,
+) -> Result<Attachment, String> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|err| format!("cannot open {}: {}", path.display(), err))?;
+    let metadata = file
+        .metadata()
+        .await
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+    let size = metadata.len();
+    if size == 0 {
+        return Err(format!("{} is empty", path.display()));
+    }
+    if size > MAX_UPLOAD_BYTES {
+        return Err(format!(
+            "{} is {}, over the {} limit",
+            path.display(),
+            format_size(size),
+            format_size(MAX_UPLOAD_BYTES)
+        ));
+    }
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("{} has no file name", path.display()))?;
+
+    let read_error = |err: std::io::Error| format!("cannot read {}: {}", path.display(), err);
+    let mut head = vec![0; SNIFF_BYTES];
+    let read = read_chunk(&mut file, &mut head).await.map_err(read_error)?;
+    let mime = detect_mime(path, &head[..read]);
+    file.rewind().await.map_err(read_error)?;
+
+    let ticket = client
+        .begin_upload(capability, &name, size, mime)
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut buffer = vec![0; ticket.chunk_size.max(1)];
+    loop {
+        let read = read_chunk(&mut file, &mut buffer)
+            .await
+            .map_err(read_error)?;
+        if read == 0 {
+            break;
+        }
+        client
+            .upload_chunk(capability, ticket.upload, &buffer[..read])
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    client
+        .finish_upload(capability, ticket.upload)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+// Fill `buffer` unless the file ends first; returns the bytes read.
+async fn read_chunk(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Save attachment `id` to `dest`, a file or a directory, or the current
+/// directory when None. Existing files are never overwritten.
+pub async fn download(
+    client: &WebSocketClient,
+    capability: CapId,
+    id: u64,
+    dest: Option<&Path>,
+) -> Result<(PathBuf, Attachment), String> {
+    let first = client
+        .download_chunk(capability, id, 0)
+        .await
+        .map_err(|err| err.to_string())?;
+    let attachment = first.attachment;
+    // Only the last component of the uploader's name, never a path
+    let name = Path::new(&attachment.name)
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(format!("attachment-{}", id)));
+    let path = match dest {
+        Some(dest) if fs::metadata(dest).await.is_ok_and(|meta| meta.is_dir()) => dest.join(name),
+        Some(dest) => dest.to_path_buf(),
+        None => name,
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await
+        .map_err(|err| format!("cannot create {}: {}", path.display(), err))?;
+    let result = async {
+        let mut written = first.data.len() as u64;
+        file.write_all(&first.data)
+            .await
+            .map_err(|err| err.to_string())?;
+        for chunk in 1..first.chunks {
+            let next = client
+                .download_chunk(capability, id, chunk)
+                .await
+                .map_err(|err| err.to_string())?;
+            written += next.data.len() as u64;
+            file.write_all(&next.data)
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        file.flush().await.map_err(|err| err.to_string())?;
+        if written != attachment.size {
+            return Err(format!("got {} of {} bytes", written, attachment.size));
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(err) = result {
+        // Leave no partial file behind
+        let _ = fs::remove_file(&path).await;
+        return Err(format!("{}: {}", path.display(), err));
+    }
+    Ok((path, attachment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_types_come_from_content_then_extension() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(detect_mime(Path::new("shot.txt"), png), "image/png");
+        assert_eq!(
+            detect_mime(Path::new("a.webp"), b"RIFF\0\0\0\0WEBPVP8 "),
+            "image/webp"
+        );
+        assert_eq!(detect_mime(Path::new("notes.MD"), b"# Hi"), "text/markdown");
+        assert_eq!(
+            detect_mime(Path::new("Makefile"), b"all:\n\tcargo"),
+            "text/plain"
+        );
+        assert_eq!(
+            detect_mime(Path::new("blob"), b"\0\x01\x02"),
+            "application/octet-stream"
+        );
+        // A multi-byte character cut off at the end of the sniffed bytes
+        assert_eq!(
+            detect_mime(Path::new("utf8"), "héllo".as_bytes()[..2].as_ref()),
+            "text/plain"
+        );
+    }
+
+    #[test]
+    fn sizes_use_binary_units() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(MAX_UPLOAD_BYTES), "8.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
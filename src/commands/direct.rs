@@ -0,0 +1,187 @@
+use super::{ArgKind, Args, Command, CommandContext, STATUS_HELP};
+use crate::ratatui_client::{BufferKind, ChatMessage, now_millis};
+use crate::websocket_client::MessageKind;
+use futures_util::future::BoxFuture;
+
+pub struct Msg;
+
+impl Command for Msg {
+    fn name(&self) -> &'static str {
+        "msg"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick> <text>"
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn help(&self) -> &'static str {
+        "Send a private message"
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Nick
+        } else {
+            ArgKind::Free
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            // Keep the message text intact rather than re-joining split words
+            let nick = args.get(0).unwrap_or_default();
+            send_direct_message(ctx, nick, args.rest(1), MessageKind::Normal).await;
+        })
+    }
+}
+
+pub struct Me;
+
+impl Command for Me {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<action>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Describe what you are doing, shown as \"* nick action\""
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move { send_text_as(ctx, args.rest(0), MessageKind::Action).await })
+    }
+}
+
+pub struct Notice;
+
+impl Command for Notice {
+    fn name(&self) -> &'static str {
+        "notice"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<#room|nick> <text>"
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn help(&self) -> &'static str {
+        "Send a notice, which never triggers notifications"
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Nick
+        } else {
+            ArgKind::Free
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let target = args.get(0).unwrap_or_default();
+            send_to(ctx, target, args.rest(1), MessageKind::Notice).await;
+        })
+    }
+}
+
+pub struct Query;
+
+impl Command for Query {
+    fn name(&self) -> &'static str {
+        "query"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Open a private conversation tab"
+    }
+
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Nick
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            match args.get(0) {
+                Some(nick) if !nick.starts_with('#') => ctx.ui.open_buffer(nick),
+                _ => ctx.system(format!("Usage: /{} {}", self.name(), self.usage())),
+            }
+        })
+    }
+}
+
+/// Send text typed without a leading slash to the active room or query.
+pub async fn send_text(ctx: &mut CommandContext, text: &str) {
+    send_text_as(ctx, text, MessageKind::Normal).await;
+}
+
+async fn send_text_as(ctx: &mut CommandContext, text: &str, kind: MessageKind) {
+    if ctx.ui.active_buffer_kind() == BufferKind::Mentions {
+        ctx.system("The mentions buffer is read-only; switch to a room or query to reply.");
+        return;
+    }
+    let target = ctx.ui.active_buffer_name();
+    send_to(ctx, &target, text, kind).await;
+}
+
+// A `#room` gets a room message, anything else a direct message.
+async fn send_to(ctx: &mut CommandContext, target: &str, text: &str, kind: MessageKind) {
+    if BufferKind::for_name(target) != BufferKind::Room {
+        send_direct_message(ctx, target, text, kind).await;
+        return;
+    }
+    match ctx
+        .client
+        .send_room_message(ctx.session.capability, target, text, kind)
+        .await
+    {
+        Ok(_) => ctx.status(STATUS_HELP, false),
+        Err(e) => ctx.status(format!("Failed to send message: {}", e), true),
+    }
+}
+
+async fn send_direct_message(ctx: &mut CommandContext, nick: &str, text: &str, kind: MessageKind) {
+    match ctx
+        .client
+        .send_direct_message(ctx.session.capability, nick, text, kind)
+        .await
+    {
+        Ok(()) => {
+            // The server only delivers to the recipient, so echo locally
+            ctx.ui.add_message_to(
+                nick,
+                ChatMessage {
+                    from: ctx.session.nickname.clone(),
+                    body: text.to_string(),
+                    timestamp: now_millis(),
+                    kind,
+                    ..Default::default()
+                },
+                100,
+            );
+            ctx.status(STATUS_HELP, false);
+        }
+        Err(e) => ctx.status(format!("Failed to message {}: {}", nick, e), true),
+    }
+}
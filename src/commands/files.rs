@@ -0,0 +1,161 @@
+use super::{Args, Command, CommandContext};
+use crate::attachments::{self, format_size};
+use crate::ratatui_client::{BufferKind, ChatMessage, now_millis};
+use crate::ui_events::UiHandle;
+use crate::websocket_client::MessageKind;
+use futures_util::future::BoxFuture;
+use std::path::PathBuf;
+
+pub struct Upload;
+
+impl Command for Upload {
+    fn name(&self) -> &'static str {
+        "upload"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<path>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Send a file of up to 8 MiB to this room or query"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let target = ctx.ui.active_buffer_name();
+            if ctx.ui.active_buffer_kind() == BufferKind::Mentions {
+                ctx.system("Switch to a room or query to upload a file.");
+                return;
+            }
+            let path = expand_home(args.rest(0));
+            ctx.system(format!("Uploading {}…", path.display()));
+
+            // Transfers outlast the command timeout, so they run on their own
+            let client = ctx.client.clone();
+            let capability = ctx.session.capability;
+            let nickname = ctx.session.nickname.clone();
+            let mut ui = ctx.ui.clone();
+            tokio::spawn(async move {
+                let attachment = match attachments::upload(&client, capability, &path).await {
+                    Ok(attachment) => attachment,
+                    Err(err) => {
+                        report(
+                            &mut ui,
+                            MessageKind::Error,
+                            format!("Upload failed: {}", err),
+                        );
+                        return;
+                    }
+                };
+                if let Err(err) = client
+                    .send_attachment(capability, &target, &attachment)
+                    .await
+                {
+                    report(
+                        &mut ui,
+                        MessageKind::Error,
+                        format!(
+                            "Uploaded {} but could not send it: {}",
+                            attachment.name, err
+                        ),
+                    );
+                    return;
+                }
+                // Room messages come back from the server; direct ones do not
+                if BufferKind::for_name(&target) == BufferKind::Query {
+                    ui.add_message_to(
+                        &target,
+                        ChatMessage {
+                            from: nickname,
+                            body: attachment.name.clone(),
+                            timestamp: now_millis(),
+                            attachment: Some(attachment),
+                            ..Default::default()
+                        },
+                        100,
+                    );
+                }
+            });
+        })
+    }
+}
+
+pub struct Download;
+
+impl Command for Download {
+    fn name(&self) -> &'static str {
+        "download"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<id> [path]"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Save an attachment to a file or directory (default: here)"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(id) = args.get(0).and_then(|id| id.parse::<u64>().ok()) else {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            };
+            let dest = Some(args.rest(1))
+                .filter(|dest| !dest.is_empty())
+                .map(expand_home);
+
+            let client = ctx.client.clone();
+            let capability = ctx.session.capability;
+            let mut ui = ctx.ui.clone();
+            tokio::spawn(async move {
+                match attachments::download(&client, capability, id, dest.as_deref()).await {
+                    Ok((path, attachment)) => report(
+                        &mut ui,
+                        MessageKind::System,
+                        format!(
+                            "Saved {} ({}) to {}",
+                            attachment.name,
+                            format_size(attachment.size),
+                            path.display()
+                        ),
+                    ),
+                    Err(err) => report(
+                        &mut ui,
+                        MessageKind::Error,
+                        format!("Download of {} failed: {}", id, err),
+                    ),
+                }
+            });
+        })
+    }
+}
+
+// Background transfers report with a message, as the status line may have
+// moved on by the time they finish.
+fn report(ui: &mut UiHandle, kind: MessageKind, body: String) {
+    ui.add_message(ChatMessage {
+        from: "System".to_string(),
+        kind,
+        body,
+        timestamp: now_millis(),
+        ..Default::default()
+    });
+}
+
+// `~/notes.txt` the way a shell would read it.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,155 @@
+use super::{Args, Command, CommandContext};
+use futures_util::future::BoxFuture;
+
+pub struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[command]"
+    }
+
+    fn help(&self) -> &'static str {
+        "Show this help, or the usage of one command"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(name) = args.get(0) else {
+                let text = ctx.registry.help_text();
+                ctx.system(text);
+                return;
+            };
+            let body = match ctx.registry.find(name) {
+                Some(command) => format!(
+                    "Usage: /{} {}\n  {}",
+                    command.name(),
+                    command.usage(),
+                    command.help()
+                ),
+                None => format!("Unknown command `{}`", name),
+            };
+            ctx.system(body);
+        })
+    }
+}
+
+pub struct Whoami;
+
+impl Command for Whoami {
+    fn name(&self) -> &'static str {
+        "whoami"
+    }
+
+    fn help(&self) -> &'static str {
+        "Show current session"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            match ctx.client.whoami(ctx.session.capability).await {
+                Ok(result) => ctx.system(format!("You are: {:?}", result)),
+                Err(e) => ctx.status(format!("Whoami failed: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Away;
+
+impl Command for Away {
+    fn name(&self) -> &'static str {
+        "away"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[reason]"
+    }
+
+    fn help(&self) -> &'static str {
+        "Mark yourself away; direct messages get the reason as a reply"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let reason = match args.rest(0) {
+                "" => "Away",
+                reason => reason,
+            };
+            match ctx
+                .client
+                .set_away(ctx.session.capability, Some(reason))
+                .await
+            {
+                Ok(()) => {
+                    ctx.ui.set_away(Some(reason.to_string()));
+                    ctx.system(format!("You are now marked as away: {}", reason));
+                }
+                Err(e) => ctx.status(format!("Failed to set away: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Back;
+
+impl Command for Back {
+    fn name(&self) -> &'static str {
+        "back"
+    }
+
+    fn help(&self) -> &'static str {
+        "Clear your away status"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            match ctx.client.set_away(ctx.session.capability, None).await {
+                Ok(()) => {
+                    ctx.ui.set_away(None);
+                    ctx.system("You are no longer marked as away.");
+                }
+                Err(e) => ctx.status(format!("Failed to clear away: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Links;
+
+impl Command for Links {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn help(&self) -> &'static str {
+        "Pick a recent link in this buffer and open it"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move { ctx.ui.show_links() })
+    }
+}
+
+pub struct Quit;
+
+impl Command for Quit {
+    fn name(&self) -> &'static str {
+        "quit"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["exit"]
+    }
+
+    fn help(&self) -> &'static str {
+        "Exit the client"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move { ctx.ui.quit() })
+    }
+}
@@ -0,0 +1,108 @@
+use super::{ArgKind, Args, Command, CommandContext};
+use crate::ignore::IgnoreRule;
+use futures_util::future::BoxFuture;
+
+pub struct Ignore;
+
+impl Command for Ignore {
+    fn name(&self) -> &'static str {
+        "ignore"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick|from:regex|body:regex>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Hide messages from a nick, or whose sender or body matches a regex"
+    }
+
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Nick
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let rule = match IgnoreRule::parse(args.rest(0)) {
+                Ok(rule) => rule,
+                Err(err) => {
+                    ctx.system(format!("Cannot ignore that: {}", err));
+                    return;
+                }
+            };
+            // The main loop changes the live list, then reports and saves
+            ctx.ui.ignore(rule);
+        })
+    }
+}
+
+pub struct Unignore;
+
+impl Command for Unignore {
+    fn name(&self) -> &'static str {
+        "unignore"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick|from:regex|body:regex>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Take a nick or filter off the ignore list"
+    }
+
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Nick
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            match IgnoreRule::parse(args.rest(0)) {
+                Ok(rule) => ctx.ui.unignore(rule),
+                Err(_) => ctx.system(format!(
+                    "{} is not on the ignore list. Type /ignores to see it.",
+                    args.rest(0)
+                )),
+            }
+        })
+    }
+}
+
+pub struct Ignores;
+
+impl Command for Ignores {
+    fn name(&self) -> &'static str {
+        "ignores"
+    }
+
+    fn help(&self) -> &'static str {
+        "List ignored nicks and filters"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let ignores = ctx.ui.ignores();
+            if ignores.is_empty() {
+                ctx.system("The ignore list is empty.");
+                return;
+            }
+            let mut text = String::from("Ignoring:");
+            for rule in ignores.rules() {
+                text.push_str(&format!("\n  {}", rule));
+            }
+            let hidden = ctx.ui.hidden();
+            if hidden > 0 {
+                text.push_str(&format!("\n{} hidden in this buffer.", hidden));
+            }
+            ctx.system(text);
+        })
+    }
+}
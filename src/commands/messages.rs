@@ -0,0 +1,198 @@
+use super::{Args, Command, CommandContext, STATUS_HELP};
+use crate::ratatui_client::BufferKind;
+use futures_util::future::BoxFuture;
+
+pub struct Edit;
+
+impl Command for Edit {
+    fn name(&self) -> &'static str {
+        "edit"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<id> <text>"
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn help(&self) -> &'static str {
+        "Correct one of your messages (Up on an empty line fills this in)"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(id) = args.get(0).and_then(|id| id.parse::<u64>().ok()) else {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            };
+            // The server echoes the edit back to every member of the room
+            match ctx
+                .client
+                .edit_message(ctx.session.capability, id, args.rest(1))
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to edit message: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Delete;
+
+impl Command for Delete {
+    fn name(&self) -> &'static str {
+        "delete"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[id]"
+    }
+
+    fn help(&self) -> &'static str {
+        "Delete one of your messages, by default the last one here"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let id = match args.get(0) {
+                Some(id) => match id.parse::<u64>() {
+                    Ok(id) => id,
+                    Err(_) => {
+                        ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                        return;
+                    }
+                },
+                None => match ctx.ui.last_own_message() {
+                    Some(id) => id,
+                    None => {
+                        ctx.system("You have no messages here to delete.");
+                        return;
+                    }
+                },
+            };
+            match ctx.client.delete_message(ctx.session.capability, id).await {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to delete message: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Reply;
+
+impl Command for Reply {
+    fn name(&self) -> &'static str {
+        "reply"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<id> <text>"
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn help(&self) -> &'static str {
+        "Reply to a message (Ctrl+R picks one)"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(parent) = args.get(0).and_then(|id| id.parse::<u64>().ok()) else {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            };
+            if ctx.ui.active_buffer_kind() != BufferKind::Room {
+                ctx.system("Replies can only be sent in rooms.");
+                return;
+            }
+            let room = ctx.ui.active_buffer_name();
+            match ctx
+                .client
+                .send_reply(ctx.session.capability, &room, parent, args.rest(1))
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to send reply: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct React;
+
+impl Command for React {
+    fn name(&self) -> &'static str {
+        "react"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<id> <emoji>"
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn help(&self) -> &'static str {
+        "React to a message (1-4 in selection mode toggle quick reactions)"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(id) = args.get(0).and_then(|id| id.parse::<u64>().ok()) else {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            };
+            match ctx
+                .client
+                .react(ctx.session.capability, id, args.rest(1))
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to react: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Unreact;
+
+impl Command for Unreact {
+    fn name(&self) -> &'static str {
+        "unreact"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<id> <emoji>"
+    }
+
+    fn min_args(&self) -> usize {
+        2
+    }
+
+    fn help(&self) -> &'static str {
+        "Take back one of your reactions"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(id) = args.get(0).and_then(|id| id.parse::<u64>().ok()) else {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            };
+            match ctx
+                .client
+                .unreact(ctx.session.capability, id, args.rest(1))
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to remove reaction: {}", e), true),
+            }
+        })
+    }
+}
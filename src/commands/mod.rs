@@ -0,0 +1,326 @@
+//! Slash-command dispatch. Each command is a `Command` registered with the
+//! `Registry`, which parses input, checks arguments, generates `/help` and
+//! feeds Tab completion.
+
+mod direct;
+mod files;
+mod general;
+mod ignore;
+mod messages;
+mod moderation;
+mod nickserv;
+mod rooms;
+
+use crate::ratatui_client::{ChatMessage, Session, now_millis};
+use crate::ui_events::UiHandle;
+use crate::websocket_client::{MessageKind, WebSocketClient};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+pub const STATUS_HELP: &str = "Type /help for commands | Press Ctrl+C to quit";
+
+pub fn format_status(nickname: &str, server_url: &str, detail: impl AsRef<str>) -> String {
+    let detail = detail.as_ref();
+    if detail.is_empty() {
+        format!("Server: {} | Nick: {}", server_url, nickname)
+    } else {
+        format!("Server: {} | Nick: {} | {}", server_url, nickname, detail)
+    }
+}
+
+/// Everything a command can touch while it runs.
+pub struct CommandContext {
+    pub registry: Arc<Registry>,
+    pub client: Arc<WebSocketClient>,
+    pub session: Session,
+    pub server_url: String,
+    pub ui: UiHandle,
+}
+
+impl CommandContext {
+    /// Show a message from "System" in the active buffer.
+    pub fn system(&mut self, body: impl Into<String>) {
+        self.ui.add_message(ChatMessage {
+            from: "System".to_string(),
+            kind: MessageKind::System,
+            body: body.into(),
+            timestamp: now_millis(),
+            ..Default::default()
+        });
+    }
+
+    pub fn status(&mut self, detail: impl AsRef<str>, is_error: bool) {
+        let status = format_status(&self.session.nickname, &self.server_url, detail);
+        self.ui.set_status(status, is_error);
+    }
+}
+
+/// The words following a command name.
+pub struct Args {
+    raw: String,
+    words: Vec<String>,
+}
+
+impl Args {
+    fn parse(raw: &str) -> Self {
+        Self {
+            raw: raw.trim().to_string(),
+            words: raw.split_whitespace().map(str::to_string).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.words.get(index).map(String::as_str)
+    }
+
+    /// Everything after the first `skip` words, with its spacing intact.
+    pub fn rest(&self, skip: usize) -> &str {
+        let mut rest = self.raw.as_str();
+        for _ in 0..skip {
+            rest = rest
+                .trim_start()
+                .split_once(char::is_whitespace)
+                .map_or("", |(_, tail)| tail);
+        }
+        rest.trim()
+    }
+}
+
+/// What an argument position completes to.
+pub enum ArgKind {
+    Free,
+    Nick,
+    Room,
+    Choice(&'static [&'static str]),
+}
+
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Argument synopsis for help and usage errors, e.g. `<room>`.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// Fewer arguments than this prints the usage instead of running.
+    fn min_args(&self) -> usize {
+        0
+    }
+
+    fn help(&self) -> &'static str;
+
+    /// Completion hook for the argument at `index`.
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Free
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()>;
+}
+
+/// A prompt the UI is holding open on behalf of a command. The answer is
+/// routed back through `Registry::resolve` with the interaction intact.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PendingInteraction {
+    IdentifyPassword { nick: String },
+    RegisterPassword { nick: String },
+}
+
+impl PendingInteraction {
+    pub fn prompt(&self) -> String {
+        match self {
+            Self::IdentifyPassword { nick } => format!("Password for nickname '{}'", nick),
+            Self::RegisterPassword { nick } => format!("Password for new nickname '{}'", nick),
+        }
+    }
+}
+
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    /// All built-in commands, in the order `/help` lists them.
+    pub fn standard() -> Self {
+        let mut registry = Self::new();
+        registry.register(general::Help);
+        registry.register(rooms::Join);
+        registry.register(rooms::Part);
+        registry.register(rooms::List);
+        registry.register(rooms::Topic);
+        registry.register(moderation::Kick);
+        registry.register(moderation::BAN);
+        registry.register(moderation::UNBAN);
+        registry.register(moderation::MUTE);
+        registry.register(moderation::UNMUTE);
+        registry.register(moderation::Op);
+        registry.register(direct::Msg);
+        registry.register(direct::Query);
+        registry.register(direct::Me);
+        registry.register(direct::Notice);
+        registry.register(files::Upload);
+        registry.register(files::Download);
+        registry.register(messages::Reply);
+        registry.register(messages::Edit);
+        registry.register(messages::Delete);
+        registry.register(messages::React);
+        registry.register(messages::Unreact);
+        registry.register(ignore::Ignore);
+        registry.register(ignore::Unignore);
+        registry.register(ignore::Ignores);
+        registry.register(general::Whoami);
+        registry.register(general::Away);
+        registry.register(general::Back);
+        registry.register(rooms::Receive);
+        registry.register(rooms::Search);
+        registry.register(general::Links);
+        registry.register(nickserv::NickServ);
+        registry.register(general::Quit);
+        registry
+    }
+
+    pub fn register(&mut self, command: impl Command + 'static) {
+        self.commands.push(Box::new(command));
+    }
+
+    /// Look a command up by name or alias, with or without the leading slash.
+    pub fn find(&self, name: &str) -> Option<&dyn Command> {
+        let name = name.strip_prefix('/').unwrap_or(name);
+        self.commands
+            .iter()
+            .find(|command| command.name() == name || command.aliases().contains(&name))
+            .map(|command| command.as_ref())
+    }
+
+    /// Every name and alias with its slash, for completion.
+    pub fn names(&self) -> Vec<String> {
+        self.commands
+            .iter()
+            .flat_map(|command| {
+                std::iter::once(command.name()).chain(command.aliases().iter().copied())
+            })
+            .map(|name| format!("/{}", name))
+            .collect()
+    }
+
+    pub fn help_text(&self) -> String {
+        let synopsis = |command: &dyn Command| {
+            format!("/{} {}", command.name(), command.usage())
+                .trim_end()
+                .to_string()
+        };
+        let width = self
+            .commands
+            .iter()
+            .map(|command| synopsis(command.as_ref()).len())
+            .max()
+            .unwrap_or(0);
+
+        let mut text = String::from("Available Commands:");
+        for command in &self.commands {
+            text.push_str(&format!(
+                "\n  {:<width$}  {}",
+                synopsis(command.as_ref()),
+                command.help()
+            ));
+            for alias in command.aliases() {
+                text.push_str(&format!(
+                    "\n  {:<width$}  Same as /{}",
+                    format!("/{}", alias),
+                    command.name()
+                ));
+            }
+        }
+        text.push_str(
+            "\n\nMessages without a leading slash are sent to the current room or query.
+Press Tab to complete commands and nicknames, Alt+1..9 to switch rooms,
+F2 to toggle the user list, F3 to show raw markdown, F4 to pick a link
+and Ctrl+F to search. Up on an empty line edits your last message; Ctrl+R
+selects a message to reply to, react to or open as a thread.",
+        );
+        text
+    }
+
+    /// Run one line of input: plain text is sent, `/name args` is dispatched.
+    pub async fn dispatch(&self, ctx: &mut CommandContext, input: &str) {
+        let trimmed = input.trim();
+
+        crate::logging::log_debug!("Command received: '{}'", trimmed);
+
+        let Some(line) = trimmed.strip_prefix('/') else {
+            direct::send_text(ctx, trimmed).await;
+            return;
+        };
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let Some(command) = self.find(name) else {
+            ctx.system(format!(
+                "Unknown command `/{}`. Type /help for a list of commands.",
+                name
+            ));
+            return;
+        };
+
+        let args = Args::parse(rest);
+        if args.len() < command.min_args() {
+            ctx.system(format!("Usage: /{} {}", command.name(), command.usage()));
+            return;
+        }
+        command.execute(ctx, args).await;
+    }
+
+    /// Complete an interaction the UI prompted for.
+    pub async fn resolve(
+        &self,
+        ctx: &mut CommandContext,
+        interaction: PendingInteraction,
+        answer: String,
+    ) {
+        match interaction {
+            PendingInteraction::IdentifyPassword { nick } => {
+                nickserv::identify(ctx, &nick, &answer).await
+            }
+            PendingInteraction::RegisterPassword { nick } => {
+                nickserv::register(ctx, &nick, &answer).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_keep_trailing_text_intact() {
+        let args = Args::parse("  alice   hello   there ");
+        assert_eq!(args.len(), 3);
+        assert_eq!(args.get(0), Some("alice"));
+        assert_eq!(args.rest(1), "hello   there");
+        assert_eq!(args.rest(3), "");
+    }
+
+    #[test]
+    fn registry_finds_aliases_and_generates_help() {
+        let registry = Registry::standard();
+        assert_eq!(registry.find("/exit").map(|c| c.name()), Some("quit"));
+        assert!(registry.find("bogus").is_none());
+
+        let help = registry.help_text();
+        assert!(help.contains("/join <room>"));
+        assert!(help.contains("/exit"));
+        assert!(registry.names().contains(&"/nickserv".to_string()));
+    }
+}
@@ -0,0 +1,322 @@
+use super::{ArgKind, Args, Command, CommandContext, STATUS_HELP};
+use crate::ratatui_client::{BufferKind, now_millis};
+use futures_util::future::BoxFuture;
+use std::time::Duration;
+
+const ROLES: &[&str] = &["operator", "voice", "user", "owner"];
+
+pub struct Kick;
+
+impl Command for Kick {
+    fn name(&self) -> &'static str {
+        "kick"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick> [reason]"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Remove someone from this room (operators)"
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Nick
+        } else {
+            ArgKind::Free
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(room) = active_room(ctx) else {
+                return;
+            };
+            let nick = args.get(0).unwrap_or_default();
+            let reason = Some(args.rest(1)).filter(|reason| !reason.is_empty());
+            match ctx
+                .client
+                .kick(ctx.session.capability, &room, nick, reason)
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to kick {}: {}", nick, e), true),
+            }
+        })
+    }
+}
+
+/// `/ban` and `/mute`, which only differ in the RPC they call.
+pub struct Restrict {
+    name: &'static str,
+    help: &'static str,
+}
+
+pub const BAN: Restrict = Restrict {
+    name: "ban",
+    help: "Ban a nick or pattern like guest-* from this room, or list bans",
+};
+
+pub const MUTE: Restrict = Restrict {
+    name: "mute",
+    help: "Stop a nick or pattern from speaking here, or list mutes",
+};
+
+impl Command for Restrict {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        "[nick|pattern] [30s|10m|2h|7d] [reason]"
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Nick
+        } else {
+            ArgKind::Free
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(room) = active_room(ctx) else {
+                return;
+            };
+            let Some(mask) = args.get(0) else {
+                list_restrictions(ctx, &room, self.name).await;
+                return;
+            };
+            // An optional duration comes before the reason
+            let duration = args.get(1).and_then(parse_duration);
+            let reason = args.rest(if duration.is_some() { 2 } else { 1 });
+            let reason = Some(reason).filter(|reason| !reason.is_empty());
+            match ctx
+                .client
+                .restrict(
+                    ctx.session.capability,
+                    self.name,
+                    &room,
+                    mask,
+                    duration,
+                    reason,
+                )
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to {} {}: {}", self.name, mask, e), true),
+            }
+        })
+    }
+}
+
+/// `/unban` and `/unmute`.
+pub struct Unrestrict {
+    name: &'static str,
+    help: &'static str,
+}
+
+pub const UNBAN: Unrestrict = Unrestrict {
+    name: "unban",
+    help: "Lift a ban in this room",
+};
+
+pub const UNMUTE: Unrestrict = Unrestrict {
+    name: "unmute",
+    help: "Lift a mute in this room",
+};
+
+impl Command for Unrestrict {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick|pattern>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Nick
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(room) = active_room(ctx) else {
+                return;
+            };
+            let mask = args.get(0).unwrap_or_default();
+            match ctx
+                .client
+                .unrestrict(ctx.session.capability, self.name, &room, mask)
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to {} {}: {}", self.name, mask, e), true),
+            }
+        })
+    }
+}
+
+pub struct Op;
+
+impl Command for Op {
+    fn name(&self) -> &'static str {
+        "op"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<nick> [operator|voice|user|owner]"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Give a registered nick a role in this room (default operator)"
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Nick
+        } else {
+            ArgKind::Choice(ROLES)
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(room) = active_room(ctx) else {
+                return;
+            };
+            let nick = args.get(0).unwrap_or_default();
+            let role = args.get(1).unwrap_or("operator");
+            if !ROLES.contains(&role) {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            }
+            match ctx
+                .client
+                .set_role(ctx.session.capability, &room, nick, role)
+                .await
+            {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to change role of {}: {}", nick, e), true),
+            }
+        })
+    }
+}
+
+// Moderation applies to the room in the active tab.
+fn active_room(ctx: &mut CommandContext) -> Option<String> {
+    if ctx.ui.active_buffer_kind() == BufferKind::Room {
+        Some(ctx.ui.active_buffer_name())
+    } else {
+        ctx.system("Switch to the room you want to moderate first.");
+        None
+    }
+}
+
+async fn list_restrictions(ctx: &mut CommandContext, room: &str, kind: &str) {
+    let restrictions = match ctx
+        .client
+        .list_restrictions(ctx.session.capability, room)
+        .await
+    {
+        Ok(restrictions) => restrictions,
+        Err(e) => {
+            ctx.status(format!("Failed to list restrictions: {}", e), true);
+            return;
+        }
+    };
+    let now = now_millis();
+    let lines: Vec<String> = restrictions
+        .iter()
+        .filter(|restriction| restriction.kind == kind)
+        .map(|restriction| {
+            let mut line = format!("  {} by {}", restriction.mask, restriction.set_by);
+            if let Some(expires_at) = restriction.expires_at {
+                let left = Duration::from_millis(expires_at.saturating_sub(now));
+                line.push_str(&format!(", {} left", format_duration(left)));
+            }
+            if let Some(reason) = &restriction.reason {
+                line.push_str(&format!(": {}", reason));
+            }
+            line
+        })
+        .collect();
+    let plural = if kind == "ban" { "bans" } else { "mutes" };
+    if lines.is_empty() {
+        ctx.system(format!("No {} in {}.", plural, room));
+    } else {
+        ctx.system(format!(
+            "Active {} in {}:\n{}",
+            plural,
+            room,
+            lines.join("\n")
+        ));
+    }
+}
+
+/// `30s`, `10m`, `2h` or `7d`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let unit = text.chars().last()?;
+    let count = &text[..text.len() - unit.len_utf8()];
+    let count: u64 = count.parse().ok().filter(|&count| count > 0)?;
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    count.checked_mul(secs).map(Duration::from_secs)
+}
+
+// The largest whole unit, rounded up, like `3h`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs().max(1);
+    let (unit, size) = [("d", 86_400), ("h", 3600), ("m", 60), ("s", 1)]
+        .into_iter()
+        .find(|&(_, size)| secs >= size)
+        .unwrap_or(("s", 1));
+    format!("{}{}", secs.div_ceil(size), unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_need_a_unit() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(7 * 86_400)));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("0h"), None);
+        assert_eq!(parse_duration("spam"), None);
+        assert_eq!(parse_duration("5é"), None);
+
+        assert_eq!(format_duration(Duration::from_secs(90)), "2m");
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+        assert_eq!(format_duration(Duration::ZERO), "1s");
+    }
+}
@@ -0,0 +1,142 @@
+use super::{ArgKind, Args, Command, CommandContext, PendingInteraction, STATUS_HELP};
+use crate::{logging, token_store};
+use futures_util::future::BoxFuture;
+
+const SUBCOMMANDS: &[&str] = &["identify", "register"];
+
+pub struct NickServ;
+
+impl Command for NickServ {
+    fn name(&self) -> &'static str {
+        "nickserv"
+    }
+
+    fn usage(&self) -> &'static str {
+        "identify|register <nick>"
+    }
+
+    fn help(&self) -> &'static str {
+        "Identify with or register a protected nickname"
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Choice(SUBCOMMANDS)
+        } else {
+            ArgKind::Nick
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            logging::log_debug!("/nickserv command received with {} args", args.len());
+            match (args.get(0), args.get(1)) {
+                (Some("identify"), Some(nick)) => start_identify(ctx, nick).await,
+                (Some("identify"), None) => ctx.system(
+                    "Usage: /nickserv identify <nick>
+You will be prompted for the nickname password.",
+                ),
+                (Some("register"), Some(nick)) => {
+                    let interaction = PendingInteraction::RegisterPassword {
+                        nick: nick.to_string(),
+                    };
+                    ctx.ui.start_prompt(interaction);
+                    ctx.system(format!(
+                        "Please enter password for new nickname '{}' in the input area below",
+                        nick
+                    ));
+                }
+                (Some("register"), None) => ctx.system(
+                    "Usage: /nickserv register <nick>
+You will be prompted for a password to protect your nickname.",
+                ),
+                (Some(_), _) => {
+                    ctx.system("Unknown nickserv command. Use 'identify' or 'register'")
+                }
+                (None, _) => ctx.system(
+                    "NickServ Commands:
+/nickserv identify <nick>  Identify with a protected nickname
+/nickserv register <nick>  Register a new nickname",
+                ),
+            }
+        })
+    }
+}
+
+async fn start_identify(ctx: &mut CommandContext, nick: &str) {
+    match ctx
+        .client
+        .check_nickname(ctx.session.capability, nick)
+        .await
+    {
+        Ok(true) => {
+            logging::log_debug!("Starting password input for nickname '{}'", nick);
+            ctx.ui.start_prompt(PendingInteraction::IdentifyPassword {
+                nick: nick.to_string(),
+            });
+            ctx.system(format!(
+                "Please enter password for nickname '{}' in the input area below",
+                nick
+            ));
+        }
+        Ok(false) => {
+            let message = format!(
+                "Nickname '{}' is not registered. Use /nickserv register <nick>.",
+                nick
+            );
+            ctx.status(format!("{} | {}", message, STATUS_HELP), true);
+            ctx.system(message);
+        }
+        Err(err) => {
+            let message = format!("Failed to verify nickname '{}': {}", nick, err);
+            ctx.status(format!("{} | {}", message, STATUS_HELP), true);
+            ctx.system(message);
+        }
+    }
+}
+
+/// Answer to a `PendingInteraction::IdentifyPassword` prompt.
+pub async fn identify(ctx: &mut CommandContext, nick: &str, password: &str) {
+    logging::log_info!("Identifying nickname '{}'", nick);
+    let result = ctx
+        .client
+        .identify_nickname(ctx.session.capability, nick, password)
+        .await;
+    match result {
+        Ok(message) => adopt_nickname(ctx, nick, &message).await,
+        Err(e) => {
+            logging::log_warn!("Identify of '{}' failed: {}", nick, e);
+            ctx.system(format!("Identification failed: {}", e));
+        }
+    }
+}
+
+/// Answer to a `PendingInteraction::RegisterPassword` prompt.
+pub async fn register(ctx: &mut CommandContext, nick: &str, password: &str) {
+    let result = ctx
+        .client
+        .register_nickname(ctx.session.capability, nick, password)
+        .await;
+    match result {
+        Ok(message) => adopt_nickname(ctx, nick, &message).await,
+        Err(e) => {
+            logging::log_warn!("Registration of '{}' failed: {}", nick, e);
+            ctx.system(format!("Registration failed: {}", e));
+        }
+    }
+}
+
+async fn adopt_nickname(ctx: &mut CommandContext, nick: &str, message: &str) {
+    let old_nickname = std::mem::replace(&mut ctx.session.nickname, nick.to_string());
+    ctx.ui.set_nickname(nick);
+    logging::log_info!("Nickname changed from '{}' to '{}'", old_nickname, nick);
+    ctx.status(STATUS_HELP, false);
+    ctx.system(format!("{} - Your display name is now '{}'", message, nick));
+
+    if let Err(err) =
+        token_store::remember_login(&ctx.client, ctx.session.capability, &ctx.server_url, nick)
+            .await
+    {
+        logging::log_warn!("Could not save a login token for '{}': {}", nick, err);
+    }
+}
@@ -0,0 +1,325 @@
+use super::{ArgKind, Args, Command, CommandContext, STATUS_HELP};
+use crate::ratatui_client::BufferKind;
+use crate::search::SearchQuery;
+use crate::websocket_client::ChatMessage;
+use futures_util::future::BoxFuture;
+
+// Matches the server's cap on `searchMessages` results.
+const HISTORY_RESULT_LIMIT: usize = 50;
+
+pub struct Join;
+
+impl Command for Join {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<room>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Join (or create) a room and switch to it"
+    }
+
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Room
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let requested = args.get(0).unwrap_or_default();
+            match ctx
+                .client
+                .join_room(ctx.session.capability, requested)
+                .await
+            {
+                Ok(room) => {
+                    ctx.ui.open_buffer(&room);
+                    if let Ok(messages) = ctx
+                        .client
+                        .receive_room_messages(ctx.session.capability, &room)
+                        .await
+                    {
+                        for msg in messages {
+                            ctx.ui.add_message_to(&room, msg.into(), 100);
+                        }
+                    }
+                    if let Ok(topic) = ctx.client.get_topic(ctx.session.capability, &room).await {
+                        ctx.ui.set_topic(topic);
+                    }
+                    ctx.status(format!("Joined {} | {}", room, STATUS_HELP), false);
+                }
+                Err(e) => ctx.status(format!("Failed to join {}: {}", requested, e), true),
+            }
+        })
+    }
+}
+
+pub struct Part;
+
+impl Command for Part {
+    fn name(&self) -> &'static str {
+        "part"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[room]"
+    }
+
+    fn help(&self) -> &'static str {
+        "Leave a room (defaults to the current tab)"
+    }
+
+    fn complete(&self, _index: usize) -> ArgKind {
+        ArgKind::Room
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let room = args
+                .get(0)
+                .map(str::to_string)
+                .unwrap_or_else(|| ctx.ui.active_buffer_name());
+            if !room.starts_with('#') {
+                // Query buffers only exist locally
+                ctx.ui.close_buffer(&room);
+                return;
+            }
+            if ctx.ui.buffer_count() <= 1 {
+                ctx.system(format!("Cannot leave {}: it is your only open room", room));
+                return;
+            }
+            match ctx.client.part_room(ctx.session.capability, &room).await {
+                Ok(room) => {
+                    ctx.ui.close_buffer(&room);
+                    ctx.status(format!("Left {} | {}", room, STATUS_HELP), false);
+                }
+                Err(e) => ctx.status(e.to_string(), true),
+            }
+        })
+    }
+}
+
+pub struct Topic;
+
+impl Command for Topic {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[room] [text|-delete]"
+    }
+
+    fn help(&self) -> &'static str {
+        "Show or change a room's topic (identified nicks only)"
+    }
+
+    fn complete(&self, index: usize) -> ArgKind {
+        if index == 0 {
+            ArgKind::Room
+        } else {
+            ArgKind::Free
+        }
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let (room, text) = match args.get(0) {
+                Some(room) if room.starts_with('#') => (room.to_string(), args.rest(1)),
+                _ if ctx.ui.active_buffer_kind() == BufferKind::Room => {
+                    (ctx.ui.active_buffer_name(), args.rest(0))
+                }
+                _ => {
+                    ctx.system("Topics belong to rooms; name one or switch to its tab.");
+                    return;
+                }
+            };
+            let capability = ctx.session.capability;
+            if text.is_empty() {
+                match ctx.client.get_topic(capability, &room).await {
+                    Ok(topic) => {
+                        let body = match (&topic.topic, &topic.set_by) {
+                            (Some(text), Some(set_by)) => {
+                                format!("Topic for {}: {} (set by {})", room, text, set_by)
+                            }
+                            (Some(text), None) => format!("Topic for {}: {}", room, text),
+                            (None, _) => format!("{} has no topic", room),
+                        };
+                        ctx.ui.set_topic(topic);
+                        ctx.system(body);
+                    }
+                    Err(e) => ctx.status(format!("Failed to get topic: {}", e), true),
+                }
+                return;
+            }
+            // The new topic comes back as a push event and a system message
+            let topic = if text == "-delete" { "" } else { text };
+            match ctx.client.set_topic(capability, &room, topic).await {
+                Ok(()) => ctx.status(STATUS_HELP, false),
+                Err(e) => ctx.status(format!("Failed to set topic: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct List;
+
+impl Command for List {
+    fn name(&self) -> &'static str {
+        "list"
+    }
+
+    fn help(&self) -> &'static str {
+        "List rooms on the server"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            match ctx.client.list_rooms(ctx.session.capability).await {
+                Ok(rooms) => {
+                    let mut body = String::from("Rooms:");
+                    for room in rooms {
+                        let joined = if room.joined { " (joined)" } else { "" };
+                        body.push_str(&format!(
+                            "\n  {:<20} {} online{}",
+                            room.name, room.members, joined
+                        ));
+                    }
+                    ctx.system(body);
+                }
+                Err(e) => ctx.status(format!("Failed to list rooms: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Receive;
+
+impl Command for Receive {
+    fn name(&self) -> &'static str {
+        "receive"
+    }
+
+    fn help(&self) -> &'static str {
+        "Fetch and display messages for this room"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, _args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let room = ctx.ui.active_buffer_name();
+            match ctx
+                .client
+                .receive_room_messages(ctx.session.capability, &room)
+                .await
+            {
+                Ok(messages) => {
+                    for msg in messages {
+                        ctx.ui.add_message(msg.into());
+                    }
+                    ctx.status("Fetched recent messages", false);
+                }
+                Err(e) => ctx.status(format!("Failed to receive messages: {}", e), true),
+            }
+        })
+    }
+}
+
+pub struct Search;
+
+impl Command for Search {
+    fn name(&self) -> &'static str {
+        "search"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[-r] [-c] [-h] <text>"
+    }
+
+    fn min_args(&self) -> usize {
+        1
+    }
+
+    fn help(&self) -> &'static str {
+        "Search this buffer (-r regex, -c case-sensitive, -h server history)"
+    }
+
+    fn execute<'a>(&'a self, ctx: &'a mut CommandContext, args: Args) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let mut query = SearchQuery::default();
+            let mut history = false;
+            let mut skip = 0;
+            while let Some(flag) = args.get(skip) {
+                match flag {
+                    "-r" => query.regex = true,
+                    "-c" => query.case_sensitive = true,
+                    "-h" => history = true,
+                    _ => break,
+                }
+                skip += 1;
+            }
+            query.text = args.rest(skip).to_string();
+            if query.text.is_empty() {
+                ctx.system(format!("Usage: /{} {}", self.name(), self.usage()));
+                return;
+            }
+
+            if !history {
+                ctx.ui.start_search(query);
+                return;
+            }
+
+            let room = ctx.ui.active_buffer_name();
+            match search_history(ctx, &room, &query).await {
+                Ok(found) => {
+                    let mut body = format!(
+                        "History matches for {} in {}: {}",
+                        query.describe(),
+                        room,
+                        found.len()
+                    );
+                    for msg in found {
+                        body.push_str(&format!("\n  {}: {}", msg.from, msg.body));
+                    }
+                    ctx.system(body);
+                }
+                Err(e) => ctx.status(format!("History search failed: {}", e), true),
+            }
+        })
+    }
+}
+
+// The server only matches substrings, so regex queries run here over the
+// room's history, kept to the same number of results.
+async fn search_history(
+    ctx: &mut CommandContext,
+    room: &str,
+    query: &SearchQuery,
+) -> Result<Vec<ChatMessage>, String> {
+    let capability = ctx.session.capability;
+    if !query.regex {
+        return ctx
+            .client
+            .search_messages(capability, room, &query.text, query.case_sensitive)
+            .await
+            .map_err(|e| e.to_string());
+    }
+    let pattern = query.compile()?;
+    let history = ctx
+        .client
+        .receive_room_messages(capability, room)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut found: Vec<ChatMessage> = history
+        .into_iter()
+        .filter(|msg| pattern.is_match(&msg.body))
+        .collect();
+    let excess = found.len().saturating_sub(HISTORY_RESULT_LIMIT);
+    found.drain(..excess);
+    Ok(found)
+}
@@ -0,0 +1,161 @@
+use crate::commands::{ArgKind, Registry};
+
+/// An in-progress Tab completion. Repeated Tab presses cycle through
+/// `candidates`, replacing everything after `prefix`.
+pub struct Completion {
+    prefix: String,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    /// Work out what the last word of `input` could complete to. `nicks`
+    /// should be ordered by preference (recent speakers first).
+    pub fn start(
+        input: &str,
+        nicks: &[String],
+        rooms: &[String],
+        commands: &Registry,
+    ) -> Option<Self> {
+        let word_start = input.rfind(' ').map_or(0, |i| i + 1);
+        let (prefix, word) = input.split_at(word_start);
+        let earlier: Vec<&str> = prefix.split_whitespace().collect();
+
+        if earlier.is_empty() && word.starts_with('/') {
+            let names = commands.names();
+            let candidates = matching(names.iter().map(String::as_str), word)
+                .map(|command| format!("{} ", command))
+                .collect();
+            return Self::with(prefix, candidates);
+        }
+
+        // Ask the command what its argument at this position is
+        let kind = match earlier.first() {
+            Some(first) if first.starts_with('/') => commands
+                .find(first)
+                .map_or(ArgKind::Free, |command| command.complete(earlier.len() - 1)),
+            _ => ArgKind::Free,
+        };
+        let candidates: Vec<String> = match kind {
+            ArgKind::Choice(choices) => matching(choices.iter().copied(), word)
+                .map(|choice| format!("{} ", choice))
+                .collect(),
+            ArgKind::Room => matching(rooms.iter().map(String::as_str), word)
+                .map(|room| format!("{} ", room))
+                .collect(),
+            ArgKind::Nick => matching(nicks.iter().map(String::as_str), word)
+                .map(|nick| format!("{} ", nick))
+                .collect(),
+            ArgKind::Free if word.starts_with('#') => {
+                matching(rooms.iter().map(String::as_str), word)
+                    .map(|room| format!("{} ", room))
+                    .collect()
+            }
+            ArgKind::Free if word.is_empty() => Vec::new(),
+            ArgKind::Free => {
+                // A nick at the start of the line is addressing someone
+                let suffix = if earlier.is_empty() { ": " } else { " " };
+                matching(nicks.iter().map(String::as_str), word)
+                    .map(|nick| format!("{}{}", nick, suffix))
+                    .collect()
+            }
+        };
+        Self::with(prefix, candidates)
+    }
+
+    fn with(prefix: &str, candidates: Vec<String>) -> Option<Self> {
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Self {
+            prefix: prefix.to_string(),
+            candidates,
+            index: 0,
+        })
+    }
+
+    /// The input line with the current candidate filled in.
+    pub fn current(&self) -> String {
+        format!("{}{}", self.prefix, self.candidates[self.index])
+    }
+
+    pub fn next(&mut self) -> String {
+        self.index = (self.index + 1) % self.candidates.len();
+        self.current()
+    }
+
+    pub fn previous(&mut self) -> String {
+        self.index = self
+            .index
+            .checked_sub(1)
+            .unwrap_or(self.candidates.len() - 1);
+        self.current()
+    }
+}
+
+// Case-insensitive prefix match that skips duplicate options.
+fn matching<'a>(
+    options: impl Iterator<Item = &'a str>,
+    word: &'a str,
+) -> impl Iterator<Item = &'a str> {
+    let lower = word.to_lowercase();
+    let mut seen = Vec::new();
+    options.filter(move |option| {
+        let key = option.to_lowercase();
+        if !key.starts_with(&lower) || seen.contains(&key) {
+            return false;
+        }
+        seen.push(key);
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Registry {
+        Registry::standard()
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn completes_commands_and_cycles() {
+        let mut completion = Completion::start("/q", &[], &[], &commands()).unwrap();
+        assert_eq!(completion.current(), "/query ");
+        assert_eq!(completion.next(), "/quit ");
+        assert_eq!(completion.next(), "/query ");
+        assert_eq!(completion.previous(), "/quit ");
+    }
+
+    #[test]
+    fn completes_subcommands() {
+        let completion = Completion::start("/nickserv id", &[], &[], &commands()).unwrap();
+        assert_eq!(completion.current(), "/nickserv identify ");
+    }
+
+    #[test]
+    fn nick_at_line_start_gets_address_suffix() {
+        let nicks = names(&["Alice", "alfred", "bob"]);
+        let mut completion = Completion::start("al", &nicks, &[], &commands()).unwrap();
+        assert_eq!(completion.current(), "Alice: ");
+        assert_eq!(completion.next(), "alfred: ");
+
+        let completion = Completion::start("/msg b", &nicks, &[], &commands()).unwrap();
+        assert_eq!(completion.current(), "/msg bob ");
+    }
+
+    #[test]
+    fn rooms_and_duplicates() {
+        let rooms = names(&["#general", "#games", "#general"]);
+        let mut completion = Completion::start("/join #g", &[], &rooms, &commands()).unwrap();
+        assert_eq!(completion.current(), "/join #general ");
+        assert_eq!(completion.next(), "/join #games ");
+        assert_eq!(completion.next(), "/join #general ");
+
+        assert!(Completion::start("hello ", &names(&["bob"]), &rooms, &commands()).is_none());
+    }
+}
@@ -0,0 +1,306 @@
+//! The TOML configuration file. Settings resolve in this order, later ones
+//! winning: built-in defaults, the top level of the file, the selected
+//! profile, `CAPINRS_SERVER_HOST`, then command-line flags.
+//!
+//! ```toml
+//! default_profile = "prod"
+//! nick = "bob"
+//! theme = "dark"
+//! markdown = true
+//! hyperlinks = "auto"
+//! mouse = true
+//!
+//! [layout]
+//! status_bar = "top"
+//! borders = "rounded"
+//! compact = false
+//!
+//! [log]
+//! level = "debug"
+//! file = "/tmp/capinrs.log"
+//!
+//! [notifications]
+//! bell = false
+//! keywords = ["deploy"]
+//! terminal = "osc9"
+//!
+//! [ignore]
+//! nicks = ["spambot"]
+//!
+//! [away]
+//! auto_after_mins = 20
+//! reason = "Idle"
+//!
+//! [keybindings]
+//! vi_mode = true
+//! search = "ctrl+s"
+//!
+//! [profiles.prod]
+//! url = "wss://capinrs-server.veronika-m-winters.workers.dev"
+//!
+//! [profiles.local]
+//! url = "ws://localhost:8787"
+//! nick = "bob-dev"
+//! ```
+
+use crate::ignore::{IgnoreConfig, IgnoreList};
+use crate::keymap::{KeybindingsConfig, Keymap};
+use crate::links::HyperlinkMode;
+use crate::logging::Level;
+use crate::notify::NotificationConfig;
+use crate::theme::{self, LayoutOptions, Theme, ThemeConfig};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SERVER_URL: &str = "wss://capinrs-server.veronika-m-winters.workers.dev";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    default_profile: Option<String>,
+    nick: Option<String>,
+    theme: Option<String>,
+    /// Render markdown in messages (the default) or show raw text
+    markdown: Option<bool>,
+    /// Make URLs clickable with OSC 8: auto, always or never
+    #[serde(default)]
+    hyperlinks: HyperlinkMode,
+    /// Capture the mouse for scrolling; off leaves text selection to the terminal
+    mouse: Option<bool>,
+    /// User themes, see `crate::theme`
+    #[serde(default)]
+    themes: BTreeMap<String, ThemeConfig>,
+    #[serde(default)]
+    layout: LayoutOptions,
+    #[serde(default)]
+    log: LogConfig,
+    /// Mentions and notifications, see `crate::notify`
+    #[serde(default)]
+    notifications: NotificationConfig,
+    /// Action name to keys, see `crate::keymap`
+    #[serde(default)]
+    keybindings: KeybindingsConfig,
+    /// Nicks and filters to hide, see `crate::ignore`
+    #[serde(default)]
+    ignore: IgnoreConfig,
+    #[serde(default)]
+    away: AwayConfig,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    url: Option<String>,
+    nick: Option<String>,
+    theme: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogConfig {
+    level: Option<String>,
+    file: Option<PathBuf>,
+    remote: Option<bool>,
+}
+
+/// The `[away]` table.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AwayConfig {
+    /// Go away after this long without a key press; 0 turns it off
+    pub auto_after_mins: u64,
+    /// Reason shown while automatically away
+    pub reason: String,
+}
+
+impl Default for AwayConfig {
+    fn default() -> Self {
+        Self {
+            auto_after_mins: 30,
+            reason: "Idle".to_string(),
+        }
+    }
+}
+
+/// Everything the client starts with once the file, environment and flags
+/// have been combined.
+#[derive(Debug)]
+pub struct Settings {
+    pub profile: Option<String>,
+    pub url: String,
+    pub nick: Option<String>,
+    pub password: Option<String>,
+    pub theme: Theme,
+    pub layout: LayoutOptions,
+    pub markdown: bool,
+    pub hyperlinks: bool,
+    pub mouse: bool,
+    pub log_level: Level,
+    pub log_file: Option<PathBuf>,
+    pub log_remote: bool,
+    pub notifications: NotificationConfig,
+    pub keymap: Keymap,
+    pub ignores: IgnoreList,
+    pub away: AwayConfig,
+    /// Where `/ignore` saves the list; None without a config directory
+    pub config_path: Option<PathBuf>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/capinrs/config.toml`, falling back to
+    /// `~/.config/capinrs/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("capinrs").join("config.toml"))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|err| err.to_string())
+    }
+
+    /// Load `path`, which must exist, or the default path, which may not.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                Self::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+            }
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(err) => Err(format!("cannot read {}: {}", path.display(), err)),
+        }
+    }
+
+    /// Combine the file with the environment for `profile`, or the file's
+    /// `default_profile` when none is named.
+    pub fn resolve(mut self, profile: Option<&str>) -> Result<Settings, String> {
+        let name = profile.map(str::to_string).or(self.default_profile.take());
+        let selected = match &name {
+            Some(name) => Some(self.profiles.remove(name).ok_or_else(|| {
+                let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                if known.is_empty() {
+                    format!("unknown profile `{}` (the config defines none)", name)
+                } else {
+                    format!(
+                        "unknown profile `{}` (expected one of: {})",
+                        name,
+                        known.join(", ")
+                    )
+                }
+            })?),
+            None => None,
+        }
+        .unwrap_or_default();
+
+        let url = std::env::var("CAPINRS_SERVER_HOST")
+            .ok()
+            .or(selected.url)
+            .unwrap_or_else(|| DEFAULT_SERVER_URL.to_string());
+        let theme_name = selected.theme.or(self.theme);
+        let mut theme = Theme::resolve(theme_name.as_deref().unwrap_or("dark"), &self.themes)?;
+        if theme::no_color_requested() {
+            theme = theme.without_color();
+        }
+        let keymap = Keymap::from_config(&self.keybindings)
+            .map_err(|err| format!("keybindings: {}", err))?;
+        let ignores =
+            IgnoreList::from_config(&self.ignore).map_err(|err| format!("ignore: {}", err))?;
+        let log_level = match &self.log.level {
+            Some(level) => level.parse()?,
+            None => Level::Info,
+        };
+
+        Ok(Settings {
+            profile: name,
+            url,
+            nick: selected.nick.or(self.nick),
+            password: None,
+            theme,
+            layout: self.layout,
+            markdown: self.markdown.unwrap_or(true),
+            hyperlinks: self.hyperlinks.enabled(),
+            mouse: self.mouse.unwrap_or(true),
+            log_level,
+            log_file: self.log.file,
+            log_remote: self.log.remote.unwrap_or(false),
+            notifications: self.notifications,
+            keymap,
+            ignores,
+            away: self.away,
+            config_path: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::StatusPosition;
+
+    const SAMPLE: &str = r#"
+default_profile = "prod"
+nick = "bob"
+
+[log]
+level = "debug"
+
+[notifications]
+bell = false
+keywords = ["deploy"]
+
+[layout]
+status_bar = "top"
+compact = true
+
+[profiles.prod]
+url = "wss://prod.example"
+
+[profiles.local]
+url = "ws://localhost:8787"
+nick = "bob-dev"
+"#;
+
+    #[test]
+    fn profiles_override_the_top_level() {
+        let settings = Config::parse(SAMPLE)
+            .unwrap()
+            .resolve(Some("local"))
+            .unwrap();
+        assert_eq!(settings.profile.as_deref(), Some("local"));
+        assert_eq!(settings.nick.as_deref(), Some("bob-dev"));
+        assert_eq!(settings.log_level, Level::Debug);
+        assert!(!settings.notifications.bell);
+        assert!(settings.notifications.mentions);
+        assert_eq!(settings.notifications.keywords, vec!["deploy"]);
+        assert_eq!(settings.layout.status_bar, StatusPosition::Top);
+        assert_eq!(settings.layout.bar_height(), 1);
+
+        let settings = Config::parse(SAMPLE).unwrap().resolve(None).unwrap();
+        assert_eq!(settings.profile.as_deref(), Some("prod"));
+        assert_eq!(settings.nick.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn unknown_profiles_and_keys_are_errors() {
+        let err = Config::parse(SAMPLE)
+            .unwrap()
+            .resolve(Some("staging"))
+            .unwrap_err();
+        assert!(err.contains("local, prod"), "{}", err);
+        assert!(Config::parse("nik = \"typo\"").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! The local ignore list: messages from ignored nicks, or whose sender or
+//! body matches a filter, are dropped before they reach a buffer. Nothing
+//! is sent to the server; `/ignore` and `/unignore` rewrite the config.
+//!
+//! ```toml
+//! [ignore]
+//! nicks = ["spambot"]
+//! senders = ["^bot-"]
+//! bodies = ["(?i)standup reminder"]
+//! ```
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Room for the odd filter without letting one pattern eat memory
+const PATTERN_SIZE_LIMIT: usize = 1 << 16;
+
+/// The `[ignore]` table.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IgnoreConfig {
+    /// Nicks to ignore, compared without case
+    pub nicks: Vec<String>,
+    /// Regexes matched against the sender
+    pub senders: Vec<String>,
+    /// Regexes matched against the message body
+    pub bodies: Vec<String>,
+}
+
+/// One entry of the list, written `nick`, `from:<regex>` or `body:<regex>`
+/// on the command line.
+#[derive(Clone, Debug)]
+pub enum IgnoreRule {
+    Nick(String),
+    Sender(Regex),
+    Body(Regex),
+}
+
+impl IgnoreRule {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if let Some(pattern) = spec.strip_prefix("from:") {
+            compile(pattern).map(Self::Sender)
+        } else if let Some(pattern) = spec.strip_prefix("body:") {
+            compile(pattern).map(Self::Body)
+        } else if spec.is_empty() || spec.contains(char::is_whitespace) {
+            Err(format!("`{}` is not a nick", spec))
+        } else {
+            Ok(Self::Nick(spec.to_string()))
+        }
+    }
+
+    fn matches(&self, from: &str, body: &str) -> bool {
+        match self {
+            Self::Nick(nick) => nick.eq_ignore_ascii_case(from),
+            Self::Sender(pattern) => pattern.is_match(from),
+            Self::Body(pattern) => pattern.is_match(body),
+        }
+    }
+
+    fn same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nick(a), Self::Nick(b)) => a.eq_ignore_ascii_case(b),
+            (Self::Sender(a), Self::Sender(b)) | (Self::Body(a), Self::Body(b)) => {
+                a.as_str() == b.as_str()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IgnoreRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nick(nick) => f.write_str(nick),
+            Self::Sender(pattern) => write!(f, "from:{}", pattern.as_str()),
+            Self::Body(pattern) => write!(f, "body:{}", pattern.as_str()),
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    if pattern.is_empty() {
+        return Err("empty filter pattern".to_string());
+    }
+    RegexBuilder::new(pattern)
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+        .map_err(|err| format!("bad filter `{}`: {}", pattern, err))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreList {
+    pub fn from_config(config: &IgnoreConfig) -> Result<Self, String> {
+        let nicks = config.nicks.iter().map(|nick| IgnoreRule::parse(nick));
+        let senders = config
+            .senders
+            .iter()
+            .map(|p| compile(p).map(IgnoreRule::Sender));
+        let bodies = config
+            .bodies
+            .iter()
+            .map(|p| compile(p).map(IgnoreRule::Body));
+        let rules = nicks
+            .chain(senders)
+            .chain(bodies)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn to_config(&self) -> IgnoreConfig {
+        let mut config = IgnoreConfig::default();
+        for rule in &self.rules {
+            match rule {
+                IgnoreRule::Nick(nick) => config.nicks.push(nick.clone()),
+                IgnoreRule::Sender(pattern) => config.senders.push(pattern.as_str().to_string()),
+                IgnoreRule::Body(pattern) => config.bodies.push(pattern.as_str().to_string()),
+            }
+        }
+        config
+    }
+
+    pub fn rules(&self) -> &[IgnoreRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// False when an equal rule is already on the list.
+    pub fn add(&mut self, rule: IgnoreRule) -> bool {
+        if self.rules.iter().any(|existing| existing.same_as(&rule)) {
+            return false;
+        }
+        self.rules.push(rule);
+        true
+    }
+
+    /// False when no such rule was on the list.
+    pub fn remove(&mut self, rule: &IgnoreRule) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|existing| !existing.same_as(rule));
+        self.rules.len() != before
+    }
+
+    pub fn hides(&self, from: &str, body: &str) -> bool {
+        self.rules.iter().any(|rule| rule.matches(from, body))
+    }
+
+    /// Rewrite the `[ignore]` table of the config at `path`, keeping the
+    /// rest of the file, comments included, as it was.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("cannot read {}: {}", path.display(), err)),
+        };
+        let mut doc: toml_edit::DocumentMut = contents
+            .parse()
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let config = self.to_config();
+        let table = doc
+            .entry("ignore")
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| format!("{}: `ignore` is not a table", path.display()))?;
+        for (key, values) in [
+            ("nicks", &config.nicks),
+            ("senders", &config.senders),
+            ("bodies", &config.bodies),
+        ] {
+            if values.is_empty() {
+                table.remove(key);
+            } else {
+                let array: toml_edit::Array = values.iter().map(String::as_str).collect();
+                table[key] = toml_edit::value(array);
+            }
+        }
+        if table.is_empty() {
+            doc.remove("ignore");
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|err| format!("cannot create {}: {}", dir.display(), err))?;
+        }
+        fs::write(path, doc.to_string())
+            .map_err(|err| format!("cannot write {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_match_nicks_senders_and_bodies() {
+        let mut list = IgnoreList::default();
+        assert!(list.add(IgnoreRule::parse("SpamBot").unwrap()));
+        assert!(list.add(IgnoreRule::parse("from:^bot-").unwrap()));
+        assert!(list.add(IgnoreRule::parse("body:(?i)standup").unwrap()));
+        assert!(!list.add(IgnoreRule::parse("spambot").unwrap()));
+        assert!(IgnoreRule::parse("body:(").is_err());
+        assert!(IgnoreRule::parse("two words").is_err());
+
+        assert!(list.hides("spambot", "hello"));
+        assert!(list.hides("bot-ci", "build passed"));
+        assert!(list.hides("alice", "Standup in 5"));
+        assert!(!list.hides("alice", "lunch?"));
+
+        assert!(list.remove(&IgnoreRule::parse("from:^bot-").unwrap()));
+        assert!(!list.hides("bot-ci", "build passed"));
+        assert_eq!(
+            list.rules()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["SpamBot", "body:(?i)standup"]
+        );
+    }
+
+    #[test]
+    fn saving_keeps_the_rest_of_the_config() {
+        let path = std::env::temp_dir()
+            .join(format!("capinrs-test-{}-ignore", std::process::id()))
+            .join("config.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "# my settings\nnick = \"bob\"\n").unwrap();
+
+        let mut list = IgnoreList::default();
+        list.add(IgnoreRule::parse("spambot").unwrap());
+        list.add(IgnoreRule::parse("body:^!").unwrap());
+        list.save(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("# my settings\nnick = \"bob\"\n"));
+        let config = crate::config::Config::parse(&contents).unwrap();
+        let settings = config.resolve(None).unwrap();
+        assert_eq!(settings.ignores.to_config(), list.to_config());
+
+        IgnoreList::default().save(&path).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("ignore"));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,452 @@
+//! Key chords mapped to named actions. The defaults reproduce the classic
+//! bindings; `[keybindings]` in the config file replaces the keys of any
+//! action it names, and `[keybindings.normal]` does the same for vi normal
+//! mode:
+//!
+//! ```toml
+//! [keybindings]
+//! vi_mode = true
+//! history-previous = "ctrl+p"
+//! scroll-up = ["up", "ctrl+y"]
+//! toggle-nick-list = "none"
+//!
+//! [keybindings.normal]
+//! page-down = "space"
+//! ```
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Submit,
+    Complete,
+    CompletePrevious,
+    DeleteBack,
+    HistoryPrevious,
+    HistoryNext,
+    ScrollUp,
+    ScrollDown,
+    /// Recall older history, scrolling once there is none left. On an
+    /// empty line this starts editing your last message instead.
+    RecallOrScrollUp,
+    RecallOrScrollDown,
+    PageUp,
+    PageDown,
+    ScrollTop,
+    ScrollBottom,
+    ToggleNickList,
+    /// Switch between rendered markdown and raw message text
+    ToggleRaw,
+    /// Pick one of the recent links to open
+    Links,
+    Search,
+    /// Pick a message to reply to or open as a thread
+    SelectMessage,
+    /// Switch to the buffer at this zero-based index
+    Buffer(usize),
+    NextBuffer,
+    PreviousBuffer,
+    /// Leave insert mode (vi mode only)
+    NormalMode,
+    InsertMode,
+    /// Enter insert mode with a `/` already typed
+    CommandLine,
+}
+
+const NAMED_ACTIONS: &[(&str, Action)] = &[
+    ("quit", Action::Quit),
+    ("submit", Action::Submit),
+    ("complete", Action::Complete),
+    ("complete-previous", Action::CompletePrevious),
+    ("delete-back", Action::DeleteBack),
+    ("history-previous", Action::HistoryPrevious),
+    ("history-next", Action::HistoryNext),
+    ("scroll-up", Action::ScrollUp),
+    ("scroll-down", Action::ScrollDown),
+    ("recall-or-scroll-up", Action::RecallOrScrollUp),
+    ("recall-or-scroll-down", Action::RecallOrScrollDown),
+    ("page-up", Action::PageUp),
+    ("page-down", Action::PageDown),
+    ("scroll-top", Action::ScrollTop),
+    ("scroll-bottom", Action::ScrollBottom),
+    ("toggle-nick-list", Action::ToggleNickList),
+    ("toggle-raw", Action::ToggleRaw),
+    ("links", Action::Links),
+    ("search", Action::Search),
+    ("select-message", Action::SelectMessage),
+    ("next-buffer", Action::NextBuffer),
+    ("previous-buffer", Action::PreviousBuffer),
+    ("normal-mode", Action::NormalMode),
+    ("insert-mode", Action::InsertMode),
+    ("command-line", Action::CommandLine),
+];
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(index) = s.strip_prefix("buffer-")
+            && let Ok(number @ 1..=9) = index.parse::<usize>()
+        {
+            return Ok(Action::Buffer(number - 1));
+        }
+        NAMED_ACTIONS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, action)| *action)
+            .ok_or_else(|| format!("unknown action `{}`", s))
+    }
+}
+
+/// One key plus modifiers, e.g. `ctrl+f`. Shift is folded into the key for
+/// characters, so `N` and `shift+n` are the same chord.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Chord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Chord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let code = match code {
+            // Terminals disagree on whether Ctrl+letter arrives upper or lower case
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            other => other,
+        };
+        Self {
+            code,
+            modifiers: modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT),
+        }
+    }
+
+    pub fn of(key: &KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid key `{}`", s);
+        let mut modifiers = KeyModifiers::NONE;
+        let mut parts: Vec<&str> = s.split('+').collect();
+        // A trailing empty part means the key itself is `+`
+        let key = match parts.pop() {
+            Some("") if s.ends_with("++") || s == "+" => {
+                parts.pop();
+                "+"
+            }
+            Some(key) if !key.is_empty() => key,
+            _ => return Err(invalid()),
+        };
+        let mut shift = false;
+        for part in parts {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= KeyModifiers::CONTROL,
+                "alt" | "meta" => modifiers |= KeyModifiers::ALT,
+                "shift" => shift = true,
+                _ => return Err(invalid()),
+            }
+        }
+
+        let code = match key.to_ascii_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" if shift => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            lower => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if shift => KeyCode::Char(c.to_ascii_uppercase()),
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => match lower.strip_prefix('f').map(str::parse::<u8>) {
+                        Some(Ok(n @ 1..=24)) => KeyCode::F(n),
+                        _ => return Err(invalid()),
+                    },
+                }
+            }
+        };
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("Alt+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::BackTab => f.write_str("Shift+Tab"),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Which keymap applies. Without vi mode the client stays in `Insert`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    Insert,
+    Normal,
+}
+
+/// A binding value: one key, a list of keys, or `"none"` to unbind.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum KeySpec {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeySpec {
+    fn keys(&self) -> Vec<&str> {
+        match self {
+            KeySpec::One(key) if key == "none" => Vec::new(),
+            KeySpec::One(key) => vec![key.as_str()],
+            KeySpec::Many(keys) => keys.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// The `[keybindings]` table.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct KeybindingsConfig {
+    #[serde(default)]
+    vi_mode: bool,
+    #[serde(default)]
+    normal: BTreeMap<String, KeySpec>,
+    #[serde(flatten)]
+    insert: BTreeMap<String, KeySpec>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    insert: HashMap<Chord, Action>,
+    normal: HashMap<Chord, Action>,
+    vi_mode: bool,
+}
+
+fn bind(map: &mut HashMap<Chord, Action>, key: &str, action: Action) {
+    let chord = key.parse().expect("default key bindings parse");
+    map.insert(chord, action);
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut insert = HashMap::new();
+        for (key, action) in [
+            ("ctrl+c", Action::Quit),
+            ("enter", Action::Submit),
+            ("tab", Action::Complete),
+            ("shift+tab", Action::CompletePrevious),
+            ("backspace", Action::DeleteBack),
+            ("up", Action::RecallOrScrollUp),
+            ("down", Action::RecallOrScrollDown),
+            ("ctrl+p", Action::HistoryPrevious),
+            ("ctrl+n", Action::HistoryNext),
+            ("pageup", Action::PageUp),
+            ("pagedown", Action::PageDown),
+            ("home", Action::ScrollTop),
+            ("end", Action::ScrollBottom),
+            ("f2", Action::ToggleNickList),
+            ("f3", Action::ToggleRaw),
+            ("f4", Action::Links),
+            ("ctrl+f", Action::Search),
+            ("ctrl+r", Action::SelectMessage),
+            ("alt+right", Action::NextBuffer),
+            ("alt+left", Action::PreviousBuffer),
+        ] {
+            bind(&mut insert, key, action);
+        }
+        for index in 0..9 {
+            bind(
+                &mut insert,
+                &format!("alt+{}", index + 1),
+                Action::Buffer(index),
+            );
+        }
+
+        let mut normal = HashMap::new();
+        for (key, action) in [
+            ("ctrl+c", Action::Quit),
+            ("i", Action::InsertMode),
+            ("a", Action::InsertMode),
+            (":", Action::CommandLine),
+            ("/", Action::Search),
+            ("v", Action::SelectMessage),
+            ("j", Action::ScrollDown),
+            ("k", Action::ScrollUp),
+            ("down", Action::ScrollDown),
+            ("up", Action::ScrollUp),
+            ("ctrl+d", Action::PageDown),
+            ("ctrl+u", Action::PageUp),
+            ("g", Action::ScrollTop),
+            ("G", Action::ScrollBottom),
+            ("l", Action::NextBuffer),
+            ("h", Action::PreviousBuffer),
+            ("ctrl+p", Action::HistoryPrevious),
+            ("ctrl+n", Action::HistoryNext),
+            ("f2", Action::ToggleNickList),
+            ("f3", Action::ToggleRaw),
+            ("f4", Action::Links),
+        ] {
+            bind(&mut normal, key, action);
+        }
+        for index in 0..9 {
+            bind(&mut normal, &(index + 1).to_string(), Action::Buffer(index));
+        }
+
+        Self {
+            insert,
+            normal,
+            vi_mode: false,
+        }
+    }
+}
+
+// Rebind every action named in `overrides`, dropping its default keys.
+fn apply(
+    map: &mut HashMap<Chord, Action>,
+    overrides: &BTreeMap<String, KeySpec>,
+) -> Result<(), String> {
+    for (name, spec) in overrides {
+        let action: Action = name.parse()?;
+        map.retain(|_, bound| *bound != action);
+        for key in spec.keys() {
+            let chord = key
+                .parse()
+                .map_err(|err| format!("{} (for `{}`)", err, name))?;
+            map.insert(chord, action);
+        }
+    }
+    Ok(())
+}
+
+impl Keymap {
+    pub fn from_config(config: &KeybindingsConfig) -> Result<Self, String> {
+        let mut keymap = Self {
+            vi_mode: config.vi_mode,
+            ..Self::default()
+        };
+        if keymap.vi_mode {
+            bind(&mut keymap.insert, "esc", Action::NormalMode);
+        }
+        apply(&mut keymap.insert, &config.insert)?;
+        apply(&mut keymap.normal, &config.normal)?;
+        Ok(keymap)
+    }
+
+    pub fn vi_mode(&self) -> bool {
+        self.vi_mode
+    }
+
+    pub fn lookup(&self, mode: InputMode, key: &KeyEvent) -> Option<Action> {
+        let map = match mode {
+            InputMode::Insert => &self.insert,
+            InputMode::Normal => &self.normal,
+        };
+        map.get(&Chord::of(key)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn chords_parse_and_normalize() {
+        let chord: Chord = "ctrl+f".parse().unwrap();
+        assert_eq!(chord, Chord::new(KeyCode::Char('F'), KeyModifiers::CONTROL));
+        assert_eq!(
+            "shift+n".parse::<Chord>().unwrap(),
+            Chord::new(KeyCode::Char('N'), KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            "shift+tab".parse::<Chord>().unwrap(),
+            Chord::new(KeyCode::BackTab, KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            "alt++".parse::<Chord>().unwrap(),
+            Chord::new(KeyCode::Char('+'), KeyModifiers::ALT)
+        );
+        assert_eq!("F2".parse::<Chord>().unwrap().to_string(), "F2");
+        assert!("hyper+x".parse::<Chord>().is_err());
+        assert!("f99".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn overrides_replace_the_default_keys() {
+        let config: KeybindingsConfig = toml::from_str(
+            r#"
+vi_mode = true
+history-previous = "ctrl+p"
+search = ["ctrl+s", "f3"]
+toggle-nick-list = "none"
+
+[normal]
+page-down = "space"
+"#,
+        )
+        .unwrap();
+        let keymap = Keymap::from_config(&config).unwrap();
+        let insert = |code, modifiers| keymap.lookup(InputMode::Insert, &key(code, modifiers));
+
+        assert_eq!(
+            insert(KeyCode::Char('p'), KeyModifiers::CONTROL),
+            Some(Action::HistoryPrevious)
+        );
+        assert_eq!(insert(KeyCode::Char('f'), KeyModifiers::CONTROL), None);
+        assert_eq!(
+            insert(KeyCode::F(3), KeyModifiers::NONE),
+            Some(Action::Search)
+        );
+        assert_eq!(insert(KeyCode::F(2), KeyModifiers::NONE), None);
+        assert_eq!(
+            insert(KeyCode::Esc, KeyModifiers::NONE),
+            Some(Action::NormalMode)
+        );
+        assert_eq!(
+            keymap.lookup(
+                InputMode::Normal,
+                &key(KeyCode::Char(' '), KeyModifiers::NONE)
+            ),
+            Some(Action::PageDown)
+        );
+
+        let bad: KeybindingsConfig = toml::from_str(r#"fly = "f""#).unwrap();
+        assert!(Keymap::from_config(&bad).unwrap_err().contains("fly"));
+    }
+}
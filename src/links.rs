@@ -0,0 +1,255 @@
+//! Links in message bodies: detection, styling, OSC 8 hyperlinks for
+//! terminals that support them, the `/links` picker and the system opener.
+
+use crate::markdown;
+use crate::ratatui_client::ChatMessage;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+use ratatui::text::Span;
+use regex::Regex;
+use serde::Deserialize;
+use std::ops::Range;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// How many links the picker lists.
+pub const PICKER_LIMIT: usize = 20;
+
+/// Byte ranges of the URLs in `text`, without trailing punctuation.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    let pattern = URL.get_or_init(|| {
+        Regex::new(r#"(?i)\b(?:https?|ftp)://[^\s<>"'`]+"#).expect("URL pattern is valid")
+    });
+    pattern
+        .find_iter(text)
+        .map(|found| {
+            let url = found.as_str();
+            let mut end = url.len();
+            // A closing paren ends the URL unless the URL opened one itself
+            while let Some(last) = url[..end].chars().last() {
+                let unbalanced_paren = last == ')'
+                    && url[..end].matches('(').count() < url[..end].matches(')').count();
+                if ".,;:!?]}*_".contains(last) || unbalanced_paren {
+                    end -= last.len_utf8();
+                } else {
+                    break;
+                }
+            }
+            found.start()..found.start() + end
+        })
+        .collect()
+}
+
+/// Split `spans` so that URLs carry `link` on top of their own style.
+pub fn style_urls(spans: Vec<Span<'static>>, link: Style) -> Vec<Span<'static>> {
+    markdown::style_ranges(spans, find_urls, link)
+}
+
+/// Distinct URLs in `messages`, newest first.
+pub fn recent(messages: &[ChatMessage], limit: usize) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    for message in messages.iter().rev() {
+        let mut found: Vec<&str> = find_urls(&message.body)
+            .into_iter()
+            .map(|range| &message.body[range])
+            .collect();
+        // Later links in the same message are newer
+        found.reverse();
+        for url in found {
+            if links.len() == limit {
+                return links;
+            }
+            if !links.iter().any(|link| link == url) {
+                links.push(url.to_string());
+            }
+        }
+    }
+    links
+}
+
+/// Hand `url` to the platform's opener without waiting for it.
+pub fn open(url: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    let mut child = command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap the opener so it does not linger as a zombie
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The `hyperlinks` config setting.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HyperlinkMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl HyperlinkMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            HyperlinkMode::Auto => terminal_supports_hyperlinks(),
+            HyperlinkMode::Always => true,
+            HyperlinkMode::Never => false,
+        }
+    }
+}
+
+// There is no query for OSC 8 support, so go by the terminals known to have it.
+fn terminal_supports_hyperlinks() -> bool {
+    let env = |name: &str| std::env::var(name).unwrap_or_default();
+    if env("TERM") == "linux" || !env("TMUX").is_empty() {
+        return false;
+    }
+    let program = env("TERM_PROGRAM");
+    ["iTerm.app", "WezTerm", "vscode", "ghostty", "Hyper"].contains(&program.as_str())
+        || env("VTE_VERSION")
+            .parse::<u32>()
+            .is_ok_and(|version| version >= 5000)
+        || ["KITTY_WINDOW_ID", "WT_SESSION", "KONSOLE_VERSION"]
+            .iter()
+            .any(|name| std::env::var_os(name).is_some())
+        || env("TERM").contains("kitty")
+        || env("TERM").contains("alacritty")
+}
+
+/// Wrap the URLs drawn in `area` in OSC 8 escapes. Cells are rewritten two
+/// at a time, because the diff skips the cell after one whose symbol looks
+/// wide; a URL cut off at the edge links to the full one from `known`.
+pub fn hyperlink_area(buffer: &mut Buffer, area: Rect, known: &[String]) {
+    for y in area.top()..area.bottom() {
+        let mut row = String::new();
+        let mut offsets = Vec::with_capacity(area.width as usize);
+        for x in area.left()..area.right() {
+            offsets.push(row.len());
+            row.push_str(buffer.get(x, y).symbol());
+        }
+        for range in find_urls(&row) {
+            let visible = &row[range.clone()];
+            let url = if range.end == row.len() {
+                known
+                    .iter()
+                    .find(|link| link.starts_with(visible))
+                    .map_or(visible, String::as_str)
+            } else {
+                visible
+            };
+            let cells: Vec<u16> = (area.left()..area.right())
+                .zip(&offsets)
+                .filter(|(_, offset)| range.contains(offset))
+                .map(|(x, _)| x)
+                .collect();
+            for pair in cells.chunks_exact(2) {
+                let text = format!(
+                    "{}{}",
+                    buffer.get(pair[0], y).symbol(),
+                    buffer.get(pair[1], y).symbol()
+                );
+                buffer
+                    .get_mut(pair[0], y)
+                    .set_symbol(&format!("\x1b]8;;{}\x07{}\x1b]8;;\x07", url, text));
+            }
+        }
+    }
+}
+
+/// The `/links` popup: recent URLs with one selected.
+pub struct LinkPicker {
+    pub links: Vec<String>,
+    pub selected: usize,
+}
+
+impl LinkPicker {
+    pub fn new(links: Vec<String>) -> Self {
+        Self { links, selected: 0 }
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.links.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.links.get(self.selected).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(text: &str) -> Vec<&str> {
+        find_urls(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn urls_drop_trailing_punctuation() {
+        assert_eq!(
+            urls("see https://example.com/a_b. and (http://x.io/wiki/Foo_(bar)), ok"),
+            vec!["https://example.com/a_b", "http://x.io/wiki/Foo_(bar)"]
+        );
+        assert_eq!(
+            urls("**https://bold.example**"),
+            vec!["https://bold.example"]
+        );
+        assert!(urls("no links here").is_empty());
+    }
+
+    #[test]
+    fn recent_links_are_newest_first_and_distinct() {
+        let message = |body: &str| ChatMessage {
+            from: "alice".to_string(),
+            body: body.to_string(),
+            timestamp: 0,
+            ..Default::default()
+        };
+        let messages = vec![
+            message("https://a.example and https://b.example"),
+            message("again https://a.example"),
+        ];
+        assert_eq!(
+            recent(&messages, PICKER_LIMIT),
+            vec!["https://a.example", "https://b.example"]
+        );
+        assert_eq!(recent(&messages, 1), vec!["https://a.example"]);
+    }
+
+    #[test]
+    fn hyperlinks_wrap_cell_pairs() {
+        let area = Rect::new(0, 0, 23, 1);
+        let mut buffer = Buffer::empty(area);
+        buffer.set_string(0, 0, "go https://ex.io/abcdef", Style::default());
+        hyperlink_area(&mut buffer, area, &["https://ex.io/abcdefgh".to_string()]);
+
+        assert_eq!(buffer.get(2, 0).symbol(), " ");
+        assert_eq!(
+            buffer.get(3, 0).symbol(),
+            "\x1b]8;;https://ex.io/abcdefgh\x07ht\x1b]8;;\x07"
+        );
+        assert_eq!(buffer.get(4, 0).symbol(), "t");
+    }
+}
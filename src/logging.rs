@@ -0,0 +1,229 @@
+//! Client-side logging. Lines go to an optional file, to stderr while the
+//! TUI is not on screen, and to the server `log` RPC only when asked for.
+//! Everything is redacted before it reaches any target.
+
+use crate::websocket_client::WebSocketClient;
+use capnweb_core::CapId;
+use regex::Regex;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::mpsc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            other => Err(format!(
+                "unknown log level `{}` (expected error, warn, info, debug or trace)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        f.write_str(name)
+    }
+}
+
+struct Logger {
+    level: Level,
+    file: Option<Mutex<File>>,
+    /// Cleared while the TUI owns the terminal
+    stderr: AtomicBool,
+    remote_enabled: bool,
+    remote: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    secrets: Mutex<Vec<String>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Set up logging once at startup. Without a call, nothing is logged.
+pub fn init(
+    level: Level,
+    file: Option<&Path>,
+    remote: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let file = match file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        None => None,
+    };
+    let _ = LOGGER.set(Logger {
+        level,
+        file,
+        stderr: AtomicBool::new(true),
+        remote_enabled: remote,
+        remote: Mutex::new(None),
+        secrets: Mutex::new(Vec::new()),
+    });
+    Ok(())
+}
+
+/// Stop (or resume) writing to stderr, which would corrupt the TUI.
+pub fn set_tui_active(active: bool) {
+    if let Some(logger) = LOGGER.get() {
+        logger.stderr.store(!active, Ordering::Relaxed);
+    }
+}
+
+/// Forward log lines to the server's `log` RPC, if `--log-remote` was given.
+pub fn attach_remote(client: Arc<WebSocketClient>, capability: CapId) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    if !logger.remote_enabled {
+        return;
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            // Failures are not logged again, which could loop forever
+            let _ = client.log(capability, &line).await;
+        }
+    });
+    *logger.remote.lock().unwrap() = Some(tx);
+}
+
+/// Remember a secret (such as a password) so it is masked wherever it appears.
+pub fn register_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    if let Some(logger) = LOGGER.get() {
+        let mut secrets = logger.secrets.lock().unwrap();
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    LOGGER.get().is_some_and(|logger| level <= logger.level)
+}
+
+pub fn write(level: Level, target: &str, message: &str) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    if level > logger.level {
+        return;
+    }
+
+    let mut message = redact(message);
+    for secret in logger.secrets.lock().unwrap().iter() {
+        message = message.replace(secret.as_str(), REDACTED);
+    }
+    let millis = crate::ratatui_client::now_millis();
+    let line = format!(
+        "{}.{:03} {:<5} {}: {}",
+        millis / 1000,
+        millis % 1000,
+        level,
+        target,
+        message
+    );
+
+    if let Some(file) = &logger.file
+        && let Ok(mut file) = file.lock()
+    {
+        let _ = writeln!(file, "{}", line);
+    }
+    if logger.stderr.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    }
+    if let Some(remote) = logger.remote.lock().unwrap().as_ref() {
+        let _ = remote.send(format!("{} {}: {}", level, target, message));
+    }
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+// Mask values that follow secret-looking keys, e.g. `password='hunter2'`.
+fn redact(message: &str) -> String {
+    static SECRET_VALUE: OnceLock<Regex> = OnceLock::new();
+    let pattern = SECRET_VALUE.get_or_init(|| {
+        Regex::new(r#"(?i)\b(password|passwd|pwd|token|secret)(\s*[=:]\s*)('[^']*'|"[^"]*"|\S+)"#)
+            .expect("redaction pattern is valid")
+    });
+    pattern
+        .replace_all(message, format!("${{1}}${{2}}{}", REDACTED))
+        .into_owned()
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::write($level, module_path!(), &format!($($arg)*));
+        }
+    };
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::logging::log_at!($crate::logging::Level::Error, $($arg)*) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::logging::log_at!($crate::logging::Level::Warn, $($arg)*) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::logging::log_at!($crate::logging::Level::Info, $($arg)*) };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::logging::log_at!($crate::logging::Level::Debug, $($arg)*) };
+}
+
+pub(crate) use {log_at, log_debug, log_error, log_info, log_warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_parse_and_order() {
+        assert_eq!("WARNING".parse::<Level>(), Ok(Level::Warn));
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Error < Level::Debug);
+    }
+
+    #[test]
+    fn secret_values_are_redacted() {
+        assert_eq!(
+            redact("identify nick='bob', password='hunter2 x'"),
+            "identify nick='bob', password=[REDACTED]"
+        );
+        assert_eq!(redact("token: abc123 rest"), "token: [REDACTED] rest");
+        assert_eq!(redact("nothing secret here"), "nothing secret here");
+    }
+}
@@ -0,0 +1,335 @@
+//! A small markdown renderer for message bodies. It understands emphasis,
+//! inline code, fenced code blocks, quotes, lists and headings, and keeps
+//! one output line per input line so search matches still line up.
+
+use crate::theme::Theme;
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Span;
+use std::ops::Range;
+
+/// Render `body` into one list of spans per line.
+pub fn render(body: &str, theme: &Theme) -> Vec<Vec<Span<'static>>> {
+    let mut fence: Option<String> = None;
+    let mut lines = Vec::new();
+    for line in body.split('\n') {
+        let trimmed = line.trim_start();
+        if let Some(info) = trimmed.strip_prefix("```") {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(info.trim().to_ascii_lowercase()),
+            };
+            lines.push(vec![Span::styled(line.to_string(), theme.dim)]);
+        } else if let Some(language) = &fence {
+            lines.push(highlight_code(line, language, theme));
+        } else {
+            lines.push(render_line(line, theme));
+        }
+    }
+    lines
+}
+
+fn render_line(line: &str, theme: &Theme) -> Vec<Span<'static>> {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    if let Some(quoted) = trimmed.strip_prefix('>') {
+        let mut spans = vec![Span::styled(format!("{}│ ", indent), theme.dim)];
+        spans.extend(render_inline(quoted.trim_start(), theme.quote, theme));
+        return spans;
+    }
+    if let Some(item) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| trimmed.strip_prefix(bullet))
+    {
+        let mut spans = vec![Span::styled(format!("{}• ", indent), theme.dim)];
+        spans.extend(render_inline(item, theme.text, theme));
+        return spans;
+    }
+    if let Some((number, item)) = trimmed.split_once(". ")
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+    {
+        let mut spans = vec![Span::styled(format!("{}{}. ", indent, number), theme.dim)];
+        spans.extend(render_inline(item, theme.text, theme));
+        return spans;
+    }
+    let heading = trimmed.trim_start_matches('#');
+    if heading.len() < trimmed.len()
+        && trimmed.len() - heading.len() <= 6
+        && heading.starts_with(' ')
+    {
+        let style = theme
+            .text
+            .add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+        return render_inline(heading.trim_start(), style, theme);
+    }
+    render_inline(line, theme.text, theme)
+}
+
+const DELIMITERS: &[(&str, Modifier)] = &[
+    ("**", Modifier::BOLD),
+    ("__", Modifier::BOLD),
+    ("~~", Modifier::CROSSED_OUT),
+    ("*", Modifier::ITALIC),
+    ("_", Modifier::ITALIC),
+];
+
+/// Render emphasis and inline code within one line. A delimiter only opens
+/// when it is closed later on the line, so a lone `*` stays literal.
+fn render_inline(text: &str, base: Style, theme: &Theme) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut open: Vec<&str> = Vec::new();
+    let style = |open: &[&str]| {
+        open.iter().fold(base, |style, delimiter| {
+            let (_, modifier) = DELIMITERS.iter().find(|(d, _)| d == delimiter).unwrap();
+            style.add_modifier(*modifier)
+        })
+    };
+    let flush = |plain: &mut String, spans: &mut Vec<Span<'static>>, style: Style| {
+        if !plain.is_empty() {
+            spans.push(Span::styled(std::mem::take(plain), style));
+        }
+    };
+
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`'
+            && let Some(end) = rest[1..].find('`')
+        {
+            flush(&mut plain, &mut spans, style(&open));
+            spans.push(Span::styled(rest[1..end + 1].to_string(), theme.code));
+            rest = &rest[end + 2..];
+            continue;
+        }
+
+        let delimiter = DELIMITERS
+            .iter()
+            .map(|(d, _)| *d)
+            .find(|d| rest.starts_with(d));
+        if let Some(delimiter) = delimiter {
+            let after = &rest[delimiter.len()..];
+            // Word-internal underscores (snake_case) are not emphasis
+            let intraword = delimiter.starts_with('_')
+                && plain.chars().last().is_some_and(char::is_alphanumeric)
+                && !open.contains(&delimiter);
+            if open.last() == Some(&delimiter) {
+                flush(&mut plain, &mut spans, style(&open));
+                open.pop();
+                rest = after;
+                continue;
+            }
+            if !intraword
+                && !open.contains(&delimiter)
+                && !after.starts_with(char::is_whitespace)
+                && after.find(delimiter).is_some_and(|end| end > 0)
+            {
+                flush(&mut plain, &mut spans, style(&open));
+                open.push(delimiter);
+                rest = after;
+                continue;
+            }
+        }
+
+        plain.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    flush(&mut plain, &mut spans, style(&open));
+    if spans.is_empty() {
+        spans.push(Span::styled(String::new(), base));
+    }
+    spans
+}
+
+/// Split `spans` so that the ranges `find` reports within each span carry
+/// `style` on top of the span's own.
+pub fn style_ranges(
+    spans: Vec<Span<'static>>,
+    find: impl Fn(&str) -> Vec<Range<usize>>,
+    style: Style,
+) -> Vec<Span<'static>> {
+    let mut styled = Vec::with_capacity(spans.len());
+    for span in spans {
+        let ranges = find(&span.content);
+        if ranges.is_empty() {
+            styled.push(span);
+            continue;
+        }
+        let text = span.content.as_ref();
+        let mut cursor = 0;
+        for range in ranges {
+            if range.start > cursor {
+                styled.push(Span::styled(
+                    text[cursor..range.start].to_string(),
+                    span.style,
+                ));
+            }
+            styled.push(Span::styled(
+                text[range.clone()].to_string(),
+                span.style.patch(style),
+            ));
+            cursor = range.end;
+        }
+        if cursor < text.len() {
+            styled.push(Span::styled(text[cursor..].to_string(), span.style));
+        }
+    }
+    styled
+}
+
+struct Language {
+    /// Space-separated
+    keywords: &'static str,
+    line_comment: &'static str,
+    single_quote_strings: bool,
+}
+
+const RUST_KEYWORDS: &str = concat!(
+    "as async await break const continue crate else enum false fn for if impl in let loop ",
+    "match mod move mut pub ref return self Self static struct super trait true type ",
+    "unsafe use where while",
+);
+
+const PYTHON_KEYWORDS: &str = concat!(
+    "and as async await break class continue def elif else except False finally for from ",
+    "if import in is lambda None not or pass raise return True try while with yield",
+);
+
+const JS_KEYWORDS: &str = concat!(
+    "async await break case catch class const continue default else export extends false ",
+    "for function if import in interface let new null of return switch this throw true ",
+    "try type typeof undefined var while",
+);
+
+const SHELL_KEYWORDS: &str =
+    "case do done elif else esac export fi for function if in local return then while";
+
+fn language(name: &str) -> Option<Language> {
+    let (keywords, line_comment, single_quote_strings) = match name {
+        "rust" | "rs" => (RUST_KEYWORDS, "//", false),
+        "python" | "py" => (PYTHON_KEYWORDS, "#", true),
+        "javascript" | "js" | "typescript" | "ts" => (JS_KEYWORDS, "//", true),
+        "sh" | "bash" | "shell" | "zsh" => (SHELL_KEYWORDS, "#", true),
+        _ => return None,
+    };
+    Some(Language {
+        keywords,
+        line_comment,
+        single_quote_strings,
+    })
+}
+
+/// Color keywords, strings, numbers and comments in a line of code. Unknown
+/// languages get the plain code style.
+fn highlight_code(line: &str, language_name: &str, theme: &Theme) -> Vec<Span<'static>> {
+    let Some(language) = language(language_name) else {
+        return vec![Span::styled(line.to_string(), theme.code)];
+    };
+
+    let mut spans = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        if rest.starts_with(language.line_comment) {
+            spans.push(Span::styled(rest.to_string(), theme.code_comment));
+            break;
+        }
+        let c = rest.chars().next().unwrap_or_default();
+        let (len, style) = if c == '"' || (c == '\'' && language.single_quote_strings) {
+            (string_len(rest, c), theme.code_string)
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '.' && ch != '_')
+                .unwrap_or(rest.len());
+            (len, theme.code_string)
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|ch: char| !ch.is_alphanumeric() && ch != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let style = if language.keywords.split(' ').any(|keyword| keyword == word) {
+                theme.code_keyword
+            } else {
+                theme.code
+            };
+            (len, style)
+        } else {
+            let len = rest
+                .find(|ch: char| {
+                    ch.is_alphanumeric()
+                        || ch == '_'
+                        || ch == '"'
+                        || ch == '\''
+                        || language.line_comment.starts_with(ch)
+                })
+                .filter(|&len| len > 0)
+                .unwrap_or(c.len_utf8());
+            (len, theme.code)
+        };
+        spans.push(Span::styled(rest[..len].to_string(), style));
+        rest = &rest[len..];
+    }
+    if spans.is_empty() {
+        spans.push(Span::styled(String::new(), theme.code));
+    }
+    spans
+}
+
+// Length of the string literal at the start of `text`, through its closing
+// quote or to the end of the line.
+fn string_len(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(spans: &[Span]) -> String {
+        spans.iter().map(|span| span.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn emphasis_and_code_drop_their_markers() {
+        let theme = Theme::dark();
+        let line = render_inline(
+            "a **bold** and `x * y` in snake_case_name",
+            theme.text,
+            &theme,
+        );
+        assert_eq!(text(&line), "a bold and x * y in snake_case_name");
+        let bold = line.iter().find(|span| span.content == "bold").unwrap();
+        assert!(bold.style.add_modifier.contains(Modifier::BOLD));
+        let code = line.iter().find(|span| span.content == "x * y").unwrap();
+        assert_eq!(code.style, theme.code);
+
+        let literal = render_inline("2 * 3 = 6", theme.text, &theme);
+        assert_eq!(text(&literal), "2 * 3 = 6");
+    }
+
+    #[test]
+    fn blocks_keep_one_line_per_input_line() {
+        let theme = Theme::dark();
+        let lines = render(
+            "> quoted\n- item\n```rust\nlet x = \"s\"; // note\n```\n# Title",
+            &theme,
+        );
+        assert_eq!(lines.len(), 6);
+        assert_eq!(text(&lines[0]), "│ quoted");
+        assert_eq!(text(&lines[1]), "• item");
+        assert_eq!(text(&lines[3]), "let x = \"s\"; // note");
+        let keyword = lines[3].iter().find(|span| span.content == "let").unwrap();
+        assert_eq!(keyword.style, theme.code_keyword);
+        let comment = lines[3].last().unwrap();
+        assert_eq!(comment.content, "// note");
+        assert_eq!(text(&lines[5]), "Title");
+    }
+}
@@ -0,0 +1,277 @@
+//! Mentions and notifications: spotting your nick or a keyword in a message,
+//! and telling you about it with the terminal bell, an OSC 9 or OSC 777
+//! terminal notification, or `notify-send`, at most once per interval.
+//!
+//! ```toml
+//! [notifications]
+//! keywords = ["deploy", "capinrs"]
+//! terminal = "osc777"
+//! desktop = true
+//! min_interval_secs = 30
+//! ```
+
+use regex::Regex;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Name of the buffer that collects a copy of every mention.
+pub const MENTIONS_BUFFER: &str = "*mentions*";
+
+// Long bodies are cut before they reach the terminal or the desktop.
+const MAX_BODY_CHARS: usize = 200;
+
+/// How to raise a notification through the terminal itself.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalNotification {
+    #[default]
+    None,
+    /// `ESC ] 9 ; body BEL`, understood by iTerm2, WezTerm and Windows Terminal
+    Osc9,
+    /// `ESC ] 777 ; notify ; title ; body BEL`, understood by VTE terminals and kitty
+    Osc777,
+}
+
+/// The `[notifications]` table.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    /// Highlight and notify when someone says your nick
+    pub mentions: bool,
+    /// Notify on direct messages in a buffer you are not looking at
+    pub direct_messages: bool,
+    /// Ring the terminal bell along with a notification
+    pub bell: bool,
+    /// Words that count as a mention along with your nick
+    pub keywords: Vec<String>,
+    pub terminal: TerminalNotification,
+    /// Also run `notify-send`
+    pub desktop: bool,
+    /// Notifications closer together than this are dropped
+    pub min_interval_secs: u64,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            mentions: true,
+            direct_messages: true,
+            bell: true,
+            keywords: Vec::new(),
+            terminal: TerminalNotification::None,
+            desktop: false,
+            min_interval_secs: 10,
+        }
+    }
+}
+
+/// Finds the current nick and the configured keywords as whole words,
+/// ignoring case.
+#[derive(Clone, Debug)]
+pub struct MentionMatcher {
+    keywords: Vec<String>,
+    pattern: Option<Regex>,
+}
+
+impl MentionMatcher {
+    pub fn new(nickname: &str, keywords: &[String]) -> Self {
+        let mut matcher = Self {
+            keywords: keywords.to_vec(),
+            pattern: None,
+        };
+        matcher.set_nickname(nickname);
+        matcher
+    }
+
+    pub fn set_nickname(&mut self, nickname: &str) {
+        let words: Vec<String> = std::iter::once(nickname)
+            .chain(self.keywords.iter().map(String::as_str))
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                // `\b` only works next to word characters, so nicks like
+                // `[bob]` match without one
+                let edge = |c: Option<char>| match c {
+                    Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+                    _ => "",
+                };
+                format!(
+                    "{}{}{}",
+                    edge(word.chars().next()),
+                    regex::escape(word),
+                    edge(word.chars().last())
+                )
+            })
+            .collect();
+        self.pattern = if words.is_empty() {
+            None
+        } else {
+            Regex::new(&format!("(?i){}", words.join("|"))).ok()
+        };
+    }
+
+    /// Byte ranges of the mentions in `text`.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        match &self.pattern {
+            Some(pattern) => pattern.find_iter(text).map(|found| found.range()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.pattern
+            .as_ref()
+            .is_some_and(|pattern| pattern.is_match(text))
+    }
+}
+
+/// Sends notifications, dropping any that follow the last one too closely.
+pub struct Notifier {
+    config: NotificationConfig,
+    last_sent: Option<Instant>,
+}
+
+impl Notifier {
+    pub fn new(config: NotificationConfig) -> Self {
+        Self {
+            config,
+            last_sent: None,
+        }
+    }
+
+    pub fn config(&self) -> &NotificationConfig {
+        &self.config
+    }
+
+    /// True when a notification may go out at `now`, which then starts a
+    /// new interval.
+    fn allow(&mut self, now: Instant) -> bool {
+        let interval = Duration::from_secs(self.config.min_interval_secs);
+        if let Some(last) = self.last_sent
+            && now.duration_since(last) < interval
+        {
+            return false;
+        }
+        self.last_sent = Some(now);
+        true
+    }
+
+    /// Raise a notification through `terminal` and the desktop. Returns
+    /// false when it was rate limited.
+    pub fn notify(
+        &mut self,
+        terminal: &mut impl Write,
+        title: &str,
+        body: &str,
+    ) -> io::Result<bool> {
+        if !self.allow(Instant::now()) {
+            return Ok(false);
+        }
+        // Message text comes from other users, so no escapes get through
+        let title = sanitize(title);
+        let body = sanitize(body);
+        if self.config.bell {
+            terminal.write_all(b"\x07")?;
+        }
+        match self.config.terminal {
+            TerminalNotification::None => {}
+            TerminalNotification::Osc9 => {
+                write!(terminal, "\x1b]9;{}: {}\x07", title, body)?;
+            }
+            TerminalNotification::Osc777 => {
+                // `;` separates the fields
+                write!(
+                    terminal,
+                    "\x1b]777;notify;{};{}\x07",
+                    title.replace(';', ","),
+                    body.replace(';', ",")
+                )?;
+            }
+        }
+        terminal.flush()?;
+        if self.config.desktop {
+            desktop_notification(&title, &body)?;
+        }
+        Ok(true)
+    }
+}
+
+fn sanitize(text: &str) -> String {
+    let mut clean: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(MAX_BODY_CHARS)
+        .collect();
+    if text.chars().count() > MAX_BODY_CHARS {
+        clean.push('…');
+    }
+    clean
+}
+
+fn desktop_notification(title: &str, body: &str) -> io::Result<()> {
+    let mut child = Command::new("notify-send")
+        .args(["--app-name=capinrs", "--", title, body])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_match_whole_words_ignoring_case() {
+        let matcher = MentionMatcher::new("bob", &["Deploy".to_string()]);
+        let text = "hey Bob, bobby says the deploy is done";
+        let found: Vec<&str> = matcher
+            .find(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect();
+        assert_eq!(found, vec!["Bob", "deploy"]);
+        assert!(!matcher.is_match("bobcat"));
+
+        let mut matcher = MentionMatcher::new("[bob]", &[]);
+        assert!(matcher.is_match("ping [bob]!"));
+        matcher.set_nickname("alice");
+        assert!(!matcher.is_match("ping [bob]!"));
+        assert!(matcher.is_match("alice: hi"));
+    }
+
+    #[test]
+    fn notifications_are_rate_limited_and_sanitized() {
+        let mut notifier = Notifier::new(NotificationConfig {
+            terminal: TerminalNotification::Osc777,
+            ..NotificationConfig::default()
+        });
+        let start = Instant::now();
+        assert!(notifier.allow(start));
+        assert!(!notifier.allow(start + Duration::from_secs(5)));
+        assert!(notifier.allow(start + Duration::from_secs(10)));
+
+        let mut notifier = Notifier::new(NotificationConfig {
+            terminal: TerminalNotification::Osc777,
+            ..NotificationConfig::default()
+        });
+        let mut out = Vec::new();
+        assert!(
+            notifier
+                .notify(&mut out, "alice", "hi\x1b]0;x\x07 bob")
+                .unwrap()
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x07\x1b]777;notify;alice;hi ]0,x  bob\x07"
+        );
+        let mut out = Vec::new();
+        assert!(!notifier.notify(&mut out, "alice", "again").unwrap());
+        assert!(out.is_empty());
+    }
+}
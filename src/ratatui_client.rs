@@ -1,7 +1,19 @@
+use crate::attachments::format_size;
+use crate::commands::{PendingInteraction, Registry};
+use crate::completion::Completion;
+use crate::config::{AwayConfig, Settings};
+use crate::ignore::{IgnoreList, IgnoreRule};
+use crate::keymap::{Action, InputMode, Keymap};
+use crate::links::{self, LinkPicker};
+use crate::markdown;
+use crate::notify::{MENTIONS_BUFFER, MentionMatcher, Notifier};
+use crate::search::{SearchMatch, SearchQuery, SearchState};
+use crate::theme::{LayoutOptions, StatusPosition, Theme};
+use crate::websocket_client::{Attachment, MessageKind, Reaction, RoomTopic};
 use capnweb_core::CapId;
 use crossterm::{
     event::{
-        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+        DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
         MouseEventKind,
     },
     execute,
@@ -10,35 +22,197 @@ use crossterm::{
 use ratatui::{
     Terminal,
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
     text::{Line, Span},
     widgets::{
-        Block, Borders, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation,
-        ScrollbarState, Wrap,
+        Clear, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Tabs, Wrap,
     },
 };
 use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Clone)]
+/// Reactions toggled with 1-4 in selection mode.
+pub const QUICK_REACTIONS: [&str; 4] = ["👍", "❤️", "😂", "🎉"];
+
+// A typing indicator lapses this long after the last notice for it.
+const TYPING_TIMEOUT_MS: u64 = 6_000;
+// We tell the server we are typing at most this often.
+const TYPING_INTERVAL_MS: u64 = 3_000;
+
+// Below this terminal width the nick list is hidden even when toggled on.
+const NICK_LIST_MIN_WIDTH: u16 = 80;
+const NICK_LIST_WIDTH: u16 = 24;
+
+#[derive(Clone, Default)]
 pub struct ChatMessage {
+    /// Server-assigned id, used to edit and delete
+    pub id: Option<u64>,
     pub from: String,
     pub body: String,
     pub timestamp: u64,
+    pub edited: bool,
+    /// Id of the message this one replies to
+    pub reply_to: Option<u64>,
+    pub reactions: Vec<Reaction>,
+    pub kind: MessageKind,
+    pub attachment: Option<Attachment>,
+}
+
+impl ChatMessage {
+    /// Rows the message takes in the list: one per line of the body, plus
+    /// one for an attachment and one for its reactions.
+    pub fn line_count(&self) -> usize {
+        self.search_lines().count()
+    }
+
+    // The text of each row, as search sees it: the attachment row is its
+    // file name and the reaction row is blank.
+    fn search_lines(&self) -> impl Iterator<Item = &str> {
+        let attachment_row = self.attachment.as_ref().map(|a| a.name.as_str());
+        let reaction_row = (!self.reactions.is_empty()).then_some("");
+        self.body
+            .split('\n')
+            .chain(attachment_row)
+            .chain(reaction_row)
+    }
 }
 
 impl From<crate::websocket_client::ChatMessage> for ChatMessage {
     fn from(msg: crate::websocket_client::ChatMessage) -> Self {
         Self {
+            id: msg.id,
             from: msg.from,
             body: msg.body,
             timestamp: msg.timestamp,
+            edited: msg.edited_at.is_some(),
+            reply_to: msg.reply_to,
+            reactions: msg.reactions,
+            kind: msg.kind,
+            attachment: msg.attachment,
         }
     }
 }
 
-pub struct ChatApp {
+/// Someone typing in a room, shown until `until` (milliseconds).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Typist {
+    pub nickname: String,
+    pub room: String,
+    pub until: u64,
+}
+
+/// Our own away status, set with /away or by idling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Away {
+    pub reason: String,
+    /// Set by idling, so the next key press ends it
+    pub auto: bool,
+}
+
+#[derive(Clone)]
+pub struct NickEntry {
+    pub nickname: String,
+    pub identified: bool,
+    pub away: bool,
+    pub last_active: u64,
+    pub rooms: Vec<String>,
+}
+
+impl From<crate::websocket_client::PresenceEntry> for NickEntry {
+    fn from(entry: crate::websocket_client::PresenceEntry) -> Self {
+        Self {
+            nickname: entry.nickname,
+            identified: entry.identified,
+            away: entry.away,
+            last_active: entry.last_active,
+            rooms: entry.rooms,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferKind {
+    Room,
+    /// Private conversation with a single nick
+    Query,
+    /// Copies of the messages that mention you
+    Mentions,
+}
+
+impl BufferKind {
+    pub fn for_name(name: &str) -> Self {
+        if name.starts_with('#') {
+            BufferKind::Room
+        } else if name == MENTIONS_BUFFER {
+            BufferKind::Mentions
+        } else {
+            BufferKind::Query
+        }
+    }
+}
+
+/// Scrollback for one room or query, with a count of messages that arrived
+/// while it was not the active tab and of those the ignore list dropped.
+pub struct Buffer {
+    pub name: String,
+    pub kind: BufferKind,
     pub messages: Vec<ChatMessage>,
+    pub unread: usize,
+    pub hidden: usize,
+    /// Rooms only; None when no topic is set
+    pub topic: Option<RoomTopic>,
+}
+
+impl Buffer {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: BufferKind::for_name(name),
+            messages: Vec::new(),
+            unread: 0,
+            hidden: 0,
+            topic: None,
+        }
+    }
+
+    fn push(&mut self, message: ChatMessage, max_messages: usize) {
+        self.messages.push(message);
+        if self.messages.len() > max_messages {
+            self.messages.remove(0);
+        }
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// Compact idle time for the nick list; empty while the user is active.
+fn format_idle(now: u64, last_active: u64) -> String {
+    let secs = now.saturating_sub(last_active) / 1000;
+    if secs < 60 {
+        String::new()
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else if secs < 86_400 {
+        format!("{}h", secs / 3600)
+    } else {
+        format!("{}d", secs / 86_400)
+    }
+}
+
+pub struct ChatApp {
+    pub buffers: Vec<Buffer>,
+    pub active_buffer: usize,
+    pub nick_list: Vec<NickEntry>,
+    pub show_nick_list: bool,
     pub input: String,
     pub status: String,
     pub is_error: bool,
@@ -46,16 +220,52 @@ pub struct ChatApp {
     pub scroll_state: ScrollbarState,
     pub list_state: ListState,
     pub password_input: Option<String>,
-    pub password_prompt: Option<String>,
-    pub current_password_command: Option<String>,
+    pub pending: Option<PendingInteraction>,
     pub command_history: Vec<String>,
     pub history_index: usize,
+    pub search: Option<SearchState>,
+    pub completion: Option<Completion>,
+    pub commands: Arc<Registry>,
+    pub keymap: Keymap,
+    pub mode: InputMode,
+    /// Show message bodies as typed instead of rendering markdown
+    pub show_raw: bool,
+    pub link_picker: Option<LinkPicker>,
+    /// Our own nick, so our messages are never mentions
+    pub nickname: String,
+    /// None when mention highlighting is turned off
+    pub mentions: Option<MentionMatcher>,
+    pub ignores: IgnoreList,
+    /// The config file `/ignore` and `/unignore` save the list to
+    pub config_path: Option<PathBuf>,
+    /// Message picked in selection mode, by id
+    pub selected_message: Option<u64>,
+    /// The next message sent is a reply to this one
+    pub replying_to: Option<u64>,
+    /// Root of the thread shown in the thread pane
+    pub thread: Option<u64>,
+    /// A command to submit instead of the input line, which is kept
+    pub queued_command: Option<String>,
+    pub typists: Vec<Typist>,
+    /// Room and time of our last typing notice
+    typing_sent: Option<(String, u64)>,
+    /// A typing notice waiting to be sent, for this room
+    typing_pending: Option<String>,
+    pub away: Option<Away>,
+    pub away_config: AwayConfig,
+    /// Time of the last key press, for auto-away
+    last_key: u64,
+    /// An away change for the server: a reason, or None for back
+    away_pending: Option<Option<String>>,
 }
 
 impl ChatApp {
     pub fn new() -> Self {
         Self {
-            messages: Vec::new(),
+            buffers: vec![Buffer::new(crate::websocket_client::DEFAULT_ROOM)],
+            active_buffer: 0,
+            nick_list: Vec::new(),
+            show_nick_list: true,
             input: String::new(),
             status: "Connecting...".to_string(),
             is_error: false,
@@ -63,49 +273,508 @@ impl ChatApp {
             scroll_state: ScrollbarState::new(0),
             list_state: ListState::default(),
             password_input: None,
-            password_prompt: None,
-            current_password_command: None,
+            pending: None,
             command_history: Vec::new(),
             history_index: 0,
+            search: None,
+            completion: None,
+            commands: Arc::new(Registry::standard()),
+            keymap: Keymap::default(),
+            mode: InputMode::Insert,
+            show_raw: false,
+            link_picker: None,
+            nickname: String::new(),
+            mentions: None,
+            ignores: IgnoreList::default(),
+            config_path: None,
+            selected_message: None,
+            replying_to: None,
+            thread: None,
+            queued_command: None,
+            typists: Vec::new(),
+            typing_sent: None,
+            typing_pending: None,
+            away: None,
+            away_config: AwayConfig::default(),
+            last_key: now_millis(),
+            away_pending: None,
         }
     }
 
     pub fn add_message(&mut self, message: ChatMessage) {
-        self.messages.push(message);
         // Keep only the last 100 messages to avoid memory issues
-        if self.messages.len() > 100 {
-            self.messages.remove(0);
-        }
-        // Update scroll state to show the latest message
-        self.scroll_to_bottom();
+        self.add_message_with_limit(message, 100);
     }
 
     pub fn add_message_with_limit(&mut self, message: ChatMessage, max_messages: usize) {
-        self.messages.push(message);
-        // Keep only the last max_messages to fit terminal size
-        if self.messages.len() > max_messages {
-            self.messages.remove(0);
+        let name = self.active_buffer_name().to_string();
+        self.add_message_to(&name, message, max_messages);
+    }
+
+    /// Append to the named buffer, opening it if this is its first message.
+    /// Mentions are also copied to the mentions buffer. Ignored messages are
+    /// only counted, and never open a buffer.
+    pub fn add_message_to(&mut self, buffer: &str, message: ChatMessage, max_messages: usize) {
+        if self.is_ignored(&message) {
+            if let Some(target) = self.buffers.iter_mut().find(|b| b.name == buffer) {
+                target.hidden += 1;
+            }
+            return;
+        }
+        self.note_activity(&message);
+        // Their message is what they were typing
+        self.typists
+            .retain(|typist| typist.nickname != message.from || typist.room != buffer);
+        let copy = (buffer != MENTIONS_BUFFER && self.is_mention(&message)).then(|| ChatMessage {
+            from: format!("{} [{}]", message.from, buffer),
+            ..message.clone()
+        });
+        self.push_message(buffer, message, max_messages);
+        if let Some(copy) = copy {
+            self.push_message(MENTIONS_BUFFER, copy, 100);
         }
-        // Update scroll state to show the latest message
+    }
+
+    fn push_message(&mut self, buffer: &str, message: ChatMessage, max_messages: usize) {
+        let index = self.open_buffer(buffer);
+        let is_active = index == self.active_buffer;
+        let target = &mut self.buffers[index];
+        target.push(message, max_messages);
+        if !is_active {
+            target.unread += 1;
+        } else if self.search.is_some() {
+            // Keep the view on the current match while searching
+            self.refresh_search(true);
+        } else {
+            // Update scroll state to show the latest message
+            self.scroll_to_bottom();
+        }
+    }
+
+    /// Replace the body of message `id` wherever it is shown.
+    pub fn edit_message(&mut self, id: u64, body: &str) {
+        for buffer in &mut self.buffers {
+            for message in &mut buffer.messages {
+                if message.id == Some(id) {
+                    message.body = body.to_string();
+                    message.edited = true;
+                }
+            }
+        }
+        if self.search.is_some() {
+            self.refresh_search(true);
+        }
+    }
+
+    /// Update the topic of an open room; other rooms are ignored.
+    pub fn set_topic(&mut self, topic: RoomTopic) {
+        if let Some(buffer) = self
+            .buffers
+            .iter_mut()
+            .find(|buffer| buffer.name == topic.room)
+        {
+            buffer.topic = topic.topic.is_some().then_some(topic);
+        }
+    }
+
+    /// Replace the reactions on message `id` wherever it is shown.
+    pub fn set_reactions(&mut self, id: u64, reactions: &[Reaction]) {
+        for buffer in &mut self.buffers {
+            for message in &mut buffer.messages {
+                if message.id == Some(id) {
+                    message.reactions = reactions.to_vec();
+                }
+            }
+        }
+        if self.search.is_some() {
+            self.refresh_search(true);
+        }
+    }
+
+    /// Remove message `id` from every buffer.
+    pub fn delete_message(&mut self, id: u64) {
+        for buffer in &mut self.buffers {
+            buffer.messages.retain(|message| message.id != Some(id));
+        }
+        if self.search.is_some() {
+            self.refresh_search(true);
+        }
+    }
+
+    /// Our most recent message in the active buffer that the server can edit.
+    pub fn last_own_message(&self) -> Option<&ChatMessage> {
+        self.active_messages()
+            .iter()
+            .rev()
+            .find(|message| message.id.is_some() && message.from == self.nickname)
+    }
+
+    pub fn active_buffer_name(&self) -> &str {
+        &self.buffers[self.active_buffer].name
+    }
+
+    pub fn active_buffer_kind(&self) -> BufferKind {
+        self.buffers[self.active_buffer].kind
+    }
+
+    pub fn active_messages(&self) -> &[ChatMessage] {
+        &self.buffers[self.active_buffer].messages
+    }
+
+    /// Returns the index of the named buffer, creating it if needed.
+    pub fn open_buffer(&mut self, name: &str) -> usize {
+        if let Some(index) = self.buffers.iter().position(|buffer| buffer.name == name) {
+            return index;
+        }
+        self.buffers.push(Buffer::new(name));
+        self.buffers.len() - 1
+    }
+
+    /// Closes the named buffer unless it is the last one open.
+    pub fn close_buffer(&mut self, name: &str) -> bool {
+        if self.buffers.len() <= 1 {
+            return false;
+        }
+        let Some(index) = self.buffers.iter().position(|buffer| buffer.name == name) else {
+            return false;
+        };
+        self.buffers.remove(index);
+        if self.active_buffer >= index && self.active_buffer > 0 {
+            self.active_buffer -= 1;
+        }
+        self.switch_buffer(self.active_buffer);
+        true
+    }
+
+    pub fn switch_buffer(&mut self, index: usize) {
+        if index >= self.buffers.len() {
+            return;
+        }
+        self.active_buffer = index;
+        self.buffers[index].unread = 0;
+        // Message ids only mean something in the buffer they came from
+        self.selected_message = None;
+        self.replying_to = None;
+        self.thread = None;
+        self.list_state.select(None);
         self.scroll_to_bottom();
+        if self.search.is_some() {
+            self.refresh_search(false);
+        }
     }
 
-    pub fn scroll_up(&mut self) {
-        if let Some(selected) = self.list_state.selected() {
-            if selected > 0 {
-                self.list_state.select(Some(selected - 1));
+    /// Start searching the active buffer. Incremental searches begin with an
+    /// empty query that is edited in place of the input line.
+    pub fn start_search(&mut self, query: SearchQuery, editing: bool) {
+        self.search = Some(SearchState::new(query, editing));
+        self.refresh_search(false);
+    }
+
+    pub fn end_search(&mut self) {
+        self.search = None;
+        self.scroll_to_bottom();
+    }
+
+    pub fn search_summary(&self) -> Option<String> {
+        self.search.as_ref().map(SearchState::summary)
+    }
+
+    fn refresh_search(&mut self, keep_position: bool) {
+        let Some(search) = self.search.as_mut() else {
+            return;
+        };
+        let previous = search.current;
+        let buffer = &self.buffers[self.active_buffer];
+        search.refresh(buffer.messages.iter().flat_map(ChatMessage::search_lines));
+        if keep_position && let Some(previous) = previous {
+            search.current = Some(previous.min(search.matches.len().saturating_sub(1)));
+        }
+        if let Some(found) = search.current_match() {
+            self.list_state.select(Some(found.line));
+        }
+    }
+
+    fn jump_to_match(&mut self, found: Option<SearchMatch>) {
+        if let Some(found) = found {
+            self.list_state.select(Some(found.line));
+        }
+    }
+
+    fn handle_search_input(&mut self, key: KeyEvent) -> bool {
+        let Some(search) = self.search.as_mut() else {
+            return false;
+        };
+        let editing = search.editing;
+        match self.keymap.lookup(InputMode::Insert, &key) {
+            Some(Action::Quit) => {
+                self.should_quit = true;
+                return true;
+            }
+            Some(Action::Search) => {
+                search.editing = true;
+                return false;
+            }
+            _ => {}
+        }
+        match key.code {
+            KeyCode::Esc => self.end_search(),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::ALT) => {
+                search.query.case_sensitive = !search.query.case_sensitive;
+                self.refresh_search(false);
+            }
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::ALT) => {
+                search.query.regex = !search.query.regex;
+                self.refresh_search(false);
+            }
+            KeyCode::Enter if editing => {
+                search.editing = false;
+            }
+            KeyCode::Enter => self.end_search(),
+            KeyCode::Backspace if editing => {
+                search.query.text.pop();
+                self.refresh_search(false);
             }
+            KeyCode::Char(c) if editing && !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                search.query.text.push(c);
+                self.refresh_search(false);
+            }
+            KeyCode::Char('n') if !editing => {
+                let found = search.next_older();
+                self.jump_to_match(found);
+            }
+            KeyCode::Char('N') if !editing => {
+                let found = search.next_newer();
+                self.jump_to_match(found);
+            }
+            KeyCode::Up => self.scroll_up(),
+            KeyCode::Down => self.scroll_down(),
+            _ => {}
+        }
+        false
+    }
+
+    pub fn set_nickname(&mut self, nickname: &str) {
+        self.nickname = nickname.to_string();
+        if let Some(mentions) = &mut self.mentions {
+            mentions.set_nickname(nickname);
+        }
+    }
+
+    /// True when someone else's message says our nick or a keyword.
+    pub fn is_mention(&self, message: &ChatMessage) -> bool {
+        message.from != "System"
+            && !message.from.eq_ignore_ascii_case(&self.nickname)
+            && self
+                .mentions
+                .as_ref()
+                .is_some_and(|mentions| mentions.is_match(&message.body))
+    }
+
+    /// True when someone else's message is on the ignore list. Events from
+    /// the server and client are always shown.
+    pub fn is_ignored(&self, message: &ChatMessage) -> bool {
+        !matches!(message.kind, MessageKind::System | MessageKind::Error)
+            && !message.from.eq_ignore_ascii_case(&self.nickname)
+            && self.ignores.hides(&message.from, &message.body)
+    }
+
+    pub fn active_hidden(&self) -> usize {
+        self.buffers[self.active_buffer].hidden
+    }
+
+    /// Put `rule` on the ignore list, or take it off when `add` is false,
+    /// then save the list to the config. Changes apply to the live list, so
+    /// commands running side by side cannot undo each other. Errors are
+    /// failures to save; the change itself has been made.
+    pub fn change_ignores(&mut self, rule: IgnoreRule, add: bool) -> Result<(), String> {
+        let spec = rule.to_string();
+        let changed = if add {
+            self.ignores.add(rule)
+        } else {
+            self.ignores.remove(&rule)
+        };
+        let body = match (add, changed) {
+            (true, true) => format!("Now ignoring {}.", spec),
+            (true, false) => format!("Already ignoring {}.", spec),
+            (false, true) => format!("No longer ignoring {}.", spec),
+            (false, false) => format!(
+                "{} is not on the ignore list. Type /ignores to see it.",
+                spec
+            ),
+        };
+        self.add_message(ChatMessage {
+            from: "System".to_string(),
+            kind: MessageKind::System,
+            body,
+            timestamp: now_millis(),
+            ..Default::default()
+        });
+        if !changed {
+            return Ok(());
+        }
+        match &self.config_path {
+            Some(path) => self.ignores.save(path),
+            None => Err("no config directory".to_string()),
+        }
+    }
+
+    pub fn set_presence(&mut self, users: Vec<NickEntry>) {
+        self.nick_list = users;
+    }
+
+    /// Show `nickname` as typing in `room` for a few seconds.
+    pub fn set_typing(&mut self, nickname: &str, room: &str, now: u64) {
+        if nickname == self.nickname {
+            return;
+        }
+        let until = now + TYPING_TIMEOUT_MS;
+        match self
+            .typists
+            .iter_mut()
+            .find(|typist| typist.nickname == nickname && typist.room == room)
+        {
+            Some(typist) => typist.until = until,
+            None => self.typists.push(Typist {
+                nickname: nickname.to_string(),
+                room: room.to_string(),
+                until,
+            }),
+        }
+    }
+
+    /// Stop showing `nickname` as typing, in `room` or everywhere.
+    pub fn clear_typing(&mut self, nickname: &str, room: Option<&str>) {
+        self.typists.retain(|typist| {
+            typist.nickname != nickname || room.is_some_and(|room| typist.room != room)
+        });
+    }
+
+    /// Drop lapsed indicators. Returns true when any were dropped.
+    pub fn expire_typing(&mut self, now: u64) -> bool {
+        let before = self.typists.len();
+        self.typists.retain(|typist| typist.until > now);
+        self.typists.len() != before
+    }
+
+    /// "alice is typing…" for the active buffer, if anyone is.
+    pub fn typing_summary(&self, now: u64) -> Option<String> {
+        let room = self.active_buffer_name();
+        let names: Vec<&str> = self
+            .typists
+            .iter()
+            .filter(|typist| typist.room == room && typist.until > now)
+            .map(|typist| typist.nickname.as_str())
+            .collect();
+        match names.as_slice() {
+            [] => None,
+            [one] => Some(format!("{} is typing…", one)),
+            [one, two] => Some(format!("{} and {} are typing…", one, two)),
+            _ => Some("Several people are typing…".to_string()),
+        }
+    }
+
+    /// Called when the input line changes; queues a typing notice for the
+    /// active room unless one went out recently.
+    pub fn note_input_changed(&mut self, now: u64) {
+        if self.active_buffer_kind() != BufferKind::Room
+            || self.input.trim().is_empty()
+            || self.input.starts_with('/')
+        {
+            return;
+        }
+        let room = self.active_buffer_name().to_string();
+        if let Some((sent_room, sent_at)) = &self.typing_sent
+            && *sent_room == room
+            && now.saturating_sub(*sent_at) < TYPING_INTERVAL_MS
+        {
+            return;
+        }
+        self.typing_sent = Some((room.clone(), now));
+        self.typing_pending = Some(room);
+    }
+
+    /// The room to send a typing notice for, if one is due.
+    pub fn take_typing(&mut self) -> Option<String> {
+        self.typing_pending.take()
+    }
+
+    /// Called on every key press; ends an automatic away.
+    pub fn note_key(&mut self, now: u64) {
+        self.last_key = now;
+        if self.away.as_ref().is_some_and(|away| away.auto) {
+            self.away = None;
+            self.away_pending = Some(None);
+        }
+    }
+
+    /// Go away once no key has been pressed for the configured time. True
+    /// when that just happened.
+    pub fn check_auto_away(&mut self, now: u64) -> bool {
+        let after = self.away_config.auto_after_mins.saturating_mul(60_000);
+        if after == 0 || self.away.is_some() || now.saturating_sub(self.last_key) < after {
+            return false;
+        }
+        let reason = self.away_config.reason.clone();
+        self.away = Some(Away {
+            reason: reason.clone(),
+            auto: true,
+        });
+        self.away_pending = Some(Some(reason));
+        true
+    }
+
+    /// Record an away set with /away, or cleared with /back.
+    pub fn set_away(&mut self, reason: Option<String>) {
+        self.away = reason.map(|reason| Away {
+            reason,
+            auto: false,
+        });
+        self.away_pending = None;
+    }
+
+    pub fn take_away_change(&mut self) -> Option<Option<String>> {
+        self.away_pending.take()
+    }
+
+    pub fn toggle_nick_list(&mut self) {
+        self.show_nick_list = !self.show_nick_list;
+    }
+
+    /// Whether the nick list is on and a terminal this wide has room for it.
+    pub fn nick_list_fits(&self, width: u16) -> bool {
+        self.show_nick_list && width >= NICK_LIST_MIN_WIDTH
+    }
+
+    // Messages reset the sender's idle time without waiting for a new snapshot.
+    fn note_activity(&mut self, message: &ChatMessage) {
+        if let Some(entry) = self
+            .nick_list
+            .iter_mut()
+            .find(|entry| entry.nickname == message.from)
+        {
+            entry.last_active = entry.last_active.max(message.timestamp);
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        if let Some(selected) = self.list_state.selected()
+            && selected > 0
+        {
+            self.list_state.select(Some(selected - 1));
         }
     }
 
     pub fn scroll_down(&mut self) {
         let total_items = self.get_total_message_lines();
-        if let Some(selected) = self.list_state.selected() {
-            if selected < total_items.saturating_sub(1) {
+        match self.list_state.selected() {
+            Some(selected) if selected < total_items.saturating_sub(1) => {
                 self.list_state.select(Some(selected + 1));
             }
-        } else if total_items > 0 {
-            self.list_state.select(Some(0));
+            Some(_) => {}
+            None if total_items > 0 => {
+                self.list_state.select(Some(0));
+            }
+            None => {}
         }
     }
 
@@ -117,15 +786,14 @@ impl ChatApp {
     }
 
     fn get_total_message_lines(&self) -> usize {
-        self.messages
+        self.active_messages()
             .iter()
-            .map(|msg| msg.body.matches('\n').count() + 1)
+            .map(ChatMessage::line_count)
             .sum()
     }
 
-    pub fn start_password_input(&mut self, prompt: String, command: String) {
-        self.password_prompt = Some(prompt);
-        self.current_password_command = Some(command);
+    pub fn start_prompt(&mut self, interaction: PendingInteraction) {
+        self.pending = Some(interaction);
         self.password_input = Some(String::new());
     }
 
@@ -133,8 +801,8 @@ impl ChatApp {
         self.password_input.is_some()
     }
 
-    pub fn get_password_prompt(&self) -> Option<&String> {
-        self.password_prompt.as_ref()
+    pub fn get_password_prompt(&self) -> Option<String> {
+        self.pending.as_ref().map(PendingInteraction::prompt)
     }
 
     pub fn get_password_input(&self) -> Option<&String> {
@@ -153,16 +821,11 @@ impl ChatApp {
         }
     }
 
-    pub fn finish_password_input(&mut self) -> Option<String> {
-        let password = self.password_input.clone();
-        self.password_input = None;
-        self.password_prompt = None;
-        self.current_password_command = None;
-        password
-    }
-
-    pub fn get_current_password_command(&self) -> Option<&String> {
-        self.current_password_command.as_ref()
+    /// Close the prompt, returning what it was for and what was typed.
+    pub fn finish_password_input(&mut self) -> Option<(PendingInteraction, String)> {
+        let password = self.password_input.take()?;
+        let interaction = self.pending.take()?;
+        Some((interaction, password))
     }
 
     pub fn add_to_history(&mut self, command: String) {
@@ -199,30 +862,6 @@ impl ChatApp {
         }
     }
 
-    pub async fn log(
-        &mut self,
-        client: &crate::websocket_client::WebSocketClient,
-        capability: capnweb_core::CapId,
-        message: &str,
-    ) {
-        match client.log(capability, message).await {
-            Ok(_) => {
-                // Log successful
-            }
-            Err(e) => {
-                // Add error message to UI instead of silently ignoring
-                self.add_message(ChatMessage {
-                    from: "Log Error".to_string(),
-                    body: format!("Log RPC failed: {}", e),
-                    timestamp: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                });
-            }
-        }
-    }
-
     pub fn set_status(&mut self, status: String, is_error: bool) {
         self.status = status;
         self.is_error = is_error;
@@ -231,11 +870,11 @@ impl ChatApp {
     pub fn handle_input(&mut self, key: KeyEvent) -> bool {
         // Handle password input mode
         if self.is_password_input_active() {
+            if self.keymap.lookup(InputMode::Insert, &key) == Some(Action::Quit) {
+                self.should_quit = true;
+                return true;
+            }
             match key.code {
-                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    self.should_quit = true;
-                    return true;
-                }
                 KeyCode::Enter => {
                     return true; // Signal that password is ready
                 }
@@ -250,52 +889,95 @@ impl ChatApp {
             return false; // Don't process as regular input
         }
 
-        // Regular input handling
-        match key.code {
-            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.should_quit = true;
-                return true;
-            }
-            KeyCode::Enter => {
-                return true; // Signal that input is ready
+        if self.link_picker.is_some() {
+            return self.handle_link_picker_input(key);
+        }
+
+        if self.selected_message.is_some() {
+            return self.handle_selection_input(key);
+        }
+
+        if self.replying_to.is_some() && key.code == KeyCode::Esc {
+            self.replying_to = None;
+            return false;
+        }
+
+        if self.search.is_some() {
+            return self.handle_search_input(key);
+        }
+
+        let action = self.keymap.lookup(self.mode, &key);
+        if !matches!(action, Some(Action::Complete | Action::CompletePrevious)) {
+            self.completion = None;
+        }
+        if let Some(action) = action {
+            return self.run_action(action);
+        }
+
+        // Unbound keys type in insert mode and do nothing in normal mode
+        if self.mode == InputMode::Insert
+            && let KeyCode::Char(c) = key.code
+            && !key.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            self.input.push(c);
+        }
+        false
+    }
+
+    /// Enter selection mode on the newest message that has an id.
+    pub fn start_selection(&mut self) {
+        match self.active_messages().iter().rev().find_map(|msg| msg.id) {
+            Some(id) => {
+                self.selected_message = Some(id);
+                self.scroll_to_message(id);
             }
-            KeyCode::Backspace => {
-                self.input.pop();
+            None => self.add_system_message("No messages to select in this buffer."),
+        }
+    }
+
+    fn handle_selection_input(&mut self, key: KeyEvent) -> bool {
+        if self.keymap.lookup(InputMode::Insert, &key) == Some(Action::Quit) {
+            self.should_quit = true;
+            return true;
+        }
+        let Some(selected) = self.selected_message else {
+            return false;
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(false),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(true),
+            KeyCode::Enter | KeyCode::Char('r') => {
+                self.selected_message = None;
+                self.replying_to = Some(selected);
+                self.mode = InputMode::Insert;
+                self.scroll_to_bottom();
             }
-            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                self.input.push(c);
+            KeyCode::Char('+') => {
+                self.selected_message = None;
+                self.mode = InputMode::Insert;
+                self.input = format!("/react {} ", selected);
             }
-            // Command history and scroll handling
-            KeyCode::Up => {
-                if let Some(history_command) = self.get_history_previous() {
-                    self.input = history_command;
-                } else {
-                    self.scroll_up();
-                }
+            KeyCode::Char(c @ '1'..='4') => {
+                let emoji = QUICK_REACTIONS[c as usize - '1' as usize];
+                let ours = self.find_message(selected).is_some_and(|msg| {
+                    msg.reactions.iter().any(|reaction| {
+                        reaction.emoji == emoji && reaction.users.contains(&self.nickname)
+                    })
+                });
+                let command = if ours { "unreact" } else { "react" };
+                self.queued_command = Some(format!("/{} {} {}", command, selected, emoji));
+                return true;
             }
-            KeyCode::Down => {
-                if let Some(history_command) = self.get_history_next() {
-                    self.input = history_command;
+            KeyCode::Char('t') => {
+                let root = self.thread_root(selected);
+                self.thread = if self.thread == Some(root) {
+                    None
                 } else {
-                    self.scroll_down();
-                }
-            }
-            KeyCode::PageUp => {
-                // Scroll up by multiple lines
-                for _ in 0..5 {
-                    self.scroll_up();
-                }
-            }
-            KeyCode::PageDown => {
-                // Scroll down by multiple lines
-                for _ in 0..5 {
-                    self.scroll_down();
-                }
-            }
-            KeyCode::Home => {
-                self.scroll_state = self.scroll_state.position(0);
+                    Some(root)
+                };
             }
-            KeyCode::End => {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.selected_message = None;
                 self.scroll_to_bottom();
             }
             _ => {}
@@ -303,13 +985,267 @@ impl ChatApp {
         false
     }
 
-    pub fn get_input(&mut self) -> String {
-        if self.is_password_input_active() {
-            // Return empty string for password input - it's handled separately
-            String::new()
+    fn move_selection(&mut self, forward: bool) {
+        let ids: Vec<u64> = self
+            .active_messages()
+            .iter()
+            .filter_map(|msg| msg.id)
+            .collect();
+        let Some(position) = ids.iter().position(|&id| Some(id) == self.selected_message) else {
+            return;
+        };
+        let next = if forward {
+            ids.get(position + 1)
         } else {
-            let input = self.input.clone();
-            self.input.clear();
+            position
+                .checked_sub(1)
+                .and_then(|previous| ids.get(previous))
+        };
+        if let Some(&id) = next {
+            self.selected_message = Some(id);
+            self.scroll_to_message(id);
+        }
+    }
+
+    // Bring the first line of message `id` into view.
+    fn scroll_to_message(&mut self, id: u64) {
+        let mut line = 0;
+        for msg in self.active_messages() {
+            if msg.id == Some(id) {
+                self.list_state.select(Some(line));
+                return;
+            }
+            line += msg.line_count();
+        }
+    }
+
+    pub fn find_message(&self, id: u64) -> Option<&ChatMessage> {
+        self.active_messages().iter().find(|msg| msg.id == Some(id))
+    }
+
+    // Follow replies up to the oldest ancestor still in the buffer. Parents
+    // always have smaller ids, which also rules out cycles.
+    fn thread_root(&self, id: u64) -> u64 {
+        let mut root = id;
+        while let Some(parent) = self.find_message(root).and_then(|msg| msg.reply_to)
+            && parent < root
+            && self.find_message(parent).is_some()
+        {
+            root = parent;
+        }
+        root
+    }
+
+    /// The open thread as (depth, message) pairs: the root, then each reply
+    /// under its parent in the order they arrived.
+    pub fn thread_messages(&self) -> Vec<(usize, ChatMessage)> {
+        let Some(root) = self.thread.and_then(|id| self.find_message(id)) else {
+            return Vec::new();
+        };
+        let mut thread = Vec::new();
+        let mut stack = vec![(0, root)];
+        while let Some((depth, message)) = stack.pop() {
+            thread.push((depth, message.clone()));
+            let replies = self
+                .active_messages()
+                .iter()
+                .filter(|reply| reply.reply_to.is_some() && reply.reply_to == message.id);
+            // Reversed so the oldest reply is popped first
+            stack.extend(replies.rev().map(|reply| (depth + 1, reply)));
+        }
+        thread
+    }
+
+    /// List recent links in the active buffer for opening.
+    pub fn open_link_picker(&mut self) {
+        let links = links::recent(self.active_messages(), links::PICKER_LIMIT);
+        if links.is_empty() {
+            self.add_system_message("No links in this buffer.");
+        } else {
+            self.link_picker = Some(LinkPicker::new(links));
+        }
+    }
+
+    fn handle_link_picker_input(&mut self, key: KeyEvent) -> bool {
+        if self.keymap.lookup(InputMode::Insert, &key) == Some(Action::Quit) {
+            self.should_quit = true;
+            return true;
+        }
+        let Some(picker) = self.link_picker.as_mut() else {
+            return false;
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => picker.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => picker.select_next(),
+            KeyCode::Enter | KeyCode::Char('o') => {
+                let url = picker.selected().map(str::to_string);
+                self.link_picker = None;
+                if let Some(url) = url {
+                    match links::open(&url) {
+                        Ok(()) => self.add_system_message(format!("Opening {}", url)),
+                        Err(err) => {
+                            self.add_system_message(format!("Could not open {}: {}", url, err))
+                        }
+                    }
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.link_picker = None,
+            _ => {}
+        }
+        false
+    }
+
+    fn add_system_message(&mut self, body: impl Into<String>) {
+        self.add_message(ChatMessage {
+            from: "System".to_string(),
+            kind: MessageKind::System,
+            body: body.into(),
+            timestamp: now_millis(),
+            ..Default::default()
+        });
+    }
+
+    /// Perform a bound action. Returns true when input is ready or quit was
+    /// requested, like `handle_input`.
+    pub fn run_action(&mut self, action: Action) -> bool {
+        match action {
+            Action::Quit => {
+                self.should_quit = true;
+                return true;
+            }
+            Action::Submit => return true,
+            Action::Complete => self.complete(true),
+            Action::CompletePrevious => self.complete(false),
+            Action::DeleteBack => {
+                self.input.pop();
+            }
+            Action::HistoryPrevious => {
+                if let Some(history_command) = self.get_history_previous() {
+                    self.input = history_command;
+                }
+            }
+            Action::HistoryNext => {
+                if let Some(history_command) = self.get_history_next() {
+                    self.input = history_command;
+                }
+            }
+            Action::ScrollUp => self.scroll_up(),
+            Action::ScrollDown => self.scroll_down(),
+            Action::RecallOrScrollUp => {
+                // Up on an empty line starts editing our last message
+                if self.input.is_empty()
+                    && self.history_index == self.command_history.len()
+                    && let Some(message) = self.last_own_message()
+                    && let Some(id) = message.id
+                {
+                    self.input = format!("/edit {} {}", id, message.body.replace('\n', " "));
+                } else if let Some(history_command) = self.get_history_previous() {
+                    self.input = history_command;
+                } else {
+                    self.scroll_up();
+                }
+            }
+            Action::RecallOrScrollDown => {
+                if let Some(history_command) = self.get_history_next() {
+                    self.input = history_command;
+                } else {
+                    self.scroll_down();
+                }
+            }
+            Action::PageUp => {
+                for _ in 0..5 {
+                    self.scroll_up();
+                }
+            }
+            Action::PageDown => {
+                for _ in 0..5 {
+                    self.scroll_down();
+                }
+            }
+            Action::ScrollTop => {
+                self.scroll_state = self.scroll_state.position(0);
+            }
+            Action::ScrollBottom => self.scroll_to_bottom(),
+            Action::ToggleNickList => self.toggle_nick_list(),
+            Action::ToggleRaw => self.show_raw = !self.show_raw,
+            Action::Links => self.open_link_picker(),
+            Action::Search => self.start_search(SearchQuery::default(), true),
+            Action::SelectMessage => self.start_selection(),
+            Action::Buffer(index) => self.switch_buffer(index),
+            Action::NextBuffer => {
+                self.switch_buffer((self.active_buffer + 1) % self.buffers.len());
+            }
+            Action::PreviousBuffer => {
+                let count = self.buffers.len();
+                self.switch_buffer((self.active_buffer + count - 1) % count);
+            }
+            Action::NormalMode if self.keymap.vi_mode() => self.mode = InputMode::Normal,
+            Action::NormalMode => {}
+            Action::InsertMode => self.mode = InputMode::Insert,
+            Action::CommandLine => {
+                self.mode = InputMode::Insert;
+                if self.input.is_empty() {
+                    self.input.push('/');
+                }
+            }
+        }
+        false
+    }
+
+    /// Complete the word before the cursor, cycling through candidates on
+    /// repeated presses.
+    fn complete(&mut self, forward: bool) {
+        if let Some(completion) = self.completion.as_mut() {
+            self.input = if forward {
+                completion.next()
+            } else {
+                completion.previous()
+            };
+            return;
+        }
+
+        // Recent speakers in this buffer come before the rest of the nick list
+        let mut nicks: Vec<String> = self.buffers[self.active_buffer]
+            .messages
+            .iter()
+            .rev()
+            .map(|msg| msg.from.clone())
+            .filter(|from| from != "System" && from != "Debug")
+            .collect();
+        nicks.extend(self.nick_list.iter().map(|user| user.nickname.clone()));
+
+        let mut rooms: Vec<String> = self
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.kind == BufferKind::Room)
+            .map(|buffer| buffer.name.clone())
+            .collect();
+        rooms.extend(self.nick_list.iter().flat_map(|user| user.rooms.clone()));
+
+        self.completion = Completion::start(&self.input, &nicks, &rooms, &self.commands);
+        if let Some(completion) = &self.completion {
+            self.input = completion.current();
+        }
+    }
+
+    pub fn get_input(&mut self) -> String {
+        if self.is_password_input_active() {
+            // Return empty string for password input - it's handled separately
+            String::new()
+        } else if let Some(command) = self.queued_command.take() {
+            command
+        } else {
+            let input = std::mem::take(&mut self.input);
+            // Whatever we type next is news again
+            self.typing_sent = None;
+            self.typing_pending = None;
+            // Plain text typed while replying goes out as a reply
+            if !input.trim().is_empty()
+                && !input.starts_with('/')
+                && let Some(parent) = self.replying_to.take()
+            {
+                return format!("/reply {} {}", parent, input);
+            }
             input
         }
     }
@@ -318,20 +1254,43 @@ impl ChatApp {
 pub struct RatatuiClient {
     app: ChatApp,
     terminal: Terminal<CrosstermBackend<io::Stdout>>,
+    theme: Theme,
+    layout: LayoutOptions,
+    /// Wrap URLs in OSC 8 escapes so the terminal makes them clickable
+    hyperlinks: bool,
+    notifier: Notifier,
 }
 
 impl RatatuiClient {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn new(settings: &Settings) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         // Setup terminal
         enable_raw_mode()?;
         let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+        execute!(stdout, EnterAlternateScreen)?;
+        // Without mouse capture the terminal's own text selection works
+        if settings.mouse {
+            execute!(stdout, EnableMouseCapture)?;
+        }
         let backend = CrosstermBackend::new(stdout);
         let terminal = Terminal::new(backend)?;
+        crate::logging::set_tui_active(true);
 
+        let mut app = ChatApp::new();
+        app.keymap = settings.keymap.clone();
+        app.ignores = settings.ignores.clone();
+        app.config_path = settings.config_path.clone();
+        app.away_config = settings.away.clone();
+        app.show_raw = !settings.markdown;
+        if settings.notifications.mentions {
+            app.mentions = Some(MentionMatcher::new("", &settings.notifications.keywords));
+        }
         Ok(Self {
-            app: ChatApp::new(),
+            app,
             terminal,
+            theme: settings.theme.clone(),
+            layout: settings.layout,
+            hyperlinks: settings.hyperlinks,
+            notifier: Notifier::new(settings.notifications.clone()),
         })
     }
 
@@ -343,8 +1302,154 @@ impl RatatuiClient {
         self.app.add_message(message);
     }
 
-    pub fn add_message_with_limit(&mut self, message: ChatMessage, max_messages: usize) {
-        self.app.add_message_with_limit(message, max_messages);
+    pub fn add_message_to(&mut self, buffer: &str, message: ChatMessage, max_messages: usize) {
+        self.app.add_message_to(buffer, message, max_messages);
+    }
+
+    /// Add a message that just arrived from the server, with a notification
+    /// when it mentions us or is a direct message in a buffer out of view.
+    pub fn add_incoming(&mut self, buffer: &str, message: ChatMessage, max_messages: usize) {
+        let config = self.notifier.config();
+        // Notices and events never notify, as on IRC
+        let title = if !matches!(message.kind, MessageKind::Normal | MessageKind::Action)
+            || self.app.is_ignored(&message)
+        {
+            None
+        } else if config.mentions && self.app.is_mention(&message) {
+            Some(format!("{} mentioned you in {}", message.from, buffer))
+        } else if config.direct_messages
+            && BufferKind::for_name(buffer) == BufferKind::Query
+            && buffer != self.app.active_buffer_name()
+        {
+            Some(format!("Message from {}", message.from))
+        } else {
+            None
+        };
+        let body = message.body.clone();
+        self.app.add_message_to(buffer, message, max_messages);
+        if let Some(title) = title {
+            match self
+                .notifier
+                .notify(self.terminal.backend_mut(), &title, &body)
+            {
+                Ok(true) => {}
+                Ok(false) => crate::logging::log_debug!("Rate limited notification: {}", title),
+                Err(err) => crate::logging::log_warn!("Failed to send notification: {}", err),
+            }
+        }
+    }
+
+    pub fn set_nickname(&mut self, nickname: &str) {
+        self.app.set_nickname(nickname);
+    }
+
+    pub fn edit_message(&mut self, id: u64, body: &str) {
+        self.app.edit_message(id, body);
+    }
+
+    pub fn delete_message(&mut self, id: u64) {
+        self.app.delete_message(id);
+    }
+
+    pub fn set_reactions(&mut self, id: u64, reactions: &[Reaction]) {
+        self.app.set_reactions(id, reactions);
+    }
+
+    pub fn last_own_message(&self) -> Option<u64> {
+        self.app.last_own_message().and_then(|message| message.id)
+    }
+
+    pub fn active_buffer_name(&self) -> String {
+        self.app.active_buffer_name().to_string()
+    }
+
+    pub fn open_link_picker(&mut self) {
+        self.app.open_link_picker();
+    }
+
+    pub fn open_buffer(&mut self, name: &str) {
+        let index = self.app.open_buffer(name);
+        self.app.switch_buffer(index);
+    }
+
+    pub fn close_buffer(&mut self, name: &str) -> bool {
+        self.app.close_buffer(name)
+    }
+
+    pub fn active_buffer_kind(&self) -> BufferKind {
+        self.app.active_buffer_kind()
+    }
+
+    pub fn start_search(&mut self, query: SearchQuery) -> String {
+        self.app.start_search(query, false);
+        self.app.search_summary().unwrap_or_default()
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.app.buffers.len()
+    }
+
+    pub fn set_presence(&mut self, users: Vec<NickEntry>) {
+        self.app.set_presence(users);
+    }
+
+    pub fn set_topic(&mut self, topic: RoomTopic) {
+        self.app.set_topic(topic);
+    }
+
+    pub fn set_typing(&mut self, nickname: &str, room: &str) {
+        self.app.set_typing(nickname, room, now_millis());
+    }
+
+    pub fn clear_typing(&mut self, nickname: &str, room: Option<&str>) {
+        self.app.clear_typing(nickname, room);
+    }
+
+    /// Drop lapsed typing indicators. Returns true when the screen changed.
+    pub fn expire_typing(&mut self) -> bool {
+        self.app.expire_typing(now_millis())
+    }
+
+    pub fn take_typing(&mut self) -> Option<String> {
+        self.app.take_typing()
+    }
+
+    pub fn check_auto_away(&mut self) -> bool {
+        self.app.check_auto_away(now_millis())
+    }
+
+    pub fn take_away_change(&mut self) -> Option<Option<String>> {
+        self.app.take_away_change()
+    }
+
+    pub fn set_away(&mut self, reason: Option<String>) {
+        self.app.set_away(reason);
+    }
+
+    /// True when a room or query with this name is open.
+    pub fn has_buffer(&self, name: &str) -> bool {
+        self.app.buffers.iter().any(|buffer| buffer.name == name)
+    }
+
+    pub fn ignores(&self) -> &IgnoreList {
+        &self.app.ignores
+    }
+
+    pub fn change_ignores(&mut self, rule: IgnoreRule, add: bool) -> Result<(), String> {
+        self.app.change_ignores(rule, add)
+    }
+
+    pub fn active_hidden(&self) -> usize {
+        self.app.active_hidden()
+    }
+
+    /// The command registry, shared with command tasks.
+    pub fn commands(&self) -> Arc<Registry> {
+        self.app.commands.clone()
+    }
+
+    pub fn nick_list_visible(&self) -> bool {
+        self.app.show_nick_list
     }
 
     pub fn should_quit(&self) -> bool {
@@ -352,67 +1457,282 @@ impl RatatuiClient {
     }
 
     pub fn draw(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let messages = self.app.messages.clone();
+        let messages = self.app.active_messages().to_vec();
+        let buffer_name = self.app.active_buffer_name().to_string();
+        let tab_titles: Vec<Line> = self
+            .app
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| {
+                let label = if buffer.unread > 0 {
+                    format!("{}:{} ({})", i + 1, buffer.name, buffer.unread)
+                } else {
+                    format!("{}:{}", i + 1, buffer.name)
+                };
+                let base = match buffer.kind {
+                    BufferKind::Room => self.theme.tab,
+                    BufferKind::Query => self.theme.tab_query,
+                    BufferKind::Mentions => self.theme.mention,
+                };
+                let style = if buffer.unread > 0 {
+                    base.add_modifier(Modifier::BOLD)
+                } else {
+                    base
+                };
+                Line::from(Span::styled(label, style))
+            })
+            .collect();
+        let active_buffer = self.app.active_buffer;
+        let buffer_kind = self.app.active_buffer_kind();
+        let topic = self.app.buffers[self.app.active_buffer]
+            .topic
+            .as_ref()
+            .map(topic_header);
+        let theme = &self.theme;
+        let layout = self.layout;
+        let sender_style = sender_style(buffer_kind, theme);
+        let mentioned: Vec<bool> = messages
+            .iter()
+            .map(|msg| self.app.is_mention(msg))
+            .collect();
+        let mentions = self.app.mentions.clone();
+        let selected_message = self.app.selected_message;
+        let nickname = self.app.nickname.clone();
+        let thread = self.app.thread_messages();
+        let replying_to = self
+            .app
+            .replying_to
+            .and_then(|id| self.app.find_message(id))
+            .map(|parent| format!("{}: {}", parent.from, preview(&parent.body, 40)));
         let input = self.app.input.clone();
         let status = self.app.status.clone();
         let is_error = self.app.is_error;
+        let typing = self.app.typing_summary(now_millis());
+        let hidden = self.app.active_hidden();
+        let away = self.app.away.as_ref().map(|away| away.reason.clone());
+        // Room tabs only list the users who joined that room
+        let nick_list: Vec<NickEntry> = self
+            .app
+            .nick_list
+            .iter()
+            .filter(|user| {
+                buffer_kind != BufferKind::Room
+                    || user.rooms.is_empty()
+                    || user.rooms.contains(&buffer_name)
+            })
+            .cloned()
+            .collect();
+        let show_raw = self.app.show_raw;
+        let hyperlinks = self.hyperlinks;
+        let known_links = if hyperlinks {
+            links::recent(&messages, usize::MAX)
+        } else {
+            Vec::new()
+        };
+        let search_matches = self
+            .app
+            .search
+            .as_ref()
+            .map(|search| search.matches.clone())
+            .unwrap_or_default();
+        let current_match = self
+            .app
+            .search
+            .as_ref()
+            .and_then(SearchState::current_match);
 
         self.terminal.draw(|f| {
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Min(1),    // Messages area
-                    Constraint::Length(3), // Input area
-                    Constraint::Length(3), // Status bar
-                ])
-                .split(f.size());
+            let bar = Constraint::Length(layout.bar_height());
+            let (tabs_area, main_area, input_area, status_area) = match layout.status_bar {
+                StatusPosition::Bottom => {
+                    let chunks = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([Constraint::Length(1), Constraint::Min(1), bar, bar])
+                        .split(f.size());
+                    (chunks[0], chunks[1], chunks[2], chunks[3])
+                }
+                StatusPosition::Top => {
+                    let chunks = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([bar, Constraint::Length(1), Constraint::Min(1), bar])
+                        .split(f.size());
+                    (chunks[1], chunks[2], chunks[3], chunks[0])
+                }
+            };
+
+            let tabs = Tabs::new(tab_titles)
+                .select(active_buffer)
+                .highlight_style(theme.tab_active);
+            f.render_widget(tabs, tabs_area);
+
+            // Split the messages area to make room for the nick list
+            let (messages_area, nick_area) = if self.app.nick_list_fits(f.size().width) {
+                let columns = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Min(1), Constraint::Length(NICK_LIST_WIDTH)])
+                    .split(main_area);
+                (columns[0], Some(columns[1]))
+            } else {
+                (main_area, None)
+            };
+
+            // The topic sits in a header line above the messages
+            let messages_area = match &topic {
+                Some(topic) if messages_area.height > 4 => {
+                    let rows = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([Constraint::Length(1), Constraint::Min(1)])
+                        .split(messages_area);
+                    let header = Paragraph::new(topic.as_str()).style(theme.quote);
+                    f.render_widget(header, rows[0]);
+                    rows[1]
+                }
+                _ => messages_area,
+            };
+
+            // The thread pane takes the lower part of the messages area
+            let (messages_area, thread_area) = if thread.is_empty() {
+                (messages_area, None)
+            } else {
+                let height = (thread.len() as u16 + 2).min(messages_area.height / 2);
+                let rows = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(3), Constraint::Length(height)])
+                    .split(messages_area);
+                (rows[0], Some(rows[1]))
+            };
 
             // Messages area with scrollbar
-            let message_items: Vec<ListItem> = messages
-                .iter()
-                .flat_map(|msg| {
-                    // Split message body by newlines to handle multi-line messages
-                    let lines: Vec<&str> = msg.body.split('\n').collect();
-                    lines
-                        .into_iter()
-                        .enumerate()
-                        .map(|(i, line)| {
-                            if i == 0 {
-                                // First line includes the sender name
-                                ListItem::new(Line::from(vec![
-                                    Span::styled(
-                                        &msg.from,
-                                        Style::default()
-                                            .fg(Color::Green)
-                                            .add_modifier(Modifier::BOLD),
-                                    ),
-                                    Span::raw(": "),
-                                    Span::raw(line),
-                                ]))
-                            } else {
-                                // Subsequent lines are indented
-                                ListItem::new(Line::from(vec![Span::raw("  "), Span::raw(line)]))
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .collect();
+            let mut message_items: Vec<ListItem> = Vec::new();
+            for (msg, &mentioned) in messages.iter().zip(&mentioned) {
+                let sender_style = if mentioned {
+                    theme.mention
+                } else {
+                    sender_style
+                };
+                let mut rendered = if show_raw {
+                    Vec::new()
+                } else {
+                    markdown::render(&msg.body, theme)
+                }
+                .into_iter();
+                let last_line = msg.body.matches('\n').count();
+                // Split message body by newlines to handle multi-line messages
+                for (i, line) in msg.body.split('\n').enumerate() {
+                    let line_index = message_items.len();
+                    let rendered_line = rendered.next();
+                    let mut spans = if i == 0 {
+                        // First line includes the sender name
+                        let mut spans = message_prefix(msg, sender_style, theme);
+                        // Replies lead with a short quote of their parent
+                        if let Some(parent) = msg.reply_to {
+                            let quote = match messages.iter().find(|m| m.id == Some(parent)) {
+                                Some(parent) => {
+                                    format!("↪ {}: {} │ ", parent.from, preview(&parent.body, 30))
+                                }
+                                None => "↪ earlier message │ ".to_string(),
+                            };
+                            spans.push(Span::styled(quote, theme.quote));
+                        }
+                        spans
+                    } else {
+                        // Subsequent lines are indented
+                        vec![Span::raw("  ")]
+                    };
+                    // Search offsets refer to the raw text, so matched lines stay raw
+                    let mut line_matches = search_matches
+                        .iter()
+                        .filter(|m| m.line == line_index)
+                        .peekable();
+                    if line_matches.peek().is_some() {
+                        spans.extend(highlight_spans(line, line_matches, current_match, theme));
+                    } else {
+                        let rendered =
+                            rendered_line.unwrap_or_else(|| vec![Span::raw(line.to_string())]);
+                        let mut body = links::style_urls(rendered, theme.link);
+                        if mentioned && let Some(mentions) = &mentions {
+                            body = markdown::style_ranges(
+                                body,
+                                |text| mentions.find(text),
+                                theme.mention,
+                            );
+                        }
+                        spans.extend(body);
+                    }
+                    if msg.edited && i == last_line {
+                        spans.push(Span::styled(" (edited)", theme.dim));
+                    }
+                    let mut style = kind_style(msg.kind, theme);
+                    if msg.id.is_some() && msg.id == selected_message {
+                        style = style.add_modifier(Modifier::REVERSED);
+                    }
+                    message_items.push(ListItem::new(Line::from(spans)).style(style));
+                }
+                if let Some(attachment) = &msg.attachment {
+                    let line_index = message_items.len();
+                    let mut spans = vec![Span::styled("  📎 ", theme.dim)];
+                    let mut name_matches = search_matches
+                        .iter()
+                        .filter(|m| m.line == line_index)
+                        .peekable();
+                    if name_matches.peek().is_some() {
+                        spans.extend(highlight_spans(
+                            &attachment.name,
+                            name_matches,
+                            current_match,
+                            theme,
+                        ));
+                    } else {
+                        spans.push(Span::styled(attachment.name.as_str(), theme.link));
+                    }
+                    spans.push(Span::styled(
+                        format!(
+                            " ({}, {}) /download {}",
+                            format_size(attachment.size),
+                            attachment.mime,
+                            attachment.id
+                        ),
+                        theme.dim,
+                    ));
+                    message_items.push(ListItem::new(Line::from(spans)));
+                }
+                if !msg.reactions.is_empty() {
+                    message_items.push(ListItem::new(Line::from(reaction_spans(
+                        &msg.reactions,
+                        &nickname,
+                        theme,
+                    ))));
+                }
+            }
 
             // Update scroll state with current content length
             let content_length = message_items.len();
             self.app.scroll_state = self.app.scroll_state.content_length(content_length);
 
             let messages_list = List::new(message_items)
-                .block(Block::default().borders(Borders::ALL).title("Messages"))
-                .style(Style::default().fg(Color::Cyan));
+                .block(layout.block(buffer_name.as_str()))
+                .style(theme.text);
 
-            f.render_stateful_widget(messages_list, chunks[0], &mut self.app.list_state);
+            f.render_stateful_widget(messages_list, messages_area, &mut self.app.list_state);
+            if hyperlinks {
+                let inner = layout.block(buffer_name.as_str()).inner(messages_area);
+                links::hyperlink_area(f.buffer_mut(), inner, &known_links);
+            }
 
             // Render scrollbar
             let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
                 .begin_symbol(Some("↑"))
                 .end_symbol(Some("↓"));
-            f.render_stateful_widget(scrollbar, chunks[0], &mut self.app.scroll_state);
+            f.render_stateful_widget(scrollbar, messages_area, &mut self.app.scroll_state);
+
+            if let Some(area) = thread_area {
+                render_thread(f, area, &thread, theme, layout);
+            }
+
+            if let Some(area) = nick_area {
+                render_nick_list(f, area, &nick_list, theme, layout);
+            }
 
             // Input area
             let input_text = if let Some(prompt) = self.app.get_password_prompt() {
@@ -420,59 +1740,116 @@ impl RatatuiClient {
                 let password_input = self.app.get_password_input().unwrap_or(&default_input);
                 let hidden_password = "*".repeat(password_input.len());
                 format!("{}: {}", prompt, hidden_password)
+            } else if let Some(search) = &self.app.search {
+                search.query.text.clone()
             } else {
                 input.clone()
             };
 
             let input_title = if self.app.is_password_input_active() {
-                "Password Input"
+                "Password Input".to_string()
+            } else if let Some(search) = &self.app.search {
+                let keys = if search.editing {
+                    "Enter: done"
+                } else {
+                    "n/N: older/newer, Ctrl+F: edit"
+                };
+                format!(
+                    "Search{}{} [{}] - {}, Alt+C: case, Alt+R: regex, Esc: close",
+                    if search.query.regex { " (regex)" } else { "" },
+                    if search.query.case_sensitive {
+                        " (case)"
+                    } else {
+                        ""
+                    },
+                    search.summary(),
+                    keys
+                )
+            } else if selected_message.is_some() {
+                "Select - Up/Down: move, Enter/r: reply, t: thread, 1-4/+: react, Esc: done"
+                    .to_string()
+            } else if let Some(parent) = &replying_to {
+                format!("Reply to {} - Esc: cancel", parent)
+            } else if self.app.mode == InputMode::Normal {
+                "Input [NORMAL] - i: insert, ':': command".to_string()
+            } else if self.app.keymap.vi_mode() {
+                "Input [INSERT] - Esc: normal mode".to_string()
+            } else {
+                "Input".to_string()
+            };
+
+            // Without borders there is no title, so it leads the line instead
+            let input_text = if !layout.compact {
+                input_text
+            } else if input_title == "Input" {
+                format!("> {}", input_text)
             } else {
-                "Input"
+                format!("{}: {}", input_title, input_text)
             };
 
             let input_paragraph = Paragraph::new(input_text.as_str())
-                .block(Block::default().borders(Borders::ALL).title(input_title))
-                .style(Style::default().fg(Color::Yellow))
+                .block(layout.block(input_title))
+                .style(theme.input)
                 .wrap(Wrap { trim: true });
 
-            f.render_widget(input_paragraph, chunks[1]);
+            f.render_widget(input_paragraph, input_area);
 
             // Status bar
-            let status_color = if is_error { Color::Red } else { Color::Blue };
-            let status_paragraph = Paragraph::new(status.as_str())
-                .block(Block::default().borders(Borders::ALL).title("Status"))
-                .style(Style::default().fg(Color::White).bg(status_color))
+            let status_style = if is_error {
+                theme.status_error
+            } else {
+                theme.status
+            };
+            let mut status_spans = vec![Span::raw(status.as_str())];
+            if let Some(typing) = &typing {
+                status_spans.push(Span::raw(" | "));
+                status_spans.push(Span::styled(typing.as_str(), theme.dim));
+            }
+            if let Some(reason) = &away {
+                status_spans.push(Span::raw(" | "));
+                status_spans.push(Span::styled(format!("Away: {}", reason), theme.nick_away));
+            }
+            if hidden > 0 {
+                status_spans.push(Span::raw(" | "));
+                status_spans.push(Span::styled(format!("{} hidden", hidden), theme.dim));
+            }
+            let status_paragraph = Paragraph::new(Line::from(status_spans))
+                .block(layout.block("Status"))
+                .style(status_style)
                 .wrap(Wrap { trim: true });
 
-            f.render_widget(status_paragraph, chunks[2]);
+            f.render_widget(status_paragraph, status_area);
+
+            if let Some(picker) = &self.app.link_picker {
+                render_link_picker(f, main_area, picker, theme, layout);
+            }
         })?;
         Ok(())
     }
 
-    pub fn handle_event(&mut self) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        if event::poll(std::time::Duration::from_millis(100))? {
-            match event::read()? {
-                Event::Key(key) => {
-                    if self.app.handle_input(key) {
-                        if self.app.should_quit {
-                            return Ok(true);
-                        }
-                        return Ok(true); // Input ready
-                    }
+    /// Apply a terminal event. Returns true when an input line (or password)
+    /// is ready or quit was requested.
+    pub fn handle_terminal_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Key(key) => {
+                self.app.note_key(now_millis());
+                let previous = self.app.input.clone();
+                let submitted = self.app.handle_input(key);
+                if !submitted && self.app.input != previous {
+                    self.app.note_input_changed(now_millis());
                 }
-                Event::Mouse(mouse) => match mouse.kind {
-                    MouseEventKind::ScrollUp => {
-                        self.app.scroll_up();
-                    }
-                    MouseEventKind::ScrollDown => {
-                        self.app.scroll_down();
-                    }
+                submitted
+            }
+            Event::Mouse(mouse) => {
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => self.app.scroll_up(),
+                    MouseEventKind::ScrollDown => self.app.scroll_down(),
                     _ => {}
-                },
-                _ => {}
+                }
+                false
             }
+            _ => false,
         }
-        Ok(false)
     }
 
     pub fn get_input(&mut self) -> String {
@@ -488,7 +1865,7 @@ impl RatatuiClient {
     }
 
     pub fn message_count(&self) -> usize {
-        self.app.messages.len()
+        self.app.active_messages().len()
     }
 
     pub fn get_terminal_size(&self) -> (u16, u16) {
@@ -500,43 +1877,242 @@ impl RatatuiClient {
     }
 
     // Password input methods
-    pub fn start_password_input(&mut self, prompt: String, command: String) {
-        self.app.start_password_input(prompt, command);
-    }
-
-    pub fn get_current_password_command(&self) -> Option<&String> {
-        self.app.get_current_password_command()
+    pub fn start_prompt(&mut self, interaction: PendingInteraction) {
+        self.app.start_prompt(interaction);
     }
 
     pub fn is_password_input_active(&self) -> bool {
         self.app.is_password_input_active()
     }
 
-    pub fn get_password_prompt(&self) -> Option<&String> {
-        self.app.get_password_prompt()
-    }
-
     pub fn get_password_input(&self) -> Option<&String> {
         self.app.get_password_input()
     }
 
-    pub fn finish_password_input(&mut self) -> Option<String> {
+    pub fn finish_password_input(&mut self) -> Option<(PendingInteraction, String)> {
         self.app.finish_password_input()
     }
+}
+
+// Split a message line into spans, highlighting search matches within it.
+fn highlight_spans<'a>(
+    line: &'a str,
+    matches: impl Iterator<Item = &'a SearchMatch>,
+    current: Option<SearchMatch>,
+    theme: &Theme,
+) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    let mut cursor = 0;
+    for found in matches {
+        if found.start < cursor || found.end > line.len() {
+            continue;
+        }
+        if found.start > cursor {
+            spans.push(Span::raw(&line[cursor..found.start]));
+        }
+        let style = if current == Some(*found) {
+            theme.search_current
+        } else {
+            theme.search_match
+        };
+        spans.push(Span::styled(&line[found.start..found.end], style));
+        cursor = found.end;
+    }
+    if cursor < line.len() || spans.is_empty() {
+        spans.push(Span::raw(&line[cursor..]));
+    }
+    spans
+}
+
+fn render_nick_list(
+    f: &mut ratatui::Frame,
+    area: Rect,
+    users: &[NickEntry],
+    theme: &Theme,
+    layout: LayoutOptions,
+) {
+    let now = now_millis();
+    let items: Vec<ListItem> = users
+        .iter()
+        .map(|user| ListItem::new(nick_line(user, now, theme)))
+        .collect();
+
+    let title = format!("Users ({})", users.len());
+    let list = List::new(items).block(layout.block(title));
+    f.render_widget(list, area);
+}
+
+// Private conversations use a distinct sender color.
+fn sender_style(kind: BufferKind, theme: &Theme) -> Style {
+    match kind {
+        BufferKind::Room | BufferKind::Mentions => theme.sender,
+        BufferKind::Query => theme.query_sender,
+    }
+}
+
+// One nick list row: the NickServ marker, the nick and how long it has idled.
+fn nick_line(user: &NickEntry, now: u64, theme: &Theme) -> Line<'static> {
+    // '+' marks a nickname identified with NickServ
+    let marker = if user.identified { "+" } else { " " };
+    let style = if user.away {
+        theme.nick_away
+    } else {
+        theme.nick
+    };
+    let mut spans = vec![
+        Span::styled(marker, theme.marker),
+        Span::styled(user.nickname.clone(), style),
+    ];
+    if user.away {
+        spans.push(Span::styled(" (away)", theme.dim));
+    }
+    let idle = format_idle(now, user.last_active);
+    if !idle.is_empty() {
+        spans.push(Span::styled(format!(" {}", idle), theme.dim));
+    }
+    Line::from(spans)
+}
+
+// The open thread: its root message and the replies under it, indented by
+// depth.
+fn render_thread(
+    f: &mut ratatui::Frame,
+    area: Rect,
+    thread: &[(usize, ChatMessage)],
+    theme: &Theme,
+    layout: LayoutOptions,
+) {
+    let items: Vec<ListItem> = thread
+        .iter()
+        .map(|(depth, msg)| {
+            ListItem::new(Line::from(vec![
+                Span::raw("  ".repeat((*depth).min(4))),
+                Span::styled(msg.from.clone(), theme.sender),
+                Span::raw(": "),
+                Span::styled(preview(&msg.body, usize::MAX), theme.text),
+            ]))
+        })
+        .collect();
+    let replies = thread.len() - 1;
+    let title = format!(
+        "Thread ({} {})",
+        replies,
+        if replies == 1 { "reply" } else { "replies" }
+    );
+    f.render_widget(List::new(items).block(layout.block(title)), area);
+}
+
+// How the first line of a message opens: `bob: `, `* bob `, `-bob- ` or
+// a marker for events and errors.
+fn message_prefix<'a>(msg: &'a ChatMessage, sender_style: Style, theme: &Theme) -> Vec<Span<'a>> {
+    match msg.kind {
+        MessageKind::Normal => vec![
+            Span::styled(msg.from.as_str(), sender_style),
+            Span::raw(": "),
+        ],
+        MessageKind::Action => vec![Span::styled(format!("* {} ", msg.from), theme.action)],
+        MessageKind::Notice => vec![Span::styled(format!("-{}- ", msg.from), theme.notice)],
+        MessageKind::System => vec![Span::styled("-!- ", theme.system)],
+        MessageKind::Error => vec![Span::styled("!! ", theme.error)],
+    }
+}
 
-    pub async fn log(
-        &mut self,
-        client: &crate::websocket_client::WebSocketClient,
-        capability: capnweb_core::CapId,
-        message: &str,
-    ) {
-        self.app.log(client, capability, message).await;
+// The base style of every row of a message, which unstyled text inherits.
+fn kind_style(kind: MessageKind, theme: &Theme) -> Style {
+    match kind {
+        MessageKind::Normal => Style::default(),
+        MessageKind::Action => theme.action,
+        MessageKind::Notice => theme.notice,
+        MessageKind::System => theme.system,
+        MessageKind::Error => theme.error,
     }
 }
 
+// "Topic: text (set by alice)" for the header line.
+fn topic_header(topic: &RoomTopic) -> String {
+    let text = topic.topic.as_deref().unwrap_or_default();
+    match &topic.set_by {
+        Some(set_by) => format!("Topic: {} (set by {})", text, set_by),
+        None => format!("Topic: {}", text),
+    }
+}
+
+// The row under a message: each emoji with its count, ours highlighted.
+fn reaction_spans(reactions: &[Reaction], nickname: &str, theme: &Theme) -> Vec<Span<'static>> {
+    let mut spans = vec![Span::raw("  ")];
+    for reaction in reactions {
+        let style = if reaction.users.iter().any(|user| user == nickname) {
+            theme.marker
+        } else {
+            theme.dim
+        };
+        spans.push(Span::styled(
+            format!("[{} {}]", reaction.emoji, reaction.users.len()),
+            style,
+        ));
+        spans.push(Span::raw(" "));
+    }
+    spans
+}
+
+/// The first line of `body`, cut to `max` characters.
+fn preview(body: &str, max: usize) -> String {
+    let line = body.lines().next().unwrap_or_default();
+    if line.chars().count() > max || body.contains('\n') {
+        let mut short: String = line.chars().take(max).collect();
+        short.push('…');
+        short
+    } else {
+        line.to_string()
+    }
+}
+
+fn render_link_picker(
+    f: &mut ratatui::Frame,
+    area: Rect,
+    picker: &LinkPicker,
+    theme: &Theme,
+    layout: LayoutOptions,
+) {
+    let width = picker
+        .links
+        .iter()
+        .map(|link| link.chars().count() as u16 + 8)
+        .max()
+        .unwrap_or(0)
+        .max(30)
+        .min(area.width);
+    let height = (picker.links.len() as u16 + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let items: Vec<ListItem> = picker
+        .links
+        .iter()
+        .enumerate()
+        .map(|(i, link)| {
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{:>2} ", i + 1), theme.dim),
+                Span::styled(link.clone(), theme.link),
+            ]))
+        })
+        .collect();
+    let title = Line::from("Links - Enter: open, Esc: close").alignment(Alignment::Center);
+    let list = List::new(items)
+        .block(layout.block(title).style(theme.text))
+        .highlight_style(theme.tab_active);
+    let mut state = ListState::default().with_selected(Some(picker.selected));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(list, popup, &mut state);
+}
+
 impl Drop for RatatuiClient {
     fn drop(&mut self) {
         // Restore terminal
+        crate::logging::set_tui_active(false);
         let _ = disable_raw_mode();
         let _ = execute!(
             self.terminal.backend_mut(),
@@ -548,7 +2124,486 @@ impl Drop for RatatuiClient {
 
 #[derive(Clone)]
 pub struct Session {
-    pub username: String,
     pub nickname: String,
     pub capability: CapId,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, body: &str) -> ChatMessage {
+        ChatMessage {
+            from: from.to_string(),
+            body: body.to_string(),
+            timestamp: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn background_buffers_count_unread() {
+        let mut app = ChatApp::new();
+        app.add_message_to("#ops", message("alice", "deploying"), 100);
+        app.add_message_to("#ops", message("alice", "done"), 100);
+
+        assert_eq!(app.active_buffer_name(), "#general");
+        assert_eq!(app.buffers[1].unread, 2);
+
+        app.switch_buffer(1);
+        assert_eq!(app.active_buffer_name(), "#ops");
+        assert_eq!(app.buffers[1].unread, 0);
+        assert_eq!(app.active_messages().len(), 2);
+    }
+
+    #[test]
+    fn nick_list_marks_identified_away_and_idle_users() {
+        let theme = Theme::dark();
+        let user = |nickname: &str, identified, away, last_active| NickEntry {
+            nickname: nickname.to_string(),
+            identified,
+            away,
+            last_active,
+            rooms: Vec::new(),
+        };
+        let row = |entry: &NickEntry, now| -> String {
+            nick_line(entry, now, &theme)
+                .spans
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect()
+        };
+        let alice = user("alice", true, false, 0);
+        assert_eq!(row(&alice, 59_000), "+alice");
+        assert_eq!(row(&alice, 5 * 60_000), "+alice 5m");
+        assert_eq!(row(&alice, 3 * 3_600_000), "+alice 3h");
+        assert_eq!(row(&alice, 2 * 86_400_000), "+alice 2d");
+        let bob = user("bob", false, true, 0);
+        assert_eq!(row(&bob, 0), " bob (away)");
+        assert_eq!(nick_line(&bob, 0, &theme).spans[1].style, theme.nick_away);
+
+        let mut app = ChatApp::new();
+        app.set_presence(vec![alice, bob]);
+        assert_eq!(app.nick_list.len(), 2);
+        assert!(app.nick_list_fits(NICK_LIST_MIN_WIDTH));
+        assert!(!app.nick_list_fits(NICK_LIST_MIN_WIDTH - 1));
+        app.toggle_nick_list();
+        assert!(!app.nick_list_fits(200));
+    }
+
+    #[test]
+    fn direct_messages_use_query_buffers() {
+        let theme = Theme::dark();
+        let mut app = ChatApp::new();
+        app.set_nickname("bob");
+
+        // /query opens a tab for the nick, and again reuses it
+        let alice = app.open_buffer("alice");
+        app.switch_buffer(alice);
+        assert_eq!(app.active_buffer_kind(), BufferKind::Query);
+        assert_eq!(app.open_buffer("alice"), alice);
+        assert_eq!(app.buffers.len(), 2);
+
+        // Incoming messages go to the sender's tab, opening it if needed
+        app.add_message_to("alice", message("alice", "psst"), 100);
+        app.add_message_to("carol", message("carol", "hi bob"), 100);
+        assert_eq!(app.active_messages().len(), 1);
+        let carol = &app.buffers[2];
+        assert_eq!((carol.kind, carol.unread), (BufferKind::Query, 1));
+
+        // Our own sends are echoed into the query and styled as private
+        app.add_message_to("alice", message("bob", "hello"), 100);
+        let sent = app.active_messages().last().unwrap();
+        assert_eq!(sent.from, "bob");
+        let style = sender_style(app.active_buffer_kind(), &theme);
+        assert_eq!(style, theme.query_sender);
+        assert_eq!(
+            message_prefix(sent, style, &theme)[0].style,
+            theme.query_sender
+        );
+        assert_eq!(sender_style(BufferKind::Room, &theme), theme.sender);
+    }
+
+    #[test]
+    fn mentions_are_copied_to_the_mentions_buffer() {
+        let mut app = ChatApp::new();
+        app.mentions = Some(MentionMatcher::new("", &[]));
+        app.set_nickname("bob");
+        app.add_message_to("#ops", message("alice", "bob: ready?"), 100);
+        app.add_message_to("#ops", message("bob", "bob is here"), 100);
+        app.add_message_to("#ops", message("alice", "bobcat"), 100);
+
+        let mentions = &app.buffers[2];
+        assert_eq!(mentions.kind, BufferKind::Mentions);
+        assert_eq!(mentions.messages.len(), 1);
+        assert_eq!(mentions.messages[0].from, "alice [#ops]");
+        assert_eq!(mentions.unread, 1);
+    }
+
+    #[test]
+    fn idling_goes_away_until_the_next_key() {
+        let mut app = ChatApp::new();
+        app.away_config.auto_after_mins = 10;
+        app.note_key(0);
+        assert!(!app.check_auto_away(9 * 60_000));
+        assert!(app.check_auto_away(10 * 60_000));
+        assert_eq!(app.take_away_change(), Some(Some("Idle".to_string())));
+        assert!(!app.check_auto_away(20 * 60_000));
+
+        app.note_key(21 * 60_000);
+        assert_eq!(app.away, None);
+        assert_eq!(app.take_away_change(), Some(None));
+
+        // Only /back ends an away set by hand
+        app.set_away(Some("lunch".to_string()));
+        app.note_key(22 * 60_000);
+        assert_eq!(
+            app.away.as_ref().map(|away| away.reason.as_str()),
+            Some("lunch")
+        );
+        assert_eq!(app.take_away_change(), None);
+        assert!(!app.check_auto_away(60 * 60_000));
+    }
+
+    #[test]
+    fn ignored_messages_are_counted_not_shown() {
+        use crate::ignore::IgnoreRule;
+
+        let mut app = ChatApp::new();
+        app.set_nickname("bob");
+        app.ignores.add(IgnoreRule::parse("spambot").unwrap());
+        app.ignores.add(IgnoreRule::parse("body:^!").unwrap());
+        app.add_message(message("SpamBot", "buy now"));
+        app.add_message(message("alice", "!roll"));
+        app.add_message(message("bob", "!roll"));
+        app.add_message(ChatMessage {
+            kind: MessageKind::System,
+            ..message("System", "!! reconnecting")
+        });
+        app.add_message_to("spambot", message("spambot", "psst"), 100);
+
+        let bodies: Vec<&str> = app
+            .active_messages()
+            .iter()
+            .map(|m| m.body.as_str())
+            .collect();
+        assert_eq!(bodies, vec!["!roll", "!! reconnecting"]);
+        assert_eq!(app.active_hidden(), 2);
+        assert_eq!(app.buffers.len(), 1);
+    }
+
+    #[test]
+    fn ignore_changes_apply_to_the_live_list() {
+        let path = std::env::temp_dir()
+            .join(format!("capinrs-test-{}-live-ignore", std::process::id()))
+            .join("config.toml");
+        let rule = |spec| IgnoreRule::parse(spec).unwrap();
+        let mut app = ChatApp::new();
+        app.config_path = Some(path.clone());
+
+        // Two /ignore commands submitted together both stick
+        assert!(app.change_ignores(rule("spambot"), true).is_ok());
+        assert!(app.change_ignores(rule("from:^bot-"), true).is_ok());
+        assert!(app.change_ignores(rule("SpamBot"), true).is_ok());
+        assert!(app.change_ignores(rule("carol"), false).is_ok());
+        let saved = crate::config::Config::parse(&std::fs::read_to_string(&path).unwrap())
+            .unwrap()
+            .resolve(None)
+            .unwrap()
+            .ignores;
+        assert_eq!(saved.to_config(), app.ignores.to_config());
+        assert_eq!(app.ignores.rules().len(), 2);
+
+        let bodies: Vec<&str> = app
+            .active_messages()
+            .iter()
+            .map(|m| m.body.as_str())
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                "Now ignoring spambot.",
+                "Now ignoring from:^bot-.",
+                "Already ignoring SpamBot.",
+                "carol is not on the ignore list. Type /ignores to see it.",
+            ]
+        );
+
+        app.config_path = None;
+        assert!(app.change_ignores(rule("spambot"), false).is_err());
+        assert_eq!(app.ignores.rules().len(), 1);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn edits_and_deletions_follow_message_ids() {
+        let mut app = ChatApp::new();
+        app.set_nickname("bob");
+        let own = |id, body: &str| ChatMessage {
+            id: Some(id),
+            ..message("bob", body)
+        };
+        app.add_message(own(1, "helo"));
+        app.add_message(own(2, "second"));
+        app.add_message(message("alice", "hi"));
+
+        assert!(!app.run_action(Action::RecallOrScrollUp));
+        assert_eq!(app.input, "/edit 2 second");
+
+        app.edit_message(1, "hello");
+        assert_eq!(app.active_messages()[0].body, "hello");
+        assert!(app.active_messages()[0].edited);
+        app.delete_message(2);
+        assert_eq!(app.active_messages().len(), 2);
+        assert_eq!(app.last_own_message().and_then(|m| m.id), Some(1));
+    }
+
+    #[test]
+    fn selected_messages_can_be_replied_to_and_threaded() {
+        let mut app = ChatApp::new();
+        let numbered = |id, reply_to, body: &str| ChatMessage {
+            id: Some(id),
+            reply_to,
+            ..message("alice", body)
+        };
+        app.add_message(numbered(1, None, "lunch?"));
+        app.add_message(numbered(2, None, "unrelated"));
+        app.add_message(numbered(3, Some(1), "pizza"));
+        app.add_message(numbered(4, Some(3), "again?"));
+        app.add_message(numbered(5, Some(1), "sushi"));
+
+        app.run_action(Action::SelectMessage);
+        assert_eq!(app.selected_message, Some(5));
+        for _ in 0..2 {
+            app.handle_input(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        }
+        assert_eq!(app.selected_message, Some(3));
+
+        app.handle_input(KeyEvent::new(KeyCode::Char('t'), KeyModifiers::NONE));
+        let thread: Vec<(usize, u64)> = app
+            .thread_messages()
+            .into_iter()
+            .map(|(depth, msg)| (depth, msg.id.unwrap()))
+            .collect();
+        assert_eq!(thread, vec![(0, 1), (1, 3), (2, 4), (1, 5)]);
+
+        app.handle_input(KeyEvent::new(KeyCode::Char('r'), KeyModifiers::NONE));
+        assert_eq!(app.selected_message, None);
+        app.input = "me too".to_string();
+        assert_eq!(app.get_input(), "/reply 3 me too");
+        assert_eq!(app.replying_to, None);
+    }
+
+    #[test]
+    fn quick_reactions_toggle_without_losing_the_draft() {
+        let mut app = ChatApp::new();
+        app.set_nickname("bob");
+        app.add_message(ChatMessage {
+            id: Some(7),
+            ..message("alice", "shipped")
+        });
+        app.add_message(message("carol", "after"));
+        assert_eq!(app.get_total_message_lines(), 2);
+
+        app.input = "half typed".to_string();
+        app.run_action(Action::SelectMessage);
+        assert!(app.handle_input(KeyEvent::new(KeyCode::Char('1'), KeyModifiers::NONE)));
+        assert_eq!(app.get_input(), "/react 7 👍");
+        assert_eq!(app.input, "half typed");
+
+        app.set_reactions(
+            7,
+            &[Reaction {
+                emoji: "👍".to_string(),
+                users: vec!["bob".to_string()],
+            }],
+        );
+        assert_eq!(app.get_total_message_lines(), 3);
+        assert!(app.handle_input(KeyEvent::new(KeyCode::Char('1'), KeyModifiers::NONE)));
+        assert_eq!(app.get_input(), "/unreact 7 👍");
+    }
+
+    #[test]
+    fn typing_indicators_lapse_and_notices_are_debounced() {
+        let mut app = ChatApp::new();
+        app.set_nickname("bob");
+        app.set_typing("bob", "#general", 0);
+        app.set_typing("alice", "#general", 0);
+        app.set_typing("carol", "#ops", 0);
+        assert_eq!(
+            app.typing_summary(1_000).as_deref(),
+            Some("alice is typing…")
+        );
+        app.set_typing("dave", "#general", 2_000);
+        assert_eq!(
+            app.typing_summary(3_000).as_deref(),
+            Some("alice and dave are typing…")
+        );
+        assert!(app.expire_typing(7_000));
+        assert_eq!(
+            app.typing_summary(7_000).as_deref(),
+            Some("dave is typing…")
+        );
+        app.add_message(message("dave", "done"));
+        assert_eq!(app.typing_summary(7_000), None);
+
+        app.input = "h".to_string();
+        app.note_input_changed(10_000);
+        assert_eq!(app.take_typing().as_deref(), Some("#general"));
+        app.input = "hi".to_string();
+        app.note_input_changed(11_000);
+        assert_eq!(app.take_typing(), None);
+        app.note_input_changed(13_000);
+        assert_eq!(app.take_typing().as_deref(), Some("#general"));
+        app.input = "/join #ops".to_string();
+        app.note_input_changed(20_000);
+        assert_eq!(app.take_typing(), None);
+    }
+
+    #[test]
+    fn message_kinds_render_distinct_prefixes() {
+        let theme = Theme::dark();
+        let wire: crate::websocket_client::ChatMessage = serde_json::from_value(
+            serde_json::json!({"from": "bob", "body": "waves", "timestamp": 1, "kind": "action"}),
+        )
+        .unwrap();
+        let action = ChatMessage::from(wire);
+        assert_eq!(action.kind, MessageKind::Action);
+
+        let prefix = |msg: &ChatMessage| -> String {
+            message_prefix(msg, theme.sender, &theme)
+                .iter()
+                .map(|span| span.content.as_ref())
+                .collect()
+        };
+        assert_eq!(prefix(&action), "* bob ");
+        let notice = ChatMessage {
+            kind: MessageKind::Notice,
+            ..message("bob", "deploy at 5")
+        };
+        assert_eq!(prefix(&notice), "-bob- ");
+        let system = ChatMessage {
+            kind: MessageKind::System,
+            ..message("System", "alice joined #ops")
+        };
+        assert_eq!(prefix(&system), "-!- ");
+        assert_eq!(prefix(&message("bob", "hi")), "bob: ");
+        assert_eq!(kind_style(MessageKind::Error, &theme), theme.error);
+    }
+
+    #[test]
+    fn topics_follow_open_rooms() {
+        let mut app = ChatApp::new();
+        let topic = |room: &str, text: Option<&str>| RoomTopic {
+            room: room.to_string(),
+            topic: text.map(str::to_string),
+            set_by: Some("alice".to_string()),
+            set_at: Some(1),
+        };
+        app.set_topic(topic("#ops", Some("pager duty")));
+        assert!(app.buffers.iter().all(|buffer| buffer.name != "#ops"));
+
+        app.set_topic(topic("#general", Some("be nice")));
+        let header = app.buffers[0].topic.as_ref().map(topic_header);
+        assert_eq!(header.as_deref(), Some("Topic: be nice (set by alice)"));
+        app.set_topic(topic("#general", None));
+        assert!(app.buffers[0].topic.is_none());
+    }
+
+    #[test]
+    fn last_buffer_cannot_be_closed() {
+        let mut app = ChatApp::new();
+        assert!(!app.close_buffer("#general"));
+
+        app.open_buffer("#ops");
+        app.switch_buffer(1);
+        assert!(app.close_buffer("#ops"));
+        assert_eq!(app.active_buffer_name(), "#general");
+    }
+
+    #[test]
+    fn incremental_search_selects_matching_line() {
+        let mut app = ChatApp::new();
+        app.add_message(message("alice", "Deploy started"));
+        app.add_message(message("bob", "lunch?\ndeploy done"));
+        app.add_message(message("carol", "nothing here"));
+
+        app.handle_input(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::CONTROL));
+        for c in "deploy".chars() {
+            app.handle_input(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(app.search_summary().as_deref(), Some("2/2"));
+        assert_eq!(app.list_state.selected(), Some(2));
+
+        app.handle_input(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        app.handle_input(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE));
+        assert_eq!(app.list_state.selected(), Some(0));
+
+        app.handle_input(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(app.search.is_none());
+        assert!(app.input.is_empty());
+    }
+
+    #[test]
+    fn attachments_take_a_searchable_row() {
+        let mut app = ChatApp::new();
+        app.add_message(ChatMessage {
+            attachment: Some(Attachment {
+                id: 7,
+                name: "deploy.log".to_string(),
+                size: 2048,
+                mime: "text/plain".to_string(),
+            }),
+            ..message("alice", "logs attached")
+        });
+        app.add_message(message("bob", "thanks"));
+        assert_eq!(app.active_messages()[0].line_count(), 2);
+
+        app.handle_input(KeyEvent::new(KeyCode::Char('f'), KeyModifiers::CONTROL));
+        for c in "deploy".chars() {
+            app.handle_input(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(app.search_summary().as_deref(), Some("1/1"));
+        assert_eq!(app.list_state.selected(), Some(1));
+    }
+
+    #[test]
+    fn tab_prefers_recent_speakers() {
+        let mut app = ChatApp::new();
+        app.add_message(message("alfred", "hi"));
+        app.add_message(message("alice", "hello"));
+
+        app.input = "al".to_string();
+        app.handle_input(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(app.input, "alice: ");
+        app.handle_input(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(app.input, "alfred: ");
+
+        // Typing ends the cycle and keeps the completed text
+        app.handle_input(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+        assert!(app.completion.is_none());
+        assert_eq!(app.input, "alfred: x");
+    }
+
+    #[test]
+    fn vi_mode_keys_move_instead_of_typing() {
+        let config = toml::from_str("vi_mode = true").unwrap();
+        let mut app = ChatApp::new();
+        app.keymap = Keymap::from_config(&config).unwrap();
+        let press = |app: &mut ChatApp, code| app.handle_input(KeyEvent::from(code));
+
+        press(&mut app, KeyCode::Char('h'));
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(app.mode, InputMode::Normal);
+        press(&mut app, KeyCode::Char('j'));
+        assert_eq!(app.input, "h");
+
+        press(&mut app, KeyCode::Char(':'));
+        assert_eq!(app.mode, InputMode::Insert);
+        assert_eq!(app.input, "h");
+        app.input.clear();
+        press(&mut app, KeyCode::Esc);
+        press(&mut app, KeyCode::Char(':'));
+        assert_eq!(app.input, "/");
+    }
+}
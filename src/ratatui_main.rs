@@ -1,46 +1,125 @@
+use crossterm::event::EventStream;
+use futures_util::StreamExt;
+use logging::Level;
 use rand::Rng;
 use std::error::Error;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+mod attachments;
+mod commands;
+mod completion;
+mod config;
+mod ignore;
+mod keymap;
+mod links;
+mod logging;
+mod markdown;
+mod notify;
 mod ratatui_client;
+mod search;
+mod theme;
+mod token_store;
+mod ui_events;
 mod websocket_client;
 
-use ratatui_client::{ChatMessage, RatatuiClient, Session};
-use websocket_client::WebSocketClient;
+use capnweb_core::CapId;
+use commands::{CommandContext, PendingInteraction, Registry, STATUS_HELP, format_status};
+use config::{Config, Settings};
+use ignore::IgnoreRule;
+use ratatui_client::{ChatMessage, RatatuiClient, Session, now_millis};
+use token_store::TokenStore;
+use ui_events::{CommandRequest, UiHandle, UiSnapshot, UiUpdate};
+use websocket_client::{DEFAULT_ROOM, MessageKind, ServerEvent, WebSocketClient};
 
 fn usage() {
     println!(
         "Usage: {} [OPTIONS]
 
 Options:
+  --config <PATH>   Read settings from PATH instead of the default config file
+  --profile <NAME>  Use the named server profile from the config file
   --url <URL>       Override the Cap'n Web endpoint
   --user <NICK>     Use a specific nickname instead of random generation
-  --password <PWD>  Nickname password for NickServ (required when using --user)
+  --password <PWD>  Nickname password for NickServ; only needed with --user
+                    until a login token has been saved
+  --log-level <LVL> Log verbosity: error, warn, info, debug or trace (default: info)
+  --log-file <PATH> Append log lines to PATH
+  --log-remote      Also send log lines to the server's log RPC
   -h, --help        Show this message
 
+Login tokens:
+  After a successful identify a token is saved to
+  $XDG_DATA_HOME/capinrs/tokens.json (default ~/.local/share/capinrs) and
+  used on later runs instead of the password.
+
+Configuration:
+  Settings are read from $XDG_CONFIG_HOME/capinrs/config.toml (default
+  ~/.config/capinrs/config.toml). The file can set nick, theme, [log],
+  [notifications], [keybindings] and named [profiles.<name>] with their own
+  url and nick; default_profile picks one when --profile is not given.
+  Themes: dark (default), light, high-contrast, or your own [themes.<name>]
+  with a base theme and per-slot colors. [layout] sets status_bar = \"top\"
+  or \"bottom\", borders = plain|rounded|double|thick and compact = true.
+  NO_COLOR disables colors. [keybindings] maps actions such as search,
+  scroll-up, history-previous or buffer-3 to keys like \"ctrl+f\" (or a list,
+  or \"none\"); vi_mode = true adds a vi-style normal mode on Esc, with its
+  own [keybindings.normal] table. markdown = false shows messages as raw
+  text; F3 (toggle-raw) switches at any time. hyperlinks = auto|always|never
+  controls clickable OSC 8 links; mouse = false leaves text selection to the
+  terminal. F4 or /links lists recent links to open. Messages that say your
+  nick or one of [notifications] keywords are highlighted and copied to the
+  *mentions* buffer; terminal = \"osc9\"|\"osc777\" and desktop = true
+  (notify-send) add notifications, at most one per min_interval_secs.
+  CAPINRS_SERVER_HOST overrides the file, and flags override both.
+
 Environment:
-  CAPINRS_SERVER_HOST   Override the default backend (wss://capinrs-server.veronika-m-winters.workers.dev)
+  CAPINRS_SERVER_HOST   Override the default backend ({})
 
 After launch you'll connect with your nickname and can start chatting!
-Commands: /help, /whoami, /receive, /nickserv, /quit",
-        std::env::args().next().unwrap_or("ratatui-client".to_string())
+Commands: /help, /join, /part, /list, /topic, /kick, /ban, /mute, /op, /ignore, /away, /upload, /msg, /query, /me, /notice, /reply, /edit, /delete, /react, /unreact, /whoami, /receive, /nickserv, /quit",
+        std::env::args()
+            .next()
+            .unwrap_or("ratatui-client".to_string()),
+        config::DEFAULT_SERVER_URL
     );
 }
 
 fn parse_cli() -> Result<CliOptions, Box<dyn Error + Send + Sync>> {
     let args: Vec<String> = std::env::args().collect();
-    let mut url = std::env::var("CAPINRS_SERVER_HOST")
-        .unwrap_or_else(|_| "wss://capinrs-server.veronika-m-winters.workers.dev".to_string());
+    let mut config: Option<PathBuf> = None;
+    let mut profile: Option<String> = None;
+    let mut url: Option<String> = None;
     let mut user: Option<String> = None;
     let mut password: Option<String> = None;
+    let mut log_level: Option<Level> = None;
+    let mut log_file: Option<PathBuf> = None;
+    let mut log_remote = false;
 
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
+            "--config" => {
+                if i + 1 < args.len() {
+                    config = Some(PathBuf::from(&args[i + 1]));
+                    i += 2;
+                } else {
+                    return Err("--config requires a value".into());
+                }
+            }
+            "--profile" => {
+                if i + 1 < args.len() {
+                    profile = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    return Err("--profile requires a value".into());
+                }
+            }
             "--url" => {
                 if i + 1 < args.len() {
-                    url = args[i + 1].clone();
+                    url = Some(args[i + 1].clone());
                     i += 2;
                 } else {
                     return Err("--url requires a value".into());
@@ -62,6 +141,26 @@ fn parse_cli() -> Result<CliOptions, Box<dyn Error + Send + Sync>> {
                     return Err("--password requires a value".into());
                 }
             }
+            "--log-level" => {
+                if i + 1 < args.len() {
+                    log_level = Some(args[i + 1].parse()?);
+                    i += 2;
+                } else {
+                    return Err("--log-level requires a value".into());
+                }
+            }
+            "--log-file" => {
+                if i + 1 < args.len() {
+                    log_file = Some(PathBuf::from(&args[i + 1]));
+                    i += 2;
+                } else {
+                    return Err("--log-file requires a value".into());
+                }
+            }
+            "--log-remote" => {
+                log_remote = true;
+                i += 1;
+            }
             "-h" | "--help" => {
                 usage();
                 std::process::exit(0);
@@ -73,16 +172,48 @@ fn parse_cli() -> Result<CliOptions, Box<dyn Error + Send + Sync>> {
     }
 
     Ok(CliOptions {
+        config,
+        profile,
         url,
         user,
         password,
+        log_level,
+        log_file,
+        log_remote,
     })
 }
 
 struct CliOptions {
-    url: String,
+    config: Option<PathBuf>,
+    profile: Option<String>,
+    url: Option<String>,
     user: Option<String>,
     password: Option<String>,
+    log_level: Option<Level>,
+    log_file: Option<PathBuf>,
+    log_remote: bool,
+}
+
+/// Resolve the config file and let command-line flags override it.
+fn load_settings(options: CliOptions) -> Result<Settings, String> {
+    let config = Config::load(options.config.as_deref())?;
+    let mut settings = config.resolve(options.profile.as_deref())?;
+    settings.config_path = options.config.or_else(Config::default_path);
+    if let Some(url) = options.url {
+        settings.url = url;
+    }
+    if options.user.is_some() {
+        settings.nick = options.user;
+    }
+    settings.password = options.password;
+    if let Some(level) = options.log_level {
+        settings.log_level = level;
+    }
+    if options.log_file.is_some() {
+        settings.log_file = options.log_file;
+    }
+    settings.log_remote |= options.log_remote;
+    Ok(settings)
 }
 
 fn generate_random_nickname() -> String {
@@ -99,16 +230,7 @@ fn generate_random_nickname() -> String {
     format!("{}{}{}", adj, noun, num)
 }
 
-const STATUS_HELP: &str = "Type /help for commands | Press Ctrl+C to quit";
-
-fn format_status(nickname: &str, server_url: &str, detail: impl AsRef<str>) -> String {
-    let detail = detail.as_ref();
-    if detail.is_empty() {
-        format!("Server: {} | Nick: {}", server_url, nickname)
-    } else {
-        format!("Server: {} | Nick: {} | {}", server_url, nickname, detail)
-    }
-}
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -120,18 +242,40 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             std::process::exit(1);
         }
     };
+    let settings = match load_settings(options) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    if options.user.is_some() && options.password.is_none() {
-        eprintln!("Error: --user requires --password to be provided.");
-        usage();
+    if let Err(err) = logging::init(
+        settings.log_level,
+        settings.log_file.as_deref(),
+        settings.log_remote,
+    ) {
+        eprintln!("Error: cannot open log file: {}", err);
         std::process::exit(1);
     }
+    if let Some(password) = &settings.password {
+        logging::register_secret(password);
+    }
 
-    let url = options.url.clone();
+    let url = settings.url.clone();
+    if let Some(profile) = &settings.profile {
+        logging::log_info!("Using profile '{}'", profile);
+    }
+    logging::log_debug!(
+        "Config: theme={}, vi_mode={}, notifications={:?}",
+        settings.theme.name,
+        settings.keymap.vi_mode(),
+        settings.notifications
+    );
 
     // Use provided nickname or generate a random one for authentication
-    let username = options
-        .user
+    let mut username = settings
+        .nick
         .clone()
         .unwrap_or_else(generate_random_nickname);
 
@@ -139,22 +283,50 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .await
         .map_err(|e| format!("Failed to connect to WebSocket: {}", e))?;
 
-    let capability = match client.authenticate(&username, "").await {
-        Ok(cap) => cap,
-        Err(err) => {
-            eprintln!("Authentication failed: {}", err);
-            std::process::exit(1);
+    // A saved token logs straight in as the identified nickname
+    let mut identified = false;
+    let mut capability = None;
+    if let Some(nick) = &settings.nick
+        && let Some(mut store) = TokenStore::open_default()
+        && let Some(token) = store.get(&url, nick).map(str::to_string)
+    {
+        logging::register_secret(&token);
+        match client.redeem_nick_token(&token).await {
+            Ok((cap, nickname)) => {
+                logging::log_info!("Logged in as '{}' with a saved token", nickname);
+                username = nickname;
+                identified = true;
+                capability = Some(cap);
+            }
+            Err(err) => {
+                logging::log_warn!("Saved token for '{}' was rejected: {}", nick, err);
+                store.remove(&url, nick);
+                if let Err(err) = store.save() {
+                    logging::log_warn!("Failed to update token store: {}", err);
+                }
+            }
         }
+    }
+
+    let capability = match capability {
+        Some(cap) => cap,
+        None => match client.authenticate(&username, "").await {
+            Ok(cap) => cap,
+            Err(err) => {
+                eprintln!("Authentication failed: {}", err);
+                std::process::exit(1);
+            }
+        },
     };
 
     let mut session = Session {
-        username: username.clone(),
         nickname: username,
         capability,
     };
 
     // Create UI
-    let mut ui = RatatuiClient::new()?;
+    let mut ui = RatatuiClient::new(&settings)?;
+    ui.set_nickname(&session.nickname);
 
     // Set initial status
     ui.set_status(
@@ -162,26 +334,23 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         false,
     );
 
-    // Test log RPC call
-    ui.log(&client, session.capability, "Client connected successfully")
-        .await;
+    logging::log_info!("Connected to {} as '{}'", url, session.nickname);
 
     // Load existing messages (calculate how many fit in terminal)
-    match client.receive_messages(session.capability).await {
+    match client
+        .receive_room_messages(session.capability, DEFAULT_ROOM)
+        .await
+    {
         Ok(messages) => {
             let total_messages = messages.len();
 
             // Calculate how many messages can fit in the terminal
-            // Terminal height - 3 (input) - 3 (status) - 2 (borders) = available height
+            // Terminal height - 1 (tabs) - 3 (input) - 3 (status) - 2 (borders) = available height
             let terminal_height = ui.get_terminal_size().1 as usize;
-            let available_height = terminal_height.saturating_sub(8); // Reserve space for UI elements
+            let available_height = terminal_height.saturating_sub(9); // Reserve space for UI elements
             let messages_to_show = available_height.max(5).min(total_messages); // At least 5, at most all messages
 
-            let start_index = if total_messages > messages_to_show {
-                total_messages - messages_to_show
-            } else {
-                0
-            };
+            let start_index = total_messages.saturating_sub(messages_to_show);
 
             for msg in messages.iter().skip(start_index) {
                 ui.add_message(msg.clone().into());
@@ -213,8 +382,17 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         }
     }
 
-    // If the user supplied a nickname and password, attempt automatic NickServ identify.
-    if let (Some(nick), Some(nick_pwd)) = (&options.user, &options.password) {
+    if let Ok(topic) = client.get_topic(session.capability, DEFAULT_ROOM).await {
+        ui.set_topic(topic);
+    }
+
+    // Seed the nick list; later changes arrive as presence events
+    if let Ok(users) = client.list_users(session.capability).await {
+        ui.set_presence(users.into_iter().map(Into::into).collect());
+    }
+
+    // Without a usable token, identify with --password or prompt for it.
+    if !identified && let Some(nick) = &settings.nick {
         match client.check_nickname(session.capability, nick).await {
             Ok(true) => {}
             Ok(false) => {
@@ -223,11 +401,13 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                 ui.set_status(format_status(&session.nickname, url.as_str(), detail), true);
                 ui.add_message(ChatMessage {
                     from: "System".to_string(),
+                    kind: MessageKind::Error,
                     body: format!("NickServ identify aborted: {}", message),
                     timestamp: SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .unwrap()
                         .as_millis() as u64,
+                    ..Default::default()
                 });
                 return Err(message.into());
             }
@@ -237,751 +417,419 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                 ui.set_status(format_status(&session.nickname, url.as_str(), detail), true);
                 ui.add_message(ChatMessage {
                     from: "System".to_string(),
+                    kind: MessageKind::Error,
                     body: format!("NickServ identify failed: {}", err),
                     timestamp: SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .unwrap()
                         .as_millis() as u64,
+                    ..Default::default()
                 });
                 return Err(message.into());
             }
         }
-        ui.log(
-            &client,
-            session.capability,
-            &format!("Auto-identifying nickname '{}' via CLI credentials", nick),
-        )
-        .await;
-        match client
-            .identify_nickname(session.capability, nick, nick_pwd)
-            .await
-        {
-            Ok(message) => {
-                let old_nickname = session.nickname.clone();
-                session.nickname = nick.to_string();
-                ui.set_status(
-                    format_status(
-                        &session.nickname,
-                        url.as_str(),
-                        format!("{} | {}", message, STATUS_HELP),
-                    ),
-                    false,
-                );
+        match &settings.password {
+            None => {
+                ui.start_prompt(PendingInteraction::IdentifyPassword { nick: nick.clone() });
                 ui.add_message(ChatMessage {
                     from: "System".to_string(),
-                    body: format!("{}", message),
-                    timestamp: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                });
-                ui.log(
-                    &client,
-                    session.capability,
-                    &format!(
-                        "Auto NickServ identify succeeded; nickname changed from '{}' to '{}'",
-                        old_nickname, session.nickname
-                    ),
-                )
-                .await;
-            }
-            Err(err) => {
-                ui.set_status(
-                    format_status(
-                        &session.nickname,
-                        url.as_str(),
-                        format!("NickServ identify failed: {} | {}", err, STATUS_HELP),
+                    kind: MessageKind::System,
+                    body: format!(
+                        "No saved login for '{}'. Enter its password below to identify.",
+                        nick
                     ),
-                    true,
-                );
-                ui.add_message(ChatMessage {
-                    from: "System".to_string(),
-                    body: format!("NickServ identify failed: {}", err),
                     timestamp: SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .unwrap()
                         .as_millis() as u64,
+                    ..Default::default()
                 });
-                return Err(format!("NickServ identify failed: {}", err).into());
+            }
+            Some(nick_pwd) => {
+                logging::log_info!("Auto-identifying nickname '{}' via CLI credentials", nick);
+                match client
+                    .identify_nickname(session.capability, nick, nick_pwd)
+                    .await
+                {
+                    Ok(message) => {
+                        let old_nickname = session.nickname.clone();
+                        session.nickname = nick.to_string();
+                        ui.set_nickname(nick);
+                        ui.set_status(
+                            format_status(
+                                &session.nickname,
+                                url.as_str(),
+                                format!("{} | {}", message, STATUS_HELP),
+                            ),
+                            false,
+                        );
+                        ui.add_message(ChatMessage {
+                            from: "System".to_string(),
+                            kind: MessageKind::System,
+                            body: message.to_string(),
+                            timestamp: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis() as u64,
+                            ..Default::default()
+                        });
+                        logging::log_info!(
+                            "Auto NickServ identify succeeded; nickname changed from '{}' to '{}'",
+                            old_nickname,
+                            session.nickname
+                        );
+                        if let Err(err) =
+                            token_store::remember_login(&client, session.capability, &url, nick)
+                                .await
+                        {
+                            logging::log_warn!(
+                                "Could not save a login token for '{}': {}",
+                                nick,
+                                err
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        ui.set_status(
+                            format_status(
+                                &session.nickname,
+                                url.as_str(),
+                                format!("NickServ identify failed: {} | {}", err, STATUS_HELP),
+                            ),
+                            true,
+                        );
+                        ui.add_message(ChatMessage {
+                            from: "System".to_string(),
+                            kind: MessageKind::Error,
+                            body: format!("NickServ identify failed: {}", err),
+                            timestamp: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis() as u64,
+                            ..Default::default()
+                        });
+                        logging::log_error!("Auto NickServ identify of '{}' failed: {}", nick, err);
+                        return Err(format!("NickServ identify failed: {}", err).into());
+                    }
+                }
             }
         }
     }
 
-    // Spawn task to handle incoming messages
-    let message_rx = client.get_message_receiver();
-    let ui_messages = Arc::new(tokio::sync::Mutex::new(Vec::<ChatMessage>::new()));
-    let ui_messages_clone = ui_messages.clone();
-
-    tokio::spawn(async move {
-        let mut rx = message_rx.lock().await;
-        while let Some(msg) = rx.recv().await {
-            let mut messages = ui_messages_clone.lock().await;
-            messages.push(msg.into());
-        }
-    });
+    let client = Arc::new(client);
+    logging::attach_remote(client.clone(), session.capability);
+    let (update_tx, mut updates) = mpsc::unbounded_channel::<UiUpdate>();
+    let commands = CommandRunner {
+        registry: ui.commands(),
+        client: client.clone(),
+        server_url: url.clone(),
+    };
 
-    // Main UI loop
+    let server_events = client.get_event_receiver();
+    let mut server_events = server_events.lock().await;
+    let mut terminal_events = EventStream::new();
+    // Idle times in the nick list age even when nothing else happens
+    let mut ticker = tokio::time::interval(Duration::from_secs(15));
+    // Typing indicators lapse on their own
+    let mut typing_ticker = tokio::time::interval(Duration::from_secs(1));
+    let mut dirty = true;
+
+    // Main UI loop: every source of change feeds this one select, and the
+    // screen is redrawn only after something has been applied.
     loop {
-        // Check for new messages
-        {
-            let messages = ui_messages.lock().await;
-            for msg in messages.iter() {
-                // Calculate the current terminal size and message limit
-                let terminal_height = ui.get_terminal_size().1 as usize;
-                let available_height = terminal_height.saturating_sub(8);
-                let max_messages = available_height.max(5);
-
-                ui.add_message_with_limit(msg.clone(), max_messages);
-            }
-        }
-        {
-            let mut messages = ui_messages.lock().await;
-            messages.clear();
+        if dirty {
+            ui.draw()?;
+            dirty = false;
         }
 
-        // Draw UI
-        ui.draw()?;
-
-        // Handle events
-        if ui.handle_event()? {
-            if ui.should_quit() {
-                break;
-            }
-
-            // Handle password input completion
-            if ui.is_password_input_active() {
-                ui.log(&client, session.capability, "Password input is active")
-                    .await;
-                // Check if Enter was pressed (password is ready)
-                let input = ui.get_input();
-                ui.log(
-                    &client,
-                    session.capability,
-                    &format!(
-                        "Password input check - input: '{}', empty: {}",
-                        input,
-                        input.is_empty()
-                    ),
-                )
-                .await;
-
-                // The key insight: during password input, the regular input should be empty
-                // and we should check if the password input has content
-                if input.is_empty() {
-                    let password_input = ui.get_password_input();
-                    let password_input_str = password_input.cloned().unwrap_or_default();
-                    let is_some = password_input.is_some();
-
-                    ui.log(
-                        &client,
-                        session.capability,
-                        &format!(
-                            "Password input check - password_input: '{}', is_some: {}, is_empty: {}",
-                            password_input_str,
-                            is_some,
-                            password_input_str.is_empty()
-                        ),
-                    )
-                    .await;
-
-                    ui.log(
-                        &client,
-                        session.capability,
-                        &format!(
-                            "Checking conditions - is_some: {}, password_input_str.is_empty(): {}, password_input_str: '{}'",
-                            is_some,
-                            password_input_str.is_empty(),
-                            password_input_str
-                        ),
-                    )
-                    .await;
-
-                    if is_some && !password_input_str.is_empty() {
-                        ui.log(
-                            &client,
-                            session.capability,
-                            "Enter pressed, finishing password input",
-                        )
-                        .await;
-                        // Get the prompt BEFORE finishing password input (which clears it)
-                        let default_prompt = String::new();
-                        let prompt = ui.get_password_prompt().unwrap_or(&default_prompt).clone();
-                        ui.log(
-                            &client,
-                            session.capability,
-                            &format!("Password prompt: '{}'", prompt),
-                        )
-                        .await;
-                        ui.log(
-                            &client,
-                            session.capability,
-                            &format!("Password prompt length: {}", prompt.len()),
-                        )
-                        .await;
-                        // Get the actual command type from the stored command
-                        let command = ui
-                            .get_current_password_command()
-                            .unwrap_or(&String::new())
-                            .clone();
-                        ui.log(
-                            &client,
-                            session.capability,
-                            &format!("Current password command: '{}'", command),
-                        )
-                        .await;
-                        let password = ui.finish_password_input();
-                        ui.log(
-                            &client,
-                            session.capability,
-                            &format!("finish_password_input returned: {:?}", password),
-                        )
-                        .await;
-                        if let Some(pwd) = password {
-                            ui.log(
-                                &client,
-                                session.capability,
-                                &format!(
-                                    "Password received, length: {}, content: '{}'",
-                                    pwd.len(),
-                                    pwd
-                                ),
-                            )
-                            .await;
-                            if command == "identify" {
-                                ui.log(
-                                    &client,
-                                    session.capability,
-                                    "Command is 'identify', proceeding with identification",
-                                )
-                                .await;
-                                // Extract nickname from prompt and call identify
-                                ui.log(
-                                    &client,
-                                    session.capability,
-                                    &format!("Looking for nickname in prompt: '{}'", prompt),
-                                )
-                                .await;
-                                if let Some(nick_start) = prompt.find("'") {
-                                    ui.log(
-                                        &client,
-                                        session.capability,
-                                        &format!("Found first quote at position: {}", nick_start),
-                                    )
-                                    .await;
-                                    if let Some(nick_end) = prompt.rfind("'") {
-                                        ui.log(
-                                            &client,
-                                            session.capability,
-                                            &format!("Found last quote at position: {}", nick_end),
-                                        )
-                                        .await;
-                                        if nick_end > nick_start {
-                                            let nick = &prompt[nick_start + 1..nick_end];
-                                            ui.log(
-                                                &client,
-                                                session.capability,
-                                                &format!("Extracted nickname: '{}'", nick),
-                                            )
-                                            .await;
-                                            ui.log(&client, session.capability, &format!("Attempting to identify nickname '{}' with password", nick)).await;
-                                            ui.log(&client, session.capability, &format!("Calling identify_nickname with nick='{}', password='{}'", nick, pwd)).await;
-                                            match client
-                                                .identify_nickname(session.capability, nick, &pwd)
-                                                .await
-                                            {
-                                                Ok(message) => {
-                                                    ui.log(&client, session.capability, &format!("Identify successful! Server response: {}", message)).await;
-                                                    // Update session nickname to the identified nickname
-                                                    let old_nickname = session.nickname.clone();
-                                                    session.nickname = nick.to_string();
-                                                    ui.log(
-                                                        &client,
-                                                        session.capability,
-                                                        &format!(
-                                                            "CHANGING NICKNAME: '{}' -> '{}'",
-                                                            old_nickname, session.nickname
-                                                        ),
-                                                    )
-                                                    .await;
-                                                    ui.set_status(
-                                                        format_status(
-                                                            &session.nickname,
-                                                            url.as_str(),
-                                                            STATUS_HELP,
-                                                        ),
-                                                        false,
-                                                    );
-                                                    ui.add_message(ChatMessage {
-                                                        from: "System".to_string(),
-                                                        body: format!(
-                                                            "{} - Your display name is now '{}'",
-                                                            message, nick
-                                                        ),
-                                                        timestamp: SystemTime::now()
-                                                            .duration_since(UNIX_EPOCH)
-                                                            .unwrap()
-                                                            .as_millis()
-                                                            as u64,
-                                                    });
-                                                }
-                                                Err(e) => {
-                                                    ui.log(
-                                                        &client,
-                                                        session.capability,
-                                                        &format!(
-                                                            "Identify failed with error: {}",
-                                                            e
-                                                        ),
-                                                    )
-                                                    .await;
-                                                    ui.add_message(ChatMessage {
-                                                        from: "System".to_string(),
-                                                        body: format!(
-                                                            "Identification failed: {}",
-                                                            e
-                                                        ),
-                                                        timestamp: SystemTime::now()
-                                                            .duration_since(UNIX_EPOCH)
-                                                            .unwrap()
-                                                            .as_millis()
-                                                            as u64,
-                                                    });
-                                                }
-                                            }
-                                        } else {
-                                            ui.log(&client, session.capability, "Nickname extraction failed: nick_end <= nick_start").await;
-                                        }
-                                    } else {
-                                        ui.log(
-                                            &client,
-                                            session.capability,
-                                            "Nickname extraction failed: no closing quote found",
-                                        )
-                                        .await;
-                                    }
-                                } else {
-                                    ui.log(
-                                        &client,
-                                        session.capability,
-                                        "Nickname extraction failed: no opening quote found",
-                                    )
-                                    .await;
-                                }
-                            } else if command == "register" {
-                                // Extract nickname from prompt and call register
-                                if let Some(nick_start) = prompt.find("'") {
-                                    if let Some(nick_end) = prompt.rfind("'") {
-                                        if nick_end > nick_start {
-                                            let nick = &prompt[nick_start + 1..nick_end];
-                                            match client
-                                                .register_nickname(session.capability, nick, &pwd)
-                                                .await
-                                            {
-                                                Ok(message) => {
-                                                    // Update session nickname to the registered nickname
-                                                    let old_nickname = session.nickname.clone();
-                                                    session.nickname = nick.to_string();
-                                                    ui.log(
-                                                        &client,
-                                                        session.capability,
-                                                        &format!(
-                                                            "CHANGING NICKNAME: '{}' -> '{}'",
-                                                            old_nickname, session.nickname
-                                                        ),
-                                                    )
-                                                    .await;
-                                                    ui.set_status(
-                                                        format_status(
-                                                            &session.nickname,
-                                                            url.as_str(),
-                                                            STATUS_HELP,
-                                                        ),
-                                                        false,
-                                                    );
-                                                    ui.add_message(ChatMessage {
-                                                        from: "System".to_string(),
-                                                        body: format!(
-                                                            "{} - Your display name is now '{}'",
-                                                            message, nick
-                                                        ),
-                                                        timestamp: SystemTime::now()
-                                                            .duration_since(UNIX_EPOCH)
-                                                            .unwrap()
-                                                            .as_millis()
-                                                            as u64,
-                                                    });
-                                                }
-                                                Err(e) => {
-                                                    ui.add_message(ChatMessage {
-                                                        from: "System".to_string(),
-                                                        body: format!("Registration failed: {}", e),
-                                                        timestamp: SystemTime::now()
-                                                            .duration_since(UNIX_EPOCH)
-                                                            .unwrap()
-                                                            .as_millis()
-                                                            as u64,
-                                                    });
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            ui.log(&client, session.capability, &format!("Conditions not met - is_some: {}, password_input_str.is_empty(): {}", is_some, password_input_str.is_empty())).await;
-                        }
+        tokio::select! {
+            event = terminal_events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                dirty = true;
+                if ui.handle_terminal_event(event?) {
+                    if ui.should_quit() {
+                        break;
                     }
+                    submit_input(&mut ui, &commands, &session, update_tx.clone());
                 }
-            } else {
-                // Handle regular command
-                let input = ui.get_input();
-                ui.log(
-                    &client,
-                    session.capability,
-                    &format!("Regular input received: '{}'", input),
-                )
-                .await;
-                if !input.trim().is_empty() {
-                    ui.log(&client, session.capability, "Processing non-empty input")
-                        .await;
-                    // Add timeout to prevent hanging
-                    match tokio::time::timeout(
-                        tokio::time::Duration::from_secs(5),
-                        handle_command(&input, &client, &mut session, &mut ui, url.as_str()),
-                    )
-                    .await
-                    {
-                        Ok(_) => {
-                            // Command completed successfully
-                            ui.add_to_history(input.clone());
-                        }
-                        Err(_) => {
-                            // Command timed out
-                            ui.set_status(
-                                format_status(
-                                    &session.nickname,
-                                    url.as_str(),
-                                    "Command timed out - connection may be lost",
-                                ),
-                                true,
-                            );
-                        }
-                    }
+                if let Some(room) = ui.take_typing() {
+                    send_typing(client.clone(), session.capability, room);
+                }
+                if let Some(reason) = ui.take_away_change() {
+                    send_away(client.clone(), session.capability, reason);
+                }
+            }
+            Some(event) = server_events.recv() => {
+                apply_server_event(&mut ui, event);
+                dirty = true;
+            }
+            Some(update) = updates.recv() => {
+                apply_update(&mut ui, &mut session, url.as_str(), update);
+                if ui.should_quit() {
+                    break;
+                }
+                dirty = true;
+            }
+            _ = ticker.tick() => {
+                if ui.nick_list_visible() {
+                    dirty = true;
+                }
+            }
+            _ = typing_ticker.tick() => {
+                if ui.expire_typing() {
+                    dirty = true;
+                }
+                if ui.check_auto_away() {
+                    dirty = true;
+                }
+                if let Some(reason) = ui.take_away_change() {
+                    send_away(client.clone(), session.capability, reason);
                 }
             }
         }
-
-        // Small delay to prevent busy waiting
-        tokio::time::sleep(tokio::time::Duration::from_millis(16)).await;
     }
 
     Ok(())
 }
 
-async fn handle_command(
-    input: &str,
-    client: &WebSocketClient,
-    session: &mut Session,
+/// Start a command task for the finished input line (or password).
+fn submit_input(
     ui: &mut RatatuiClient,
-    server_url: &str,
+    commands: &CommandRunner,
+    session: &Session,
+    update_tx: mpsc::UnboundedSender<UiUpdate>,
 ) {
-    let trimmed = input.trim();
-
-    // Log every command
-    ui.log(
-        &client,
-        session.capability,
-        &format!("Command received: '{}'", trimmed),
-    )
-    .await;
-
-    if !trimmed.starts_with('/') {
-        // Send message
-        match client.send_message(session.capability, trimmed).await {
-            Ok(_) => {
-                ui.set_status(
-                    format_status(&session.nickname, server_url, STATUS_HELP),
-                    false,
-                );
-            }
-            Err(e) => {
-                ui.set_status(
-                    format_status(
-                        &session.nickname,
-                        server_url,
-                        format!("Failed to send message: {}", e),
-                    ),
-                    true,
-                );
-            }
+    let request = if ui.is_password_input_active() {
+        if ui.get_password_input().is_none_or(|pwd| pwd.is_empty()) {
+            return;
         }
-        return;
-    }
+        let Some((interaction, answer)) = ui.finish_password_input() else {
+            return;
+        };
+        logging::register_secret(&answer);
+        CommandRequest::Answer {
+            interaction,
+            answer,
+        }
+    } else {
+        let input = ui.get_input();
+        if input.trim().is_empty() {
+            return;
+        }
+        ui.add_to_history(input.clone());
+        CommandRequest::Input(input)
+    };
+    let handle = UiHandle::new(UiSnapshot::of(ui), update_tx);
+    commands.spawn(request, session.clone(), handle);
+}
 
-    let parts: Vec<&str> = trimmed.split_whitespace().collect();
-    let command = parts[0];
+// Typing notices are best effort; a failure is not worth bothering the user.
+fn send_typing(client: Arc<WebSocketClient>, capability: CapId, room: String) {
+    tokio::spawn(async move {
+        if let Err(err) = client.typing(capability, &room).await {
+            logging::log_warn!("Could not send typing notice to {}: {}", room, err);
+        }
+    });
+}
 
-    match command {
-        "/quit" | "/exit" => {
-            ui.quit();
+// Automatic away changes follow key presses, so a failure is only logged.
+fn send_away(client: Arc<WebSocketClient>, capability: CapId, reason: Option<String>) {
+    tokio::spawn(async move {
+        if let Err(err) = client.set_away(capability, reason.as_deref()).await {
+            logging::log_warn!("Could not update away status: {}", err);
         }
-        "/help" => {
-            ui.add_message(ChatMessage {
-                from: "System".to_string(),
-                body: "Available Commands:
-  /help                  Show this help
-  /whoami                Show current session
-  /receive               Fetch and display messages
-/nickserv identify <nick>  Identify with a protected nickname
-/nickserv register <nick>  Register a new nickname
-  /quit                  Exit the client
-
-Messages without a leading slash are broadcast to the chat."
-                    .to_string(),
-                timestamp: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64,
-            });
+    });
+}
+
+fn apply_server_event(ui: &mut RatatuiClient, event: ServerEvent) {
+    match event {
+        ServerEvent::Message(msg) => {
+            // Calculate the current terminal size and message limit
+            let terminal_height = ui.get_terminal_size().1 as usize;
+            let available_height = terminal_height.saturating_sub(9);
+            let max_messages = available_height.max(5);
+
+            let room = msg.room.clone();
+            ui.add_incoming(&room, msg.into(), max_messages);
         }
-        "/whoami" => match client.whoami(session.capability).await {
-            Ok(result) => {
-                ui.add_message(ChatMessage {
-                    from: "System".to_string(),
-                    body: format!("You are: {:?}", result),
-                    timestamp: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                });
-            }
-            Err(e) => {
-                ui.set_status(
-                    format_status(
-                        &session.nickname,
-                        server_url,
-                        format!("Whoami failed: {}", e),
-                    ),
-                    true,
-                );
-            }
-        },
-        "/receive" => match client.receive_messages(session.capability).await {
-            Ok(messages) => {
-                for msg in messages {
-                    ui.add_message(msg.into());
-                }
-                ui.set_status(
-                    format_status(&session.nickname, server_url, "Fetched recent messages"),
-                    false,
-                );
-            }
-            Err(e) => {
-                ui.set_status(
-                    format_status(
-                        &session.nickname,
-                        server_url,
-                        format!("Failed to receive messages: {}", e),
-                    ),
-                    true,
-                );
+        ServerEvent::DirectMessage(msg) => {
+            // Direct messages land in a query buffer named after the sender
+            let peer = msg.from.clone();
+            ui.add_incoming(&peer, msg.into(), 100);
+        }
+        ServerEvent::MessageEdited(msg) => {
+            if let Some(id) = msg.id {
+                ui.edit_message(id, &msg.body);
             }
-        },
-        "/nickserv" => {
-            // Add a system message to show the command was received
+        }
+        ServerEvent::MessageDeleted(deleted) => ui.delete_message(deleted.id),
+        ServerEvent::Reaction(update) => ui.set_reactions(update.id, &update.reactions),
+        ServerEvent::Presence(users) => {
+            ui.set_presence(users.into_iter().map(Into::into).collect());
+        }
+        ServerEvent::Typing(event) => ui.set_typing(&event.nickname, &event.room),
+        // The server also posts joins and parts as system messages
+        ServerEvent::UserJoined(event) => {
+            logging::log_debug!("{} joined {}", event.nickname, event.room);
+        }
+        ServerEvent::UserLeft(event) => ui.clear_typing(&event.nickname, Some(&event.room)),
+        ServerEvent::TopicChanged(topic) => ui.set_topic(topic),
+        ServerEvent::Kicked(event) => {
+            ui.close_buffer(&event.room);
+            let reason = event
+                .reason
+                .map(|reason| format!(": {}", reason))
+                .unwrap_or_default();
             ui.add_message(ChatMessage {
-                from: "Debug".to_string(),
+                from: "System".to_string(),
+                kind: MessageKind::Error,
                 body: format!(
-                    "DEBUG: /nickserv command received with {} parts",
-                    parts.len()
+                    "You were removed from {} by {}{}",
+                    event.room, event.by, reason
                 ),
                 timestamp: SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
                     .as_millis() as u64,
+                ..Default::default()
             });
-            ui.log(
-                &client,
-                session.capability,
-                &format!(
-                    "/nickserv command received with {} parts: {:?}",
-                    parts.len(),
-                    parts
-                ),
-            )
-            .await;
-            if parts.len() < 2 {
-                ui.add_message(ChatMessage {
-                    from: "System".to_string(),
-                    body: "NickServ Commands:
-/nickserv identify <nick>  Identify with a protected nickname
-/nickserv register <nick>  Register a new nickname"
-                        .to_string(),
-                    timestamp: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                });
-                return;
-            }
-
-            let subcommand = parts[1];
-            match subcommand {
-                "identify" => {
-                    ui.add_message(ChatMessage {
-                        from: "Debug".to_string(),
-                        body: "DEBUG: /nickserv identify subcommand received".to_string(),
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as u64,
-                    });
-                    ui.log(
-                        &client,
-                        session.capability,
-                        "/nickserv identify subcommand received",
-                    )
-                    .await;
-                    if parts.len() < 3 {
-                        ui.add_message(ChatMessage {
-                            from: "System".to_string(),
-                            body: "Usage: /nickserv identify <nick>
-You will be prompted for the nickname password."
-                                .to_string(),
-                            timestamp: SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis() as u64,
-                        });
-                        return;
-                    }
-                    let nick = parts[2];
-
-                    match client.check_nickname(session.capability, nick).await {
-                        Ok(true) => {
-                            // Start password input mode
-                            ui.log(
-                                &client,
-                                session.capability,
-                                &format!("Starting password input for nickname '{}'", nick),
-                            )
-                            .await;
-                            let prompt_text = format!("Password for nickname '{}'", nick);
-                            ui.log(
-                                &client,
-                                session.capability,
-                                &format!("Setting prompt to: '{}'", prompt_text),
-                            )
-                            .await;
-                            ui.start_password_input(prompt_text, "identify".to_string());
-                            ui.add_message(ChatMessage {
-                                from: "System".to_string(),
-                                body: format!(
-                                    "Please enter password for nickname '{}' in the input area below",
-                                    nick
-                                ),
-                                timestamp: SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis() as u64,
-                            });
-                        }
-                        Ok(false) => {
-                            let message = format!(
-                                "Nickname '{}' is not registered. Use /nickserv register <nick>.",
-                                nick
-                            );
-                            let detail = format!("{} | {}", message, STATUS_HELP);
-                            ui.set_status(
-                                format_status(&session.nickname, server_url, detail),
-                                true,
-                            );
-                            ui.add_message(ChatMessage {
-                                from: "System".to_string(),
-                                body: message,
-                                timestamp: SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis() as u64,
-                            });
-                            return;
-                        }
-                        Err(err) => {
-                            let message = format!("Failed to verify nickname '{}': {}", nick, err);
-                            let detail = format!("{} | {}", message, STATUS_HELP);
-                            ui.set_status(
-                                format_status(&session.nickname, server_url, detail),
-                                true,
-                            );
-                            ui.add_message(ChatMessage {
-                                from: "System".to_string(),
-                                body: message,
-                                timestamp: SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_millis() as u64,
-                            });
-                            return;
-                        }
-                    }
-                }
-                "register" => {
-                    if parts.len() < 3 {
-                        ui.add_message(ChatMessage {
-                            from: "System".to_string(),
-                            body: "Usage: /nickserv register <nick>
-You will be prompted for a password to protect your nickname."
-                                .to_string(),
-                            timestamp: SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis() as u64,
-                        });
-                        return;
-                    }
-                    let nick = parts[2];
-
-                    // Start password input mode
-                    ui.start_password_input(
-                        format!("Password for new nickname '{}'", nick),
-                        "register".to_string(),
-                    );
-                    ui.add_message(ChatMessage {
-                        from: "System".to_string(),
-                        body: format!(
-                            "Please enter password for new nickname '{}' in the input area below",
-                            nick
-                        ),
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as u64,
-                    });
-                }
-                _ => {
-                    ui.add_message(ChatMessage {
+        }
+        ServerEvent::PresenceChanged(change) => {
+            if !change.online {
+                ui.clear_typing(&change.nickname, None);
+            } else if ui.has_buffer(&change.nickname) {
+                // A query with them says when they step away or return
+                let body = match (change.away, change.reason) {
+                    (true, Some(reason)) => format!("{} is away: {}", change.nickname, reason),
+                    (true, None) => format!("{} is away", change.nickname),
+                    (false, _) => format!("{} is here", change.nickname),
+                };
+                ui.add_message_to(
+                    &change.nickname,
+                    ChatMessage {
                         from: "System".to_string(),
-                        body: "Unknown nickserv command. Use 'identify' or 'register'".to_string(),
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as u64,
-                    });
-                }
+                        kind: MessageKind::System,
+                        body,
+                        timestamp: now_millis(),
+                        ..Default::default()
+                    },
+                    100,
+                );
             }
         }
-        _ => {
-            ui.add_message(ChatMessage {
-                from: "System".to_string(),
-                body: format!(
-                    "Unknown command `{}`. Type /help for a list of commands.",
-                    command
+    }
+}
+
+fn apply_update(ui: &mut RatatuiClient, session: &mut Session, server_url: &str, update: UiUpdate) {
+    match update {
+        UiUpdate::AddMessage(buffer, msg) if ui.has_buffer(&buffer) => {
+            ui.add_message_to(&buffer, msg, 100)
+        }
+        UiUpdate::AddMessage(_, msg) => ui.add_message(msg),
+        UiUpdate::AddMessageTo(buffer, msg, max_messages) => {
+            ui.add_message_to(&buffer, msg, max_messages)
+        }
+        UiUpdate::Status(status, is_error) => ui.set_status(status, is_error),
+        UiUpdate::OpenBuffer(name) => ui.open_buffer(&name),
+        UiUpdate::CloseBuffer(name) => {
+            ui.close_buffer(&name);
+        }
+        UiUpdate::Prompt(interaction) => ui.start_prompt(interaction),
+        UiUpdate::StartSearch(query) => {
+            let summary = ui.start_search(query);
+            ui.set_status(
+                format_status(
+                    &session.nickname,
+                    server_url,
+                    format!("Search: {} (n/N to move, Esc to close)", summary),
                 ),
-                timestamp: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64,
-            });
+                false,
+            );
+        }
+        UiUpdate::ShowLinks => ui.open_link_picker(),
+        UiUpdate::Topic(topic) => ui.set_topic(topic),
+        UiUpdate::Ignore(rule) => save_ignores(ui, session, server_url, rule, true),
+        UiUpdate::Unignore(rule) => save_ignores(ui, session, server_url, rule, false),
+        UiUpdate::Away(reason) => ui.set_away(reason),
+        UiUpdate::Nickname(nickname) => {
+            ui.set_nickname(&nickname);
+            session.nickname = nickname;
         }
+        UiUpdate::Quit => ui.quit(),
+        UiUpdate::CommandFinished { timed_out: true } => {
+            ui.set_status(
+                format_status(
+                    &session.nickname,
+                    server_url,
+                    "Command timed out - connection may be lost",
+                ),
+                true,
+            );
+        }
+        UiUpdate::CommandFinished { timed_out: false } => {}
+    }
+}
+
+// The list is changed either way; only a failed save is worth a status.
+fn save_ignores(
+    ui: &mut RatatuiClient,
+    session: &Session,
+    server_url: &str,
+    rule: IgnoreRule,
+    add: bool,
+) {
+    if let Err(err) = ui.change_ignores(rule, add) {
+        ui.set_status(
+            format_status(
+                &session.nickname,
+                server_url,
+                format!("Ignore list not saved: {}", err),
+            ),
+            true,
+        );
+    }
+}
+
+/// Everything a command task needs besides its request, the session and
+/// its `UiHandle`.
+struct CommandRunner {
+    registry: Arc<Registry>,
+    client: Arc<WebSocketClient>,
+    server_url: String,
+}
+
+impl CommandRunner {
+    /// Run one command as its own task, so a slow RPC holds up neither the
+    /// UI nor the commands typed after it. The task reports back through
+    /// its `UiHandle` and finishes with `CommandFinished`; changes to the
+    /// session come back as updates too, so each task starts from a copy.
+    fn spawn(&self, request: CommandRequest, session: Session, ui: UiHandle) {
+        let registry = self.registry.clone();
+        let mut ctx = CommandContext {
+            registry: registry.clone(),
+            client: self.client.clone(),
+            session,
+            server_url: self.server_url.clone(),
+            ui,
+        };
+        tokio::spawn(async move {
+            let work = async {
+                match request {
+                    CommandRequest::Input(input) => registry.dispatch(&mut ctx, &input).await,
+                    CommandRequest::Answer {
+                        interaction,
+                        answer,
+                    } => registry.resolve(&mut ctx, interaction, answer).await,
+                }
+            };
+            // Add timeout so a lost connection cannot leave the task hanging
+            let timed_out = tokio::time::timeout(COMMAND_TIMEOUT, work).await.is_err();
+            if timed_out {
+                logging::log_warn!("Command timed out after {:?}", COMMAND_TIMEOUT);
+            }
+            ctx.ui.finish(timed_out);
+        });
     }
 }
@@ -0,0 +1,215 @@
+use regex::{Regex, RegexBuilder};
+
+/// What the user is searching for and how to interpret it.
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub case_sensitive: bool,
+    pub regex: bool,
+}
+
+impl SearchQuery {
+    /// Plain queries are escaped so both modes share the regex engine.
+    pub fn compile(&self) -> Result<Regex, String> {
+        let pattern = if self.regex {
+            self.text.clone()
+        } else {
+            regex::escape(&self.text)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .size_limit(1 << 20)
+            .build()
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn describe(&self) -> String {
+        let mut flags = Vec::new();
+        if self.regex {
+            flags.push("regex");
+        }
+        if self.case_sensitive {
+            flags.push("case");
+        }
+        if flags.is_empty() {
+            self.text.clone()
+        } else {
+            format!("{} [{}]", self.text, flags.join(","))
+        }
+    }
+}
+
+/// A match inside one rendered message line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// Index of the rendered line in the message list
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct SearchState {
+    pub query: SearchQuery,
+    /// True while the query is being typed (incremental mode)
+    pub editing: bool,
+    pub matches: Vec<SearchMatch>,
+    pub current: Option<usize>,
+    pub error: Option<String>,
+}
+
+impl SearchState {
+    pub fn new(query: SearchQuery, editing: bool) -> Self {
+        Self {
+            query,
+            editing,
+            matches: Vec::new(),
+            current: None,
+            error: None,
+        }
+    }
+
+    /// Recompute matches against `lines`, keeping the cursor on the newest
+    /// match so incremental search follows the bottom of the buffer.
+    pub fn refresh<'a>(&mut self, lines: impl Iterator<Item = &'a str>) {
+        self.matches.clear();
+        self.current = None;
+        self.error = None;
+        if self.query.text.is_empty() {
+            return;
+        }
+        let regex = match self.query.compile() {
+            Ok(regex) => regex,
+            Err(err) => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        for (line, text) in lines.enumerate() {
+            for found in regex.find_iter(text) {
+                if found.start() == found.end() {
+                    continue;
+                }
+                self.matches.push(SearchMatch {
+                    line,
+                    start: found.start(),
+                    end: found.end(),
+                });
+            }
+        }
+        if !self.matches.is_empty() {
+            self.current = Some(self.matches.len() - 1);
+        }
+    }
+
+    /// Move towards older messages, wrapping around at the top.
+    pub fn next_older(&mut self) -> Option<SearchMatch> {
+        let len = self.matches.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match self.current {
+            Some(0) | None => len - 1,
+            Some(i) => i - 1,
+        };
+        self.current = Some(index);
+        self.matches.get(index).copied()
+    }
+
+    /// Move towards newer messages, wrapping around at the bottom.
+    pub fn next_newer(&mut self) -> Option<SearchMatch> {
+        let len = self.matches.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match self.current {
+            Some(i) if i + 1 < len => i + 1,
+            _ => 0,
+        };
+        self.current = Some(index);
+        self.matches.get(index).copied()
+    }
+
+    pub fn current_match(&self) -> Option<SearchMatch> {
+        self.current.and_then(|i| self.matches.get(i).copied())
+    }
+
+    pub fn summary(&self) -> String {
+        if let Some(err) = &self.error {
+            return format!("invalid pattern: {}", err);
+        }
+        match self.current {
+            Some(i) => format!("{}/{}", i + 1, self.matches.len()),
+            None if self.query.text.is_empty() => String::new(),
+            None => "no matches".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(text: &str, case_sensitive: bool, regex: bool) -> SearchState {
+        SearchState::new(
+            SearchQuery {
+                text: text.to_string(),
+                case_sensitive,
+                regex,
+            },
+            false,
+        )
+    }
+
+    #[test]
+    fn plain_search_is_case_insensitive_by_default() {
+        let mut search = state("deploy", false, false);
+        search.refresh(["Deploy started", "nothing here", "deploy done"].into_iter());
+
+        assert_eq!(search.matches.len(), 2);
+        assert_eq!(search.current_match().unwrap().line, 2);
+    }
+
+    #[test]
+    fn plain_search_escapes_regex_syntax() {
+        let mut search = state("a.b", false, false);
+        search.refresh(["axb", "a.b"].into_iter());
+
+        assert_eq!(
+            search.matches,
+            vec![SearchMatch {
+                line: 1,
+                start: 0,
+                end: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn regex_and_case_sensitive_modes() {
+        let mut search = state(r"v\d+", true, true);
+        search.refresh(["v1 and V2", "v10"].into_iter());
+
+        assert_eq!(search.matches.len(), 2);
+        assert_eq!(search.summary(), "2/2");
+    }
+
+    #[test]
+    fn navigation_wraps_in_both_directions() {
+        let mut search = state("x", false, false);
+        search.refresh(["x", "x", "x"].into_iter());
+
+        assert_eq!(search.next_older().unwrap().line, 1);
+        assert_eq!(search.next_older().unwrap().line, 0);
+        assert_eq!(search.next_older().unwrap().line, 2);
+        assert_eq!(search.next_newer().unwrap().line, 0);
+    }
+
+    #[test]
+    fn invalid_regex_reports_error() {
+        let mut search = state("(", false, true);
+        search.refresh(["("].into_iter());
+
+        assert!(search.matches.is_empty());
+        assert!(search.summary().starts_with("invalid pattern"));
+    }
+}
@@ -0,0 +1,437 @@
+//! Colors and layout for the TUI. Three themes are built in; the config file
+//! can define more under `[themes.<name>]`, each starting from a built-in
+//! `base` and overriding individual slots:
+//!
+//! ```toml
+//! theme = "paper"
+//!
+//! [themes.paper]
+//! base = "light"
+//! sender = "blue"
+//! search_match = "black on #ffd75f"
+//! ```
+//!
+//! A non-empty `NO_COLOR` environment variable drops every color and keeps
+//! only bold, italic and reverse video.
+
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::block::Title;
+use ratatui::widgets::{Block, BorderType, Borders};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+pub const BUILTIN_THEMES: &[&str] = &["dark", "light", "high-contrast"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub name: String,
+    /// Message bodies
+    pub text: Style,
+    /// Sender names in rooms
+    pub sender: Style,
+    /// Sender names in private queries
+    pub query_sender: Style,
+    pub input: Style,
+    pub status: Style,
+    pub status_error: Style,
+    pub tab: Style,
+    pub tab_query: Style,
+    pub tab_active: Style,
+    pub search_match: Style,
+    pub search_current: Style,
+    pub nick: Style,
+    pub nick_away: Style,
+    /// The `+` in front of identified nicks
+    pub marker: Style,
+    /// Secondary text such as idle times
+    pub dim: Style,
+    /// Inline code and code blocks in messages
+    pub code: Style,
+    pub code_keyword: Style,
+    pub code_string: Style,
+    pub code_comment: Style,
+    /// Quoted lines in messages
+    pub quote: Style,
+    /// URLs in messages
+    pub link: Style,
+    /// Your nick or a keyword in a message, and the mentions tab
+    pub mention: Style,
+    /// `/me` actions
+    pub action: Style,
+    pub notice: Style,
+    /// Joins, nick changes and other events
+    pub system: Style,
+    pub error: Style,
+}
+
+fn fg(color: Color) -> Style {
+    Style::default().fg(color)
+}
+
+fn on(fg: Color, bg: Color) -> Style {
+    Style::default().fg(fg).bg(bg)
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            name: "dark".to_string(),
+            text: fg(Color::Cyan),
+            sender: fg(Color::Green).add_modifier(Modifier::BOLD),
+            query_sender: fg(Color::Magenta).add_modifier(Modifier::BOLD),
+            input: fg(Color::Yellow),
+            status: on(Color::White, Color::Blue),
+            status_error: on(Color::White, Color::Red),
+            tab: fg(Color::Gray),
+            tab_query: fg(Color::Magenta),
+            tab_active: fg(Color::Yellow).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            search_match: on(Color::Black, Color::Yellow),
+            search_current: on(Color::Black, Color::LightRed).add_modifier(Modifier::BOLD),
+            nick: fg(Color::Green),
+            nick_away: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            marker: fg(Color::Yellow),
+            dim: fg(Color::DarkGray),
+            code: fg(Color::White),
+            code_keyword: fg(Color::LightMagenta),
+            code_string: fg(Color::LightGreen),
+            code_comment: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            quote: fg(Color::Gray).add_modifier(Modifier::ITALIC),
+            link: fg(Color::LightBlue).add_modifier(Modifier::UNDERLINED),
+            mention: fg(Color::LightRed).add_modifier(Modifier::BOLD),
+            action: fg(Color::LightMagenta).add_modifier(Modifier::ITALIC),
+            notice: fg(Color::LightYellow),
+            system: fg(Color::DarkGray),
+            error: fg(Color::LightRed),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            name: "light".to_string(),
+            text: fg(Color::Reset),
+            sender: fg(Color::Blue).add_modifier(Modifier::BOLD),
+            query_sender: fg(Color::Magenta).add_modifier(Modifier::BOLD),
+            input: fg(Color::Black),
+            status: on(Color::White, Color::Blue),
+            status_error: on(Color::White, Color::Red),
+            tab: fg(Color::DarkGray),
+            tab_query: fg(Color::Magenta),
+            tab_active: fg(Color::Blue).add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            search_match: on(Color::Black, Color::LightYellow),
+            search_current: on(Color::Black, Color::LightRed).add_modifier(Modifier::BOLD),
+            nick: fg(Color::Blue),
+            nick_away: fg(Color::Gray).add_modifier(Modifier::ITALIC),
+            marker: fg(Color::Red),
+            dim: fg(Color::DarkGray),
+            code: fg(Color::Black),
+            code_keyword: fg(Color::Magenta),
+            code_string: fg(Color::Green),
+            code_comment: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            quote: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            link: fg(Color::Blue).add_modifier(Modifier::UNDERLINED),
+            mention: fg(Color::Red).add_modifier(Modifier::BOLD),
+            action: fg(Color::Magenta).add_modifier(Modifier::ITALIC),
+            notice: fg(Color::Yellow),
+            system: fg(Color::DarkGray),
+            error: fg(Color::Red),
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        let bold = Modifier::BOLD;
+        Self {
+            name: "high-contrast".to_string(),
+            text: fg(Color::White),
+            sender: fg(Color::LightYellow).add_modifier(bold),
+            query_sender: fg(Color::LightMagenta).add_modifier(bold),
+            input: fg(Color::White).add_modifier(bold),
+            status: on(Color::Black, Color::White).add_modifier(bold),
+            status_error: on(Color::White, Color::Red).add_modifier(bold),
+            tab: fg(Color::White),
+            tab_query: fg(Color::LightMagenta),
+            tab_active: on(Color::Black, Color::LightYellow).add_modifier(bold),
+            search_match: on(Color::Black, Color::LightCyan),
+            search_current: on(Color::Black, Color::LightYellow)
+                .add_modifier(bold | Modifier::UNDERLINED),
+            nick: fg(Color::LightGreen),
+            nick_away: fg(Color::White).add_modifier(Modifier::ITALIC),
+            marker: fg(Color::LightYellow).add_modifier(bold),
+            dim: fg(Color::White),
+            code: fg(Color::LightCyan),
+            code_keyword: fg(Color::LightYellow).add_modifier(bold),
+            code_string: fg(Color::LightGreen),
+            code_comment: fg(Color::White).add_modifier(Modifier::ITALIC),
+            quote: fg(Color::White).add_modifier(Modifier::ITALIC),
+            link: fg(Color::LightCyan).add_modifier(bold | Modifier::UNDERLINED),
+            mention: on(Color::Black, Color::LightRed).add_modifier(bold),
+            action: fg(Color::LightMagenta).add_modifier(bold | Modifier::ITALIC),
+            notice: fg(Color::LightYellow).add_modifier(bold),
+            system: fg(Color::White).add_modifier(Modifier::ITALIC),
+            error: fg(Color::LightRed).add_modifier(bold),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// Look `name` up among the user's themes, then the built-in ones.
+    pub fn resolve(name: &str, user: &BTreeMap<String, ThemeConfig>) -> Result<Self, String> {
+        let Some(config) = user.get(name) else {
+            return Self::builtin(name).ok_or_else(|| {
+                let mut known: Vec<&str> = BUILTIN_THEMES.to_vec();
+                known.extend(user.keys().map(String::as_str));
+                format!(
+                    "unknown theme `{}` (expected one of: {})",
+                    name,
+                    known.join(", ")
+                )
+            });
+        };
+        let base = config.base.as_deref().unwrap_or("dark");
+        let mut theme = Self::builtin(base)
+            .ok_or_else(|| format!("theme `{}`: unknown base theme `{}`", name, base))?;
+        theme.name = name.to_string();
+        config
+            .apply(&mut theme)
+            .map_err(|err| format!("theme `{}`: {}", name, err))?;
+        Ok(theme)
+    }
+
+    /// Strip all colors, keeping text attributes so highlights stay visible.
+    pub fn without_color(mut self) -> Self {
+        for style in self.slots_mut() {
+            *style = Style::default().add_modifier(style.add_modifier);
+        }
+        // Reverse video stands in for the highlight backgrounds
+        self.status = self.status.add_modifier(Modifier::REVERSED);
+        self.search_match = self.search_match.add_modifier(Modifier::REVERSED);
+        self.mention = self.mention.add_modifier(Modifier::REVERSED);
+        self.error = self.error.add_modifier(Modifier::BOLD);
+        self.search_current = self
+            .search_current
+            .add_modifier(Modifier::REVERSED | Modifier::BOLD);
+        self.status_error = self
+            .status_error
+            .add_modifier(Modifier::REVERSED | Modifier::BOLD);
+        self
+    }
+
+    fn slots_mut(&mut self) -> [&mut Style; 26] {
+        [
+            &mut self.text,
+            &mut self.sender,
+            &mut self.query_sender,
+            &mut self.input,
+            &mut self.status,
+            &mut self.status_error,
+            &mut self.tab,
+            &mut self.tab_query,
+            &mut self.tab_active,
+            &mut self.search_match,
+            &mut self.search_current,
+            &mut self.nick,
+            &mut self.nick_away,
+            &mut self.marker,
+            &mut self.dim,
+            &mut self.code,
+            &mut self.code_keyword,
+            &mut self.code_string,
+            &mut self.code_comment,
+            &mut self.quote,
+            &mut self.link,
+            &mut self.mention,
+            &mut self.action,
+            &mut self.notice,
+            &mut self.system,
+            &mut self.error,
+        ]
+    }
+}
+
+/// True when the `NO_COLOR` convention asks for monochrome output.
+pub fn no_color_requested() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// A user theme from the config file. Each slot is `"<fg>"` or
+/// `"<fg> on <bg>"`, using color names, `#rrggbb` or a 0-255 index.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThemeConfig {
+    base: Option<String>,
+    text: Option<String>,
+    sender: Option<String>,
+    query_sender: Option<String>,
+    input: Option<String>,
+    status: Option<String>,
+    status_error: Option<String>,
+    tab: Option<String>,
+    tab_query: Option<String>,
+    tab_active: Option<String>,
+    search_match: Option<String>,
+    search_current: Option<String>,
+    nick: Option<String>,
+    nick_away: Option<String>,
+    marker: Option<String>,
+    dim: Option<String>,
+    code: Option<String>,
+    code_keyword: Option<String>,
+    code_string: Option<String>,
+    code_comment: Option<String>,
+    quote: Option<String>,
+    link: Option<String>,
+    mention: Option<String>,
+    action: Option<String>,
+    notice: Option<String>,
+    system: Option<String>,
+    error: Option<String>,
+}
+
+impl ThemeConfig {
+    fn apply(&self, theme: &mut Theme) -> Result<(), String> {
+        let overrides = [
+            &self.text,
+            &self.sender,
+            &self.query_sender,
+            &self.input,
+            &self.status,
+            &self.status_error,
+            &self.tab,
+            &self.tab_query,
+            &self.tab_active,
+            &self.search_match,
+            &self.search_current,
+            &self.nick,
+            &self.nick_away,
+            &self.marker,
+            &self.dim,
+            &self.code,
+            &self.code_keyword,
+            &self.code_string,
+            &self.code_comment,
+            &self.quote,
+            &self.link,
+            &self.mention,
+            &self.action,
+            &self.notice,
+            &self.system,
+            &self.error,
+        ];
+        for (style, value) in theme.slots_mut().into_iter().zip(overrides) {
+            if let Some(value) = value {
+                *style = parse_colors(value, *style)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replace the colors of `style` with `"<fg>"` or `"<fg> on <bg>"`.
+fn parse_colors(value: &str, style: Style) -> Result<Style, String> {
+    let color = |name: &str| {
+        name.trim()
+            .parse::<Color>()
+            .map_err(|_| format!("unknown color `{}`", name.trim()))
+    };
+    match value.split_once(" on ") {
+        Some((fg, bg)) => Ok(style.fg(color(fg)?).bg(color(bg)?)),
+        None => Ok(style.fg(color(value)?)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusPosition {
+    Top,
+    #[default]
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BorderStyle {
+    #[default]
+    Plain,
+    Rounded,
+    Double,
+    Thick,
+}
+
+/// The `[layout]` table.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutOptions {
+    pub status_bar: StatusPosition,
+    pub borders: BorderStyle,
+    /// Drop the borders so every row is content
+    pub compact: bool,
+}
+
+impl LayoutOptions {
+    /// A block in the configured border style. Compact mode drops the
+    /// title too, since it would take a row of its own.
+    pub fn block<'a, T: Into<Title<'a>>>(&self, title: T) -> Block<'a> {
+        if self.compact {
+            return Block::default();
+        }
+        let border_type = match self.borders {
+            BorderStyle::Plain => BorderType::Plain,
+            BorderStyle::Rounded => BorderType::Rounded,
+            BorderStyle::Double => BorderType::Double,
+            BorderStyle::Thick => BorderType::Thick,
+        };
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(border_type)
+            .title(title)
+    }
+
+    /// Height of the input and status bars.
+    pub fn bar_height(&self) -> u16 {
+        if self.compact { 1 } else { 3 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_themes_override_their_base() {
+        let mut user = BTreeMap::new();
+        user.insert(
+            "paper".to_string(),
+            ThemeConfig {
+                base: Some("light".to_string()),
+                sender: Some("red".to_string()),
+                search_match: Some("black on #ffd75f".to_string()),
+                ..ThemeConfig::default()
+            },
+        );
+        let theme = Theme::resolve("paper", &user).unwrap();
+        assert_eq!(theme.name, "paper");
+        assert_eq!(theme.sender.fg, Some(Color::Red));
+        assert!(theme.sender.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(theme.search_match.bg, Some(Color::Rgb(0xff, 0xd7, 0x5f)));
+        assert_eq!(theme.input, Theme::light().input);
+
+        user.get_mut("paper").unwrap().nick = Some("chartreuse-ish".to_string());
+        assert!(Theme::resolve("paper", &user).is_err());
+        assert!(Theme::resolve("neon", &user).unwrap_err().contains("paper"));
+    }
+
+    #[test]
+    fn no_color_keeps_only_attributes() {
+        let theme = Theme::high_contrast().without_color();
+        assert_eq!(theme.text, Style::default());
+        assert_eq!(theme.search_match.bg, None);
+        assert!(theme.search_match.add_modifier.contains(Modifier::REVERSED));
+        assert!(theme.sender.add_modifier.contains(Modifier::BOLD));
+    }
+}
@@ -0,0 +1,176 @@
+//! Login tokens saved between runs so `--user` does not need `--password`
+//! every time. Tokens are keyed by server URL and nickname and kept in a
+//! file only the current user can read.
+
+use crate::logging;
+use crate::websocket_client::WebSocketClient;
+use capnweb_core::CapId;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct StoredToken {
+    server: String,
+    nick: String,
+    token: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct TokenFile {
+    #[serde(default)]
+    tokens: Vec<StoredToken>,
+}
+
+pub struct TokenStore {
+    path: PathBuf,
+    file: TokenFile,
+}
+
+impl TokenStore {
+    /// `$XDG_DATA_HOME/capinrs/tokens.json`, falling back to
+    /// `~/.local/share/capinrs/tokens.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })?;
+        Some(data_home.join("capinrs").join("tokens.json"))
+    }
+
+    /// Load the store at `path`; a missing or unreadable file starts empty.
+    pub fn load(path: &Path) -> Self {
+        let file = fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            path: path.to_path_buf(),
+            file,
+        }
+    }
+
+    pub fn open_default() -> Option<Self> {
+        Self::default_path().map(|path| Self::load(&path))
+    }
+
+    pub fn get(&self, server: &str, nick: &str) -> Option<&str> {
+        self.file
+            .tokens
+            .iter()
+            .find(|entry| entry.server == server && entry.nick == nick)
+            .map(|entry| entry.token.as_str())
+    }
+
+    pub fn set(&mut self, server: &str, nick: &str, token: &str) {
+        self.remove(server, nick);
+        self.file.tokens.push(StoredToken {
+            server: server.to_string(),
+            nick: nick.to_string(),
+            token: token.to_string(),
+        });
+    }
+
+    pub fn remove(&mut self, server: &str, nick: &str) {
+        self.file
+            .tokens
+            .retain(|entry| !(entry.server == server && entry.nick == nick));
+    }
+
+    /// Write the store, readable and writable by the owner only.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_string_pretty(&self.file)?;
+
+        // Write a private temp file and rename it over the old one
+        let tmp = self.path.with_extension("json.tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..32)
+        .map(|_| format!("{:02x}", rng.r#gen::<u8>()))
+        .collect()
+}
+
+/// Issue a fresh token for the identified `nick` and save it locally.
+pub async fn remember_login(
+    client: &WebSocketClient,
+    capability: CapId,
+    server: &str,
+    nick: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut store = TokenStore::open_default().ok_or("no data directory for the token store")?;
+    let token = generate_token();
+    logging::register_secret(&token);
+    client.store_nick_token(capability, &token).await?;
+    store.set(server, nick, &token);
+    store.save()?;
+    logging::log_info!("Saved login token for '{}' on {}", nick, server);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("capinrs-test-{}-{}", std::process::id(), name))
+            .join("tokens.json")
+    }
+
+    #[test]
+    fn tokens_round_trip_per_server_and_nick() {
+        let path = temp_path("round-trip");
+        let mut store = TokenStore::load(&path);
+        store.set("wss://a", "bob", "t1");
+        store.set("wss://b", "bob", "t2");
+        store.set("wss://a", "bob", "t3");
+        store.save().unwrap();
+
+        let store = TokenStore::load(&path);
+        assert_eq!(store.get("wss://a", "bob"), Some("t3"));
+        assert_eq!(store.get("wss://b", "bob"), Some("t2"));
+        assert_eq!(store.get("wss://a", "alice"), None);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn generated_tokens_are_long_and_distinct() {
+        let a = generate_token();
+        assert_eq!(a.len(), 64);
+        assert_ne!(a, generate_token());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::commands::PendingInteraction;
+use crate::ignore::{IgnoreList, IgnoreRule};
+use crate::ratatui_client::{BufferKind, ChatMessage, RatatuiClient};
+use crate::search::SearchQuery;
+use crate::websocket_client::RoomTopic;
+use tokio::sync::mpsc;
+
+/// A state change produced by a background command task and applied to the
+/// UI by the main loop.
+pub enum UiUpdate {
+    /// Command output for the buffer it was typed in, or the active buffer
+    /// once that one has closed
+    AddMessage(String, ChatMessage),
+    AddMessageTo(String, ChatMessage, usize),
+    Status(String, bool),
+    OpenBuffer(String),
+    CloseBuffer(String),
+    Prompt(PendingInteraction),
+    StartSearch(SearchQuery),
+    ShowLinks,
+    Topic(RoomTopic),
+    /// Changes to the live ignore list, which the main loop also saves
+    Ignore(IgnoreRule),
+    Unignore(IgnoreRule),
+    /// Our away reason, or None once back
+    Away(Option<String>),
+    Nickname(String),
+    Quit,
+    /// Sent once per request after it completes or times out
+    CommandFinished {
+        timed_out: bool,
+    },
+}
+
+/// Work submitted from the main loop to a command task.
+pub enum CommandRequest {
+    Input(String),
+    Answer {
+        interaction: PendingInteraction,
+        answer: String,
+    },
+}
+
+/// What a command may read about the UI at the moment it was submitted.
+#[derive(Clone)]
+pub struct UiSnapshot {
+    /// Where the command was typed, and where its output goes
+    pub active_buffer: String,
+    pub active_kind: BufferKind,
+    pub buffer_count: usize,
+    /// Id of our last editable message in the active buffer
+    pub last_own_message: Option<u64>,
+    pub ignores: IgnoreList,
+    /// Messages the ignore list dropped from the active buffer
+    pub hidden: usize,
+}
+
+impl UiSnapshot {
+    pub fn of(ui: &RatatuiClient) -> Self {
+        Self {
+            active_buffer: ui.active_buffer_name(),
+            active_kind: ui.active_buffer_kind(),
+            buffer_count: ui.buffer_count(),
+            last_own_message: ui.last_own_message(),
+            ignores: ui.ignores().clone(),
+            hidden: ui.active_hidden(),
+        }
+    }
+}
+
+/// Stand-in for `RatatuiClient` inside command tasks. Reads come from the
+/// snapshot taken at submission; writes are sent back to the main loop.
+#[derive(Clone)]
+pub struct UiHandle {
+    snapshot: UiSnapshot,
+    tx: mpsc::UnboundedSender<UiUpdate>,
+}
+
+impl UiHandle {
+    pub fn new(snapshot: UiSnapshot, tx: mpsc::UnboundedSender<UiUpdate>) -> Self {
+        Self { snapshot, tx }
+    }
+
+    fn send(&self, update: UiUpdate) {
+        // The receiver only goes away when the UI is shutting down
+        let _ = self.tx.send(update);
+    }
+
+    /// Add to the buffer the command was typed in, even if the user has
+    /// switched tabs since.
+    pub fn add_message(&mut self, message: ChatMessage) {
+        self.send(UiUpdate::AddMessage(
+            self.snapshot.active_buffer.clone(),
+            message,
+        ));
+    }
+
+    pub fn add_message_to(&mut self, buffer: &str, message: ChatMessage, max_messages: usize) {
+        self.send(UiUpdate::AddMessageTo(
+            buffer.to_string(),
+            message,
+            max_messages,
+        ));
+    }
+
+    pub fn set_status(&mut self, status: String, is_error: bool) {
+        self.send(UiUpdate::Status(status, is_error));
+    }
+
+    pub fn active_buffer_name(&self) -> String {
+        self.snapshot.active_buffer.clone()
+    }
+
+    pub fn active_buffer_kind(&self) -> BufferKind {
+        self.snapshot.active_kind
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.snapshot.buffer_count
+    }
+
+    pub fn last_own_message(&self) -> Option<u64> {
+        self.snapshot.last_own_message
+    }
+
+    pub fn ignores(&self) -> &IgnoreList {
+        &self.snapshot.ignores
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.snapshot.hidden
+    }
+
+    pub fn ignore(&mut self, rule: IgnoreRule) {
+        self.send(UiUpdate::Ignore(rule));
+    }
+
+    pub fn unignore(&mut self, rule: IgnoreRule) {
+        self.send(UiUpdate::Unignore(rule));
+    }
+
+    pub fn set_away(&mut self, reason: Option<String>) {
+        self.send(UiUpdate::Away(reason));
+    }
+
+    pub fn open_buffer(&mut self, name: &str) {
+        self.snapshot.active_buffer = name.to_string();
+        self.snapshot.active_kind = BufferKind::for_name(name);
+        self.send(UiUpdate::OpenBuffer(name.to_string()));
+    }
+
+    pub fn close_buffer(&mut self, name: &str) {
+        self.send(UiUpdate::CloseBuffer(name.to_string()));
+    }
+
+    pub fn start_prompt(&mut self, interaction: PendingInteraction) {
+        self.send(UiUpdate::Prompt(interaction));
+    }
+
+    pub fn start_search(&mut self, query: SearchQuery) {
+        self.send(UiUpdate::StartSearch(query));
+    }
+
+    pub fn show_links(&mut self) {
+        self.send(UiUpdate::ShowLinks);
+    }
+
+    pub fn set_topic(&mut self, topic: RoomTopic) {
+        self.send(UiUpdate::Topic(topic));
+    }
+
+    pub fn set_nickname(&mut self, nickname: &str) {
+        self.send(UiUpdate::Nickname(nickname.to_string()));
+    }
+
+    pub fn quit(&mut self) {
+        self.send(UiUpdate::Quit);
+    }
+
+    pub fn finish(&mut self, timed_out: bool) {
+        self.send(UiUpdate::CommandFinished { timed_out });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_follows_the_buffer_it_was_typed_in() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut ui = UiHandle::new(
+            UiSnapshot {
+                active_buffer: "#general".to_string(),
+                active_kind: BufferKind::Room,
+                buffer_count: 1,
+                last_own_message: None,
+                ignores: IgnoreList::default(),
+                hidden: 0,
+            },
+            tx,
+        );
+
+        ui.add_message(ChatMessage::default());
+        ui.open_buffer("alice");
+        assert_eq!(ui.active_buffer_name(), "alice");
+        assert_eq!(ui.active_buffer_kind(), BufferKind::Query);
+        ui.add_message(ChatMessage::default());
+
+        ui.finish(false);
+        assert!(matches!(rx.try_recv(), Ok(UiUpdate::AddMessage(name, _)) if name == "#general"));
+        assert!(matches!(rx.try_recv(), Ok(UiUpdate::OpenBuffer(name)) if name == "alice"));
+        assert!(matches!(rx.try_recv(), Ok(UiUpdate::AddMessage(name, _)) if name == "alice"));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(UiUpdate::CommandFinished { timed_out: false })
+        ));
+    }
+}
@@ -1,27 +1,220 @@
+use crate::logging;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use capnweb_core::CapId;
 use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{Mutex, mpsc};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
-const DEFAULT_BACKEND: &str = "ws://localhost:8787";
-const CHAT_CAP_ID: u64 = 2;
+/// Room every session starts in; messages without a room belong here.
+pub const DEFAULT_ROOM: &str = "#general";
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+/// How a message is meant to be read. Clients may send the first three;
+/// `System` and `Error` come from the server (or the client itself).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    #[default]
+    Normal,
+    /// `/me waves`
+    Action,
+    Notice,
+    /// Joins, parts, nick changes and other events
+    System,
+    Error,
+}
+
+impl MessageKind {
+    pub fn is_normal(&self) -> bool {
+        *self == MessageKind::Normal
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
+    /// Server-assigned id; direct messages have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
     pub from: String,
     pub body: String,
     pub timestamp: u64,
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// Recipient nickname when this is a direct message rather than a room message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// When the author last edited the message.
+    #[serde(default, rename = "editedAt", skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<u64>,
+    /// Id of the message this one replies to.
+    #[serde(default, rename = "replyTo", skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+    #[serde(default, skip_serializing_if = "MessageKind::is_normal")]
+    pub kind: MessageKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>,
+}
+
+/// A finished upload as messages refer to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: u64,
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+}
+
+/// An upload the server has agreed to take, sent in chunks of `chunk_size`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadTicket {
+    pub upload: u64,
+    pub chunk_size: usize,
 }
 
+/// One stored piece of an attachment, `chunk` of `chunks`.
+#[derive(Debug, Clone)]
+pub struct AttachmentChunk {
+    pub attachment: Attachment,
+    pub chunks: u64,
+    pub data: Vec<u8>,
+}
+
+/// One emoji on a message and the nicknames that reacted with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub users: Vec<String>,
+}
+
+/// The full reaction list of a message after someone reacted or unreacted.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RpcRequest {
-    pub method: String,
-    pub args: Vec<Value>,
+pub struct ReactionUpdate {
     pub id: u64,
+    pub room: String,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+/// A room message removed by its author.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedMessage {
+    pub id: u64,
+    pub room: String,
+}
+
+/// Someone typing in a room; it lapses unless repeated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypingEvent {
+    pub nickname: String,
+    pub room: String,
+}
+
+/// A nick that joined or left a room we are in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomMemberEvent {
+    pub nickname: String,
+    pub room: String,
+}
+
+/// One user coming online, going offline or changing their away flag.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceChange {
+    pub nickname: String,
+    pub online: bool,
+    #[serde(default)]
+    pub away: bool,
+    /// Set along with `away`
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// One connected user as reported by the server's presence snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresenceEntry {
+    pub nickname: String,
+    pub identified: bool,
+    #[serde(default)]
+    pub away: bool,
+    pub last_active: u64,
+    #[serde(default)]
+    pub rooms: Vec<String>,
+}
+
+/// A room's topic and who set it; `topic` is None when there is none.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomTopic {
+    pub room: String,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub set_by: Option<String>,
+    #[serde(default)]
+    pub set_at: Option<u64>,
+}
+
+/// A ban or mute on a nick or `*`/`?` pattern in one room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Restriction {
+    /// `ban` or `mute`
+    pub kind: String,
+    pub mask: String,
+    pub set_by: String,
+    pub set_at: u64,
+    /// None for permanent restrictions
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// We were removed from a room by a kick or a ban.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KickEvent {
+    pub room: String,
+    pub by: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// A room as reported by `listRooms`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub name: String,
+    pub members: u64,
+    pub joined: bool,
+}
+
+/// Server-initiated calls delivered on the exported client capability.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    Message(ChatMessage),
+    DirectMessage(ChatMessage),
+    MessageEdited(ChatMessage),
+    MessageDeleted(DeletedMessage),
+    Reaction(ReactionUpdate),
+    Presence(Vec<PresenceEntry>),
+    Typing(TypingEvent),
+    UserJoined(RoomMemberEvent),
+    UserLeft(RoomMemberEvent),
+    PresenceChanged(PresenceChange),
+    TopicChanged(RoomTopic),
+    Kicked(KickEvent),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,55 +227,103 @@ pub struct RpcResponse {
 // Local RPC target that the server can call (similar to ChatClient in TypeScript)
 #[derive(Clone)]
 pub struct ChatClient {
-    pub on_message: Arc<Mutex<Option<Box<dyn Fn(ChatMessage) + Send + Sync>>>>,
+    event_tx: mpsc::UnboundedSender<ServerEvent>,
 }
 
 impl ChatClient {
-    pub fn new() -> Self {
-        Self {
-            on_message: Arc::new(Mutex::new(None)),
-        }
+    pub fn new(event_tx: mpsc::UnboundedSender<ServerEvent>) -> Self {
+        Self { event_tx }
     }
 
-    pub async fn set_on_message<F>(&self, callback: F)
-    where
-        F: Fn(ChatMessage) + Send + Sync + 'static,
-    {
-        let mut handler = self.on_message.lock().await;
-        *handler = Some(Box::new(callback));
+    // Dispatch a server-initiated call: ["pipeline", exportId, [method], [args]]
+    pub fn handle_call(&self, method: &str, args: &[Value]) -> Result<(), String> {
+        let event = match method {
+            "receiveMessage" => ServerEvent::Message(first_arg(method, args)?),
+            "receiveDirectMessage" => ServerEvent::DirectMessage(first_arg(method, args)?),
+            "receiveMessageEdited" => ServerEvent::MessageEdited(first_arg(method, args)?),
+            "receiveMessageDeleted" => ServerEvent::MessageDeleted(first_arg(method, args)?),
+            "receiveReaction" => ServerEvent::Reaction(first_arg(method, args)?),
+            "receivePresence" => ServerEvent::Presence(first_arg(method, args)?),
+            "userTyping" => ServerEvent::Typing(first_arg(method, args)?),
+            "userJoined" => ServerEvent::UserJoined(first_arg(method, args)?),
+            "userLeft" => ServerEvent::UserLeft(first_arg(method, args)?),
+            "presenceChanged" => ServerEvent::PresenceChanged(first_arg(method, args)?),
+            "topicChanged" => ServerEvent::TopicChanged(first_arg(method, args)?),
+            "kicked" => ServerEvent::Kicked(first_arg(method, args)?),
+            other => return Err(format!("unknown client method `{}`", other)),
+        };
+        self.event_tx
+            .send(event)
+            .map_err(|_| "event receiver dropped".to_string())
     }
+}
 
-    // This method will be called by the server via RPC
-    pub async fn receive_message(&self, message: ChatMessage) {
-        let handler = self.on_message.lock().await;
-        if let Some(ref callback) = *handler {
-            callback(message);
-        }
-        // Note: Messages are handled via the message_rx channel instead of callbacks
+fn first_arg<T: DeserializeOwned>(method: &str, args: &[Value]) -> Result<T, String> {
+    let value = args
+        .first()
+        .ok_or_else(|| format!("`{}` expects one argument", method))?;
+    decode(value.clone()).map_err(|err| format!("`{}` argument is malformed: {}", method, err))
+}
+
+// Split ["pipeline", exportId, [method], [args]] into the method name and its
+// unescaped arguments.
+fn pipeline_call(pipeline: &Value) -> Option<(&str, Vec<Value>)> {
+    let pipeline = pipeline.as_array()?;
+    if pipeline.len() < 4 || pipeline[0].as_str() != Some("pipeline") {
+        return None;
+    }
+    let method = pipeline[2].as_array()?.first()?.as_str()?;
+    match unescape_arrays(pipeline[3].clone()) {
+        Value::Array(args) => Some((method, args)),
+        _ => None,
+    }
+}
+
+// Cap'n Web escapes every array inside a value as `[[...]]` so it can't be
+// mistaken for a special form like `["bytes", ...]`; undo that, leaving the
+// special forms alone.
+fn unescape_arrays(value: Value) -> Value {
+    match value {
+        Value::Array(mut items) if items.len() == 1 && items[0].is_array() => match items.pop() {
+            Some(Value::Array(inner)) => {
+                Value::Array(inner.into_iter().map(unescape_arrays).collect())
+            }
+            _ => unreachable!("checked above"),
+        },
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key, unescape_arrays(value)))
+                .collect(),
+        ),
+        other => other,
     }
 }
 
+/// Deserialize a value received over Cap'n Web.
+fn decode<T: DeserializeOwned>(value: Value) -> Result<T, serde_json::Error> {
+    serde_json::from_value(unescape_arrays(value))
+}
+
+type PendingRequests = Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<RpcResponse>>>>;
+
 pub struct WebSocketClient {
-    client: ChatClient,
     request_id: Arc<Mutex<u64>>,
-    pending_requests: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<RpcResponse>>>>,
-    message_tx: mpsc::UnboundedSender<ChatMessage>,
-    message_rx: Arc<Mutex<mpsc::UnboundedReceiver<ChatMessage>>>,
+    pending_requests: PendingRequests,
+    event_rx: Arc<Mutex<mpsc::UnboundedReceiver<ServerEvent>>>,
     request_tx: mpsc::UnboundedSender<Value>,
 }
 
 impl WebSocketClient {
     pub async fn new(url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let client = ChatClient::new();
-        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
         let (request_tx, mut request_rx) = mpsc::unbounded_channel();
+        let local_client = ChatClient::new(event_tx);
 
         let client = Self {
-            client,
             request_id: Arc::new(Mutex::new(0)),
             pending_requests: Arc::new(Mutex::new(HashMap::new())),
-            message_tx,
-            message_rx: Arc::new(Mutex::new(message_rx)),
+            event_rx: Arc::new(Mutex::new(event_rx)),
             request_tx,
         };
 
@@ -91,130 +332,81 @@ impl WebSocketClient {
         let (mut ws_sink, mut ws_stream) = ws_stream.split();
 
         // Spawn task to handle incoming messages
-        let request_id = client.request_id.clone();
         let pending_requests = client.pending_requests.clone();
-        let local_client = client.client.clone();
-        let message_tx = client.message_tx.clone();
         let request_tx_for_incoming = client.request_tx.clone();
 
         tokio::spawn(async move {
             while let Some(msg) = ws_stream.next().await {
                 match msg {
                     Ok(Message::Text(text)) => {
-                        if let Ok(json_msg) = serde_json::from_str::<Value>(&text) {
-                            // Handle Cap'n Web RPC responses
-                            if let Some(array) = json_msg.as_array() {
-                                if array.len() >= 2 {
-                                    match array[0].as_str() {
-                                        Some("resolve") => {
-                                            // This is a resolve response: ["resolve", importId, value]
-                                            if array.len() >= 3 {
-                                                let import_id = array[1].as_u64().unwrap_or(0);
-                                                let result = &array[2];
-                                                let response = RpcResponse {
-                                                    result: Some(result.clone()),
-                                                    error: None,
-                                                    id: import_id,
-                                                };
-                                                let mut pending = pending_requests.lock().await;
-                                                if let Some(tx) = pending.remove(&import_id) {
-                                                    let _ = tx.send(response);
-                                                }
-                                            }
-                                        }
-                                        Some("reject") => {
-                                            // This is a reject response: ["reject", importId, error]
-                                            if array.len() >= 3 {
-                                                let import_id = array[1].as_u64().unwrap_or(0);
-                                                let error_value = &array[2];
-                                                let error_msg = if let Some(err_array) =
-                                                    error_value.as_array()
-                                                {
-                                                    if err_array.len() >= 2 {
-                                                        err_array[1]
-                                                            .as_str()
-                                                            .unwrap_or("Unknown error")
-                                                    } else {
-                                                        "Unknown error"
-                                                    }
-                                                } else {
-                                                    error_value.as_str().unwrap_or("Unknown error")
-                                                };
-                                                let response = RpcResponse {
-                                                    result: None,
-                                                    error: Some(error_msg.to_string()),
-                                                    id: import_id,
-                                                };
-                                                let mut pending = pending_requests.lock().await;
-                                                if let Some(tx) = pending.remove(&import_id) {
-                                                    let _ = tx.send(response);
-                                                }
-                                            }
-                                        }
-                                        Some("push") => {
-                                            // This is a server-initiated RPC call: ["push", ["pipeline", exportId, [method], [args]]]
-                                            if array.len() >= 2 {
-                                                if let Some(pipeline) = array[1].as_array() {
-                                                    if pipeline.len() >= 4
-                                                        && pipeline[0].as_str() == Some("pipeline")
-                                                    {
-                                                        let method = pipeline[2]
-                                                            .as_array()
-                                                            .and_then(|m| m.get(0))
-                                                            .and_then(Value::as_str);
-                                                        let args = pipeline[3].as_array();
-
-                                                        if let Some("receiveMessage") = method {
-                                                            if let Some(args_array) = args {
-                                                                if let Some(msg_data) =
-                                                                    args_array.get(0)
-                                                                {
-                                                                    if let Ok(chat_message) =
-                                                                        serde_json::from_value::<
-                                                                            ChatMessage,
-                                                                        >(
-                                                                            msg_data.clone()
-                                                                        )
-                                                                    {
-                                                                        local_client
-                                                                            .receive_message(
-                                                                                chat_message
-                                                                                    .clone(),
-                                                                            )
-                                                                            .await;
-                                                                        let _ = message_tx
-                                                                            .send(chat_message);
-                                                                    }
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        Some("pull") => {
-                                            // Server is requesting a value - we need to respond
-                                            if array.len() >= 2 {
-                                                let pull_id = array[1].as_u64().unwrap_or(0);
-                                                // Respond with a resolve message: ["resolve", pullId, null]
-                                                // The server is pulling the return value from a method call
-                                                let resolve_msg = json!(["resolve", pull_id, null]);
-                                                let _ = request_tx_for_incoming.send(resolve_msg);
-                                            }
-                                        }
-                                        _ => {
-                                            // Silently ignore unknown message types
-                                        }
+                        let Ok(Value::Array(array)) = serde_json::from_str::<Value>(&text) else {
+                            continue;
+                        };
+                        if array.len() < 2 {
+                            continue;
+                        }
+                        // Handle Cap'n Web RPC responses
+                        match array[0].as_str() {
+                            Some("resolve") if array.len() >= 3 => {
+                                // This is a resolve response: ["resolve", importId, value]
+                                let import_id = array[1].as_u64().unwrap_or(0);
+                                let response = RpcResponse {
+                                    result: Some(array[2].clone()),
+                                    error: None,
+                                    id: import_id,
+                                };
+                                let mut pending = pending_requests.lock().await;
+                                if let Some(tx) = pending.remove(&import_id) {
+                                    let _ = tx.send(response);
+                                }
+                            }
+                            Some("reject") if array.len() >= 3 => {
+                                // This is a reject response: ["reject", importId, error]
+                                let import_id = array[1].as_u64().unwrap_or(0);
+                                let error_value = &array[2];
+                                let error_msg = match error_value.as_array() {
+                                    Some(err_array) if err_array.len() >= 2 => {
+                                        err_array[1].as_str().unwrap_or("Unknown error")
                                     }
+                                    Some(_) => "Unknown error",
+                                    None => error_value.as_str().unwrap_or("Unknown error"),
+                                };
+                                let response = RpcResponse {
+                                    result: None,
+                                    error: Some(error_msg.to_string()),
+                                    id: import_id,
+                                };
+                                let mut pending = pending_requests.lock().await;
+                                if let Some(tx) = pending.remove(&import_id) {
+                                    let _ = tx.send(response);
                                 }
                             }
+                            Some("push") => {
+                                // This is a server-initiated RPC call: ["push", ["pipeline", exportId, [method], [args]]]
+                                if let Some((method, args)) = pipeline_call(&array[1]) {
+                                    // Unknown or malformed calls are ignored; the pull
+                                    // that follows still resolves to null.
+                                    let _ = local_client.handle_call(method, &args);
+                                }
+                            }
+                            Some("pull") => {
+                                // Server is requesting a value - we need to respond
+                                let pull_id = array[1].as_u64().unwrap_or(0);
+                                // Respond with a resolve message: ["resolve", pullId, null]
+                                // The server is pulling the return value from a method call
+                                let resolve_msg = json!(["resolve", pull_id, null]);
+                                let _ = request_tx_for_incoming.send(resolve_msg);
+                            }
+                            _ => {
+                                // Silently ignore unknown message types
+                            }
                         }
                     }
                     Ok(Message::Close(_)) => {
                         break;
                     }
                     Err(e) => {
-                        eprintln!("WebSocket error: {}", e);
+                        logging::log_error!("WebSocket error: {}", e);
                         break;
                     }
                     _ => {}
@@ -223,13 +415,12 @@ impl WebSocketClient {
         });
 
         // Spawn task to handle outgoing messages
-        let request_tx_clone = client.request_tx.clone();
         tokio::spawn(async move {
             while let Some(request) = request_rx.recv().await {
                 let message_text = serde_json::to_string(&request).unwrap_or_default();
                 let message = Message::Text(message_text);
                 if let Err(e) = ws_sink.send(message).await {
-                    eprintln!("Failed to send WebSocket message: {}", e);
+                    logging::log_error!("Failed to send WebSocket message: {}", e);
                     break;
                 }
             }
@@ -305,48 +496,555 @@ impl WebSocketClient {
         Ok(CapId::new(id))
     }
 
-    pub async fn send_message(
+    /// Ask the server to remember `token` for this session's identity.
+    pub async fn store_nick_token(
         &self,
         capability: CapId,
+        token: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.call(
+            "storeNickToken",
+            vec![json!(capability.as_u64()), json!(token)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Start a session from a token stored earlier, instead of `auth`.
+    /// Returns the new session capability and the identified nickname.
+    pub async fn redeem_nick_token(
+        &self,
+        token: &str,
+    ) -> Result<(CapId, String), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self.call("redeemNickToken", vec![json!(token)]).await?;
+
+        if response.get("status").and_then(Value::as_str) != Some("ok") {
+            let message = response
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("Token not accepted");
+            return Err(message.to_string().into());
+        }
+
+        let id = response
+            .get("session")
+            .and_then(|session| session.get("id"))
+            .and_then(Value::as_u64)
+            .ok_or("Token response missing session capability")?;
+        let nickname = response
+            .get("nickname")
+            .and_then(Value::as_str)
+            .ok_or("Token response missing nickname")?;
+
+        Ok((CapId::new(id), nickname.to_string()))
+    }
+
+    /// Send a message of `kind` to `room`.
+    pub async fn send_room_message(
+        &self,
+        capability: CapId,
+        room: &str,
+        message: &str,
+        kind: MessageKind,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut args = vec![json!(capability.as_u64()), json!(room), json!(message)];
+        if !kind.is_normal() {
+            args.push(json!({ "kind": kind }));
+        }
+        self.call("sendRoomMessage", args).await?;
+        Ok(())
+    }
+
+    /// Send a message to `room` as a reply to message `parent`.
+    pub async fn send_reply(
+        &self,
+        capability: CapId,
+        room: &str,
+        parent: u64,
         message: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.call(
-            "sendMessage",
-            vec![json!(capability.as_u64()), json!(message)],
+            "sendRoomMessage",
+            vec![
+                json!(capability.as_u64()),
+                json!(room),
+                json!(message),
+                json!({ "replyTo": parent }),
+            ],
         )
         .await?;
         Ok(())
     }
 
-    pub async fn receive_messages(
+    pub async fn receive_room_messages(
         &self,
         capability: CapId,
+        room: &str,
     ) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error + Send + Sync>> {
         let response = self
-            .call("receiveMessages", vec![json!(capability.as_u64())])
+            .call(
+                "receiveRoomMessages",
+                vec![json!(capability.as_u64()), json!(room)],
+            )
             .await?;
 
-        let messages = response
-            .get("messages")
-            .and_then(Value::as_array)
-            .ok_or("Response missing messages array")?;
-
-        let mut result = Vec::new();
-        for msg in messages {
-            // Handle nested array - messages might be wrapped in another array
-            if let Some(msg_array) = msg.as_array() {
-                for nested_msg in msg_array {
-                    if let Ok(chat_msg) = serde_json::from_value::<ChatMessage>(nested_msg.clone())
-                    {
-                        result.push(chat_msg);
-                    }
-                }
-            } else if let Ok(chat_msg) = serde_json::from_value::<ChatMessage>(msg.clone()) {
-                result.push(chat_msg);
-            }
+        parse_messages(&response)
+    }
+
+    /// Search a room's stored history on the server for a plain substring.
+    /// The server caps the number of results, newest last.
+    pub async fn search_messages(
+        &self,
+        capability: CapId,
+        room: &str,
+        query: &str,
+        case_sensitive: bool,
+    ) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "searchMessages",
+                vec![
+                    json!(capability.as_u64()),
+                    json!(room),
+                    json!(query),
+                    json!({ "caseSensitive": case_sensitive }),
+                ],
+            )
+            .await?;
+
+        parse_messages(&response)
+    }
+
+    /// Replace the body of one of our own room messages.
+    pub async fn edit_message(
+        &self,
+        capability: CapId,
+        id: u64,
+        body: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "editMessage",
+                vec![json!(capability.as_u64()), json!(id), json!(body)],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Remove one of our own room messages.
+    pub async fn delete_message(
+        &self,
+        capability: CapId,
+        id: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("deleteMessage", vec![json!(capability.as_u64()), json!(id)])
+            .await?;
+        status_result(&response)
+    }
+
+    /// Add our `emoji` reaction to message `id`.
+    pub async fn react(
+        &self,
+        capability: CapId,
+        id: u64,
+        emoji: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "react",
+                vec![json!(capability.as_u64()), json!(id), json!(emoji)],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Take back our `emoji` reaction to message `id`.
+    pub async fn unreact(
+        &self,
+        capability: CapId,
+        id: u64,
+        emoji: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "unreact",
+                vec![json!(capability.as_u64()), json!(id), json!(emoji)],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Tell the other members of `room` that we are typing.
+    pub async fn typing(
+        &self,
+        capability: CapId,
+        room: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("typing", vec![json!(capability.as_u64()), json!(room)])
+            .await?;
+        status_result(&response)
+    }
+
+    /// Mark this session away with `reason`, or back with None.
+    pub async fn set_away(
+        &self,
+        capability: CapId,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("setAway", vec![json!(capability.as_u64()), json!(reason)])
+            .await?;
+        status_result(&response)
+    }
+
+    /// Send a private message delivered only to `nickname`'s session.
+    pub async fn send_direct_message(
+        &self,
+        capability: CapId,
+        nickname: &str,
+        message: &str,
+        kind: MessageKind,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut args = vec![json!(capability.as_u64()), json!(nickname), json!(message)];
+        if !kind.is_normal() {
+            args.push(json!({ "kind": kind }));
+        }
+        let response = self.call("sendDirectMessage", args).await?;
+
+        let status = response
+            .get("status")
+            .and_then(Value::as_str)
+            .ok_or("Response missing status")?;
+
+        if status == "ok" {
+            Ok(())
+        } else {
+            let message = response
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("No message");
+            Err(message.to_string().into())
+        }
+    }
+
+    /// Join (creating if needed) a room; returns the server's normalized name.
+    pub async fn join_room(
+        &self,
+        capability: CapId,
+        room: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("joinRoom", vec![json!(capability.as_u64()), json!(room)])
+            .await?;
+
+        let room = response
+            .get("room")
+            .and_then(Value::as_str)
+            .ok_or("Response missing room")?;
+
+        Ok(room.to_string())
+    }
+
+    pub async fn get_topic(
+        &self,
+        capability: CapId,
+        room: &str,
+    ) -> Result<RoomTopic, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("getTopic", vec![json!(capability.as_u64()), json!(room)])
+            .await?;
+        status_result(&response)?;
+        Ok(decode(response)?)
+    }
+
+    /// Set the topic of `room`; an empty topic clears it.
+    pub async fn set_topic(
+        &self,
+        capability: CapId,
+        room: &str,
+        topic: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "setTopic",
+                vec![json!(capability.as_u64()), json!(room), json!(topic)],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    pub async fn kick(
+        &self,
+        capability: CapId,
+        room: &str,
+        nickname: &str,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "kick",
+                vec![
+                    json!(capability.as_u64()),
+                    json!(room),
+                    json!(nickname),
+                    json!(reason),
+                ],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Ban or mute (`method`) everyone matching `mask` in `room`, for
+    /// `duration` or until lifted.
+    pub async fn restrict(
+        &self,
+        capability: CapId,
+        method: &str,
+        room: &str,
+        mask: &str,
+        duration: Option<Duration>,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut options = serde_json::Map::new();
+        if let Some(duration) = duration {
+            options.insert("durationSecs".to_string(), json!(duration.as_secs()));
+        }
+        if let Some(reason) = reason {
+            options.insert("reason".to_string(), json!(reason));
         }
+        let response = self
+            .call(
+                method,
+                vec![
+                    json!(capability.as_u64()),
+                    json!(room),
+                    json!(mask),
+                    Value::Object(options),
+                ],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Lift a ban or mute (`method` is `unban` or `unmute`).
+    pub async fn unrestrict(
+        &self,
+        capability: CapId,
+        method: &str,
+        room: &str,
+        mask: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                method,
+                vec![json!(capability.as_u64()), json!(room), json!(mask)],
+            )
+            .await?;
+        status_result(&response)
+    }
 
-        Ok(result)
+    pub async fn list_restrictions(
+        &self,
+        capability: CapId,
+        room: &str,
+    ) -> Result<Vec<Restriction>, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "listRestrictions",
+                vec![json!(capability.as_u64()), json!(room)],
+            )
+            .await?;
+        status_result(&response)?;
+        let restrictions = response
+            .get("restrictions")
+            .cloned()
+            .ok_or("Response missing restrictions")?;
+        Ok(decode(restrictions)?)
+    }
+
+    /// Announce an upload of `size` bytes; the server answers with the
+    /// upload id that the chunks are sent to.
+    pub async fn begin_upload(
+        &self,
+        capability: CapId,
+        name: &str,
+        size: u64,
+        mime: &str,
+    ) -> Result<UploadTicket, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "beginUpload",
+                vec![
+                    json!(capability.as_u64()),
+                    json!({ "name": name, "size": size, "mime": mime }),
+                ],
+            )
+            .await?;
+        status_result(&response)?;
+        Ok(decode(response)?)
+    }
+
+    pub async fn upload_chunk(
+        &self,
+        capability: CapId,
+        upload: u64,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "uploadChunk",
+                vec![json!(capability.as_u64()), json!(upload), bytes_value(data)],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Store a fully sent upload and get the attachment to send with a message.
+    pub async fn finish_upload(
+        &self,
+        capability: CapId,
+        upload: u64,
+    ) -> Result<Attachment, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "finishUpload",
+                vec![json!(capability.as_u64()), json!(upload)],
+            )
+            .await?;
+        status_result(&response)?;
+        let attachment = response
+            .get("attachment")
+            .cloned()
+            .ok_or("Response missing attachment")?;
+        Ok(decode(attachment)?)
+    }
+
+    pub async fn download_chunk(
+        &self,
+        capability: CapId,
+        id: u64,
+        chunk: u64,
+    ) -> Result<AttachmentChunk, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "downloadAttachment",
+                vec![json!(capability.as_u64()), json!(id), json!(chunk)],
+            )
+            .await?;
+        status_result(&response)?;
+        let attachment = response
+            .get("attachment")
+            .cloned()
+            .ok_or("Response missing attachment")?;
+        let chunks = response
+            .get("chunks")
+            .and_then(Value::as_u64)
+            .ok_or("Response missing chunk count")?;
+        let data = response
+            .get("data")
+            .and_then(bytes_from)
+            .ok_or("Response missing chunk data")?;
+        Ok(AttachmentChunk {
+            attachment: decode(attachment)?,
+            chunks,
+            data,
+        })
+    }
+
+    /// Post an attachment to a `#room`, or to a nick as a direct message,
+    /// with its file name as the body.
+    pub async fn send_attachment(
+        &self,
+        capability: CapId,
+        target: &str,
+        attachment: &Attachment,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let method = if target.starts_with('#') {
+            "sendRoomMessage"
+        } else {
+            "sendDirectMessage"
+        };
+        let response = self
+            .call(
+                method,
+                vec![
+                    json!(capability.as_u64()),
+                    json!(target),
+                    json!(attachment.name),
+                    json!({ "attachment": attachment.id }),
+                ],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    /// Give `nickname` a role in `room`: owner, operator, voice or user.
+    pub async fn set_role(
+        &self,
+        capability: CapId,
+        room: &str,
+        nickname: &str,
+        role: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call(
+                "setRole",
+                vec![
+                    json!(capability.as_u64()),
+                    json!(room),
+                    json!(nickname),
+                    json!(role),
+                ],
+            )
+            .await?;
+        status_result(&response)
+    }
+
+    pub async fn part_room(
+        &self,
+        capability: CapId,
+        room: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("partRoom", vec![json!(capability.as_u64()), json!(room)])
+            .await?;
+
+        let status = response
+            .get("status")
+            .and_then(Value::as_str)
+            .ok_or("Response missing status")?;
+
+        if status == "ok" {
+            let room = response
+                .get("room")
+                .and_then(Value::as_str)
+                .ok_or("Response missing room")?;
+            Ok(room.to_string())
+        } else {
+            let message = response
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("No message");
+            Err(format!("Part failed: {}", message).into())
+        }
+    }
+
+    pub async fn list_rooms(
+        &self,
+        capability: CapId,
+    ) -> Result<Vec<RoomInfo>, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("listRooms", vec![json!(capability.as_u64())])
+            .await?;
+
+        let rooms = response
+            .get("rooms")
+            .cloned()
+            .ok_or("Response missing rooms array")?;
+
+        Ok(decode(rooms)?)
     }
 
     pub async fn whoami(
@@ -455,18 +1153,190 @@ impl WebSocketClient {
         Ok(())
     }
 
-    pub fn get_message_receiver(&self) -> Arc<Mutex<mpsc::UnboundedReceiver<ChatMessage>>> {
-        self.message_rx.clone()
+    pub async fn list_users(
+        &self,
+        capability: CapId,
+    ) -> Result<Vec<PresenceEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self
+            .call("listUsers", vec![json!(capability.as_u64())])
+            .await?;
+
+        let users = response
+            .get("users")
+            .cloned()
+            .ok_or("Response missing users array")?;
+
+        Ok(decode(users)?)
+    }
+
+    pub fn get_event_receiver(&self) -> Arc<Mutex<mpsc::UnboundedReceiver<ServerEvent>>> {
+        self.event_rx.clone()
+    }
+}
+
+// Cap'n Web sends a Uint8Array as `["bytes", <base64>]`.
+fn bytes_value(data: &[u8]) -> Value {
+    json!(["bytes", BASE64.encode(data)])
+}
+
+fn bytes_from(value: &Value) -> Option<Vec<u8>> {
+    match value.as_array()?.as_slice() {
+        [tag, Value::String(encoded)] if tag == "bytes" => BASE64.decode(encoded).ok(),
+        _ => None,
     }
+}
 
-    pub fn get_client(&self) -> &ChatClient {
-        &self.client
+// `Ok` for `{status: "ok"}`, otherwise the server's message as the error.
+fn status_result(response: &Value) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let status = response
+        .get("status")
+        .and_then(Value::as_str)
+        .ok_or("Response missing status")?;
+
+    if status == "ok" {
+        Ok(())
+    } else {
+        let message = response
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("No message");
+        Err(message.to_string().into())
     }
 }
 
-// Create WebSocket session similar to TypeScript newWebSocketRpcSession
-pub async fn create_websocket_session(
-    url: &str,
-) -> Result<WebSocketClient, Box<dyn std::error::Error + Send + Sync>> {
-    WebSocketClient::new(url).await
+fn parse_messages(
+    response: &Value,
+) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error + Send + Sync>> {
+    let messages = response
+        .get("messages")
+        .cloned()
+        .ok_or("Response missing messages array")?;
+
+    Ok(decode(messages)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a raw push frame through the same path as the socket reader.
+    fn push_event(frame: &str) -> ServerEvent {
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let frame: Value = serde_json::from_str(frame).unwrap();
+        let (method, args) = pipeline_call(&frame[1]).expect("pipeline call");
+        ChatClient::new(event_tx)
+            .handle_call(method, &args)
+            .unwrap();
+        event_rx.try_recv().unwrap()
+    }
+
+    #[test]
+    fn escaped_presence_push_decodes() {
+        let frame = r##"["push",["pipeline",0,["receivePresence"],[[[[
+            {"nickname":"alice","identified":true,"away":false,"lastActive":5,"rooms":[["#general","#rust"]]},
+            {"nickname":"bob","identified":false,"lastActive":7,"rooms":[[]]}
+        ]]]]]]"##;
+        let ServerEvent::Presence(users) = push_event(frame) else {
+            panic!("expected a presence event");
+        };
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].rooms, vec!["#general", "#rust"]);
+        assert!(users[1].rooms.is_empty());
+    }
+
+    #[test]
+    fn escaped_reactions_survive_history_and_pushes() {
+        let response = json!({"status": "ok", "messages": [[
+            {"id": 1, "from": "alice", "body": "hi", "timestamp": 1, "room": "#general",
+             "reactions": [[{"emoji": "👍", "users": [["alice", "bob"]]}]]},
+            {"id": 2, "from": "bob", "body": "yo", "timestamp": 2, "room": "#general"}
+        ]]});
+        let messages = parse_messages(&response).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].reactions,
+            vec![Reaction {
+                emoji: "👍".to_string(),
+                users: vec!["alice".to_string(), "bob".to_string()],
+            }]
+        );
+
+        let frame = r##"["push",["pipeline",0,["receiveReaction"],[[
+            {"id":1,"room":"#general","reactions":[[{"emoji":"🎉","users":[["carol"]]}]]}
+        ]]]]"##;
+        let ServerEvent::Reaction(update) = push_event(frame) else {
+            panic!("expected a reaction event");
+        };
+        assert_eq!(update.reactions[0].users, vec!["carol"]);
+
+        let frame = r##"["push",["pipeline",0,["receiveMessage"],[[
+            {"id":3,"from":"carol","body":"ok","timestamp":3,"room":"#general",
+             "reactions":[[{"emoji":"👍","users":[["dave"]]}]]}
+        ]]]]"##;
+        let ServerEvent::Message(message) = push_event(frame) else {
+            panic!("expected a message event");
+        };
+        assert_eq!(message.reactions[0].users, vec!["dave"]);
+    }
+
+    #[test]
+    fn message_with_attachment_and_reaction_round_trips() {
+        let wire = json!({"id": 4, "from": "alice", "body": "notes.txt", "timestamp": 4,
+            "room": "#general",
+            "attachment": {"id": 9, "name": "notes.txt", "size": 12, "mime": "text/plain"},
+            "reactions": [[{"emoji": "👍", "users": [["bob"]]}]]});
+        let message: ChatMessage = decode(wire).unwrap();
+        let message: ChatMessage = decode(serde_json::to_value(&message).unwrap()).unwrap();
+        assert_eq!(
+            message.attachment,
+            Some(Attachment {
+                id: 9,
+                name: "notes.txt".to_string(),
+                size: 12,
+                mime: "text/plain".to_string(),
+            })
+        );
+        assert_eq!(message.reactions[0].users, vec!["bob"]);
+    }
+
+    #[test]
+    fn escaped_user_list_decodes() {
+        let users: Vec<PresenceEntry> = decode(json!([[
+            {"nickname": "alice", "identified": true, "lastActive": 5, "rooms": [["#general"]]}
+        ]]))
+        .unwrap();
+        assert_eq!(users[0].nickname, "alice");
+        assert_eq!(users[0].rooms, vec!["#general"]);
+    }
+
+    #[test]
+    fn escaped_room_list_decodes() {
+        let rooms: Vec<RoomInfo> = decode(json!([[
+            {"name": "#general", "members": 3, "joined": true},
+            {"name": "#rust", "members": 1, "joined": false}
+        ]]))
+        .unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[1].name, "#rust");
+    }
+
+    #[test]
+    fn escaped_restriction_list_decodes() {
+        let restrictions: Vec<Restriction> = decode(json!([[
+            {"kind": "ban", "mask": "troll*", "setBy": "alice", "setAt": 1},
+            {"kind": "mute", "mask": "bob", "setBy": "alice", "setAt": 2, "expiresAt": 60}
+        ]]))
+        .unwrap();
+        assert_eq!(restrictions.len(), 2);
+        assert_eq!(restrictions[1].expires_at, Some(60));
+    }
+
+    #[test]
+    fn special_forms_are_not_unescaped() {
+        let value = json!({"data": ["bytes", "aGk="], "list": [[["bytes", "aGk="]]]});
+        assert_eq!(
+            unescape_arrays(value),
+            json!({"data": ["bytes", "aGk="], "list": [["bytes", "aGk="]]})
+        );
+    }
 }
@@ -4,9 +4,30 @@ import { newWebSocketRpcSession, RpcTarget } from 'capnweb';
 const CALCULATOR_CAP_ID = 1;
 const CHAT_CAPABILITY_ID = 2;
 const SESSION_CAPABILITY_START = 10_000;
+const DEFAULT_ROOM = '#general';
+const ROOM_NAME_PATTERN = /^#[A-Za-z0-9_-]{1,32}$/;
+const SEARCH_RESULT_LIMIT = 50;
+const SEARCH_QUERY_MAX_LENGTH = 200;
+const REACTION_MAX_LENGTH = 32;
+const TOPIC_MAX_LENGTH = 300;
+const MASK_MAX_LENGTH = 64;
+const REASON_MAX_LENGTH = 200;
+const AUDIT_LOG_LIMIT = 1000;
+const AUDIT_PAGE_SIZE = 50;
+const ATTACHMENT_MAX_BYTES = 8 * 1024 * 1024;
+// Durable Object values are capped at 128 KiB, so blobs are kept in pieces
+const ATTACHMENT_CHUNK_BYTES = 64 * 1024;
+const ATTACHMENT_NAME_MAX_LENGTH = 200;
+// Each upload holds its file in memory until it is finished
+const UPLOADS_PER_SESSION = 2;
+const UPLOAD_IDLE_MS = 60 * 1000;
+const MIME_PATTERN = /^[a-z0-9][a-z0-9!#$&^_.+-]*\/[a-z0-9][a-z0-9!#$&^_.+-]*$/;
+const DEFAULT_MIME = 'application/octet-stream';
 
 export interface Env {
   CAPNWEB: DurableObjectNamespace;
+  // Registered nick that owns every room, for whoever runs the server
+  CHAT_OWNER?: string;
 }
 
 type DurableObjectStateWithStorage = {
@@ -16,8 +37,83 @@ type DurableObjectStateWithStorage = {
   };
 };
 
+type PresenceEntry = {
+  nickname: string;
+  identified: boolean;
+  away: boolean;
+  awayReason?: string;
+  lastActive: number;
+  rooms: string[];
+};
+
+type DirectMessage = {
+  from: string;
+  to: string;
+  body: string;
+  timestamp: number;
+  kind?: MessageKind;
+  attachment?: AttachmentRef;
+};
+
+type DeletedMessage = {
+  id: number;
+  room: string;
+};
+
+type Reaction = {
+  emoji: string;
+  users: string[];
+};
+
+type ReactionUpdate = {
+  id: number;
+  room: string;
+  reactions: Reaction[];
+};
+
+type TypingEvent = {
+  nickname: string;
+  room: string;
+};
+
+type RoomMemberEvent = {
+  nickname: string;
+  room: string;
+};
+
+type PresenceChange = {
+  nickname: string;
+  online: boolean;
+  away: boolean;
+  reason?: string;
+};
+
+type RoomTopic = {
+  room: string;
+  topic: string | null;
+  setBy?: string;
+  setAt?: number;
+};
+
+type KickEvent = {
+  room: string;
+  by: string;
+  reason?: string;
+};
+
 type ChatClientStub = {
-  receiveMessage(message: { from: string; body: string; timestamp: number }): Promise<void> | void;
+  receiveMessage(message: WireMessage): Promise<void> | void;
+  receiveMessageEdited?(message: WireMessage): Promise<void> | void;
+  receiveMessageDeleted?(message: DeletedMessage): Promise<void> | void;
+  receiveReaction?(update: ReactionUpdate): Promise<void> | void;
+  receiveDirectMessage?(message: DirectMessage): Promise<void> | void;
+  receivePresence?(users: PresenceEntry[]): Promise<void> | void;
+  userTyping?(event: TypingEvent): Promise<void> | void;
+  userJoined?(event: RoomMemberEvent): Promise<void> | void;
+  userLeft?(event: RoomMemberEvent): Promise<void> | void;
+  presenceChanged?(change: PresenceChange): Promise<void> | void;
+  topicChanged?(topic: RoomTopic): Promise<void> | void;
+  kicked?(event: KickEvent): Promise<void> | void;
   onRpcBroken?(callback: (error: unknown) => void): void;
 };
 
@@ -46,11 +142,15 @@ class ChatSession extends RpcTarget {
     console.log(`DEBUG: sendMessage called with message='${message}', currentNickname='${this.currentNickname}', username='${this.username}'`);
     const chatState = await loadChatState(this.state);
 
-    const newMessage = {
+    const newMessage: StoredMessage = {
+      id: chatState.nextMessageId,
       from: this.currentNickname,
+      author: this.username,
       body: message,
       timestamp: Date.now(),
+      room: DEFAULT_ROOM,
     };
+    chatState.nextMessageId += 1;
     console.log(`DEBUG: Created message with from='${newMessage.from}'`);
 
     chatState.messages.push(newMessage);
@@ -164,12 +264,194 @@ class ChatSession extends RpcTarget {
   }
 }
 
+// Main RPC target handed to each WebSocket peer. It forwards calls to the
+// durable object and remembers which session capabilities were issued over
+// this socket so presence can be tracked per connection.
+class ChatConnection extends RpcTarget {
+  readonly sessionIds: Set<number> = new Set();
+  clientStub: ChatClientStub | null = null;
+
+  constructor(private readonly server: CapnWebDurable) {
+    super();
+  }
+
+  async auth(username: string, password: string) {
+    const result = await this.server.auth(username, password);
+    await this.server.attachSession(this, result.session.id);
+    return result;
+  }
+
+  async redeemNickToken(token: string) {
+    const result = await this.server.redeemNickToken(token);
+    if ('session' in result) {
+      await this.server.attachSession(this, result.session.id);
+    }
+    return result;
+  }
+
+  sendMessage(capabilityId: number, message: string) {
+    return this.server.sendMessage(capabilityId, message);
+  }
+
+  receiveMessages(capabilityId: number) {
+    return this.server.receiveMessages(capabilityId);
+  }
+
+  sendRoomMessage(capabilityId: number, room: string, message: string, options?: SendOptions) {
+    return this.server.sendRoomMessage(capabilityId, room, message, options);
+  }
+
+  receiveRoomMessages(capabilityId: number, room: string) {
+    return this.server.receiveRoomMessages(capabilityId, room);
+  }
+
+  searchMessages(capabilityId: number, room: string, query: string, options?: SearchOptions) {
+    return this.server.searchMessages(capabilityId, room, query, options);
+  }
+
+  editMessage(capabilityId: number, id: number, body: string) {
+    return this.server.editMessage(capabilityId, id, body);
+  }
+
+  deleteMessage(capabilityId: number, id: number) {
+    return this.server.deleteMessage(capabilityId, id);
+  }
+
+  react(capabilityId: number, id: number, emoji: string) {
+    return this.server.react(capabilityId, id, emoji);
+  }
+
+  unreact(capabilityId: number, id: number, emoji: string) {
+    return this.server.unreact(capabilityId, id, emoji);
+  }
+
+  sendDirectMessage(capabilityId: number, nickname: string, message: string, options?: SendOptions) {
+    return this.server.sendDirectMessage(capabilityId, nickname, message, options);
+  }
+
+  typing(capabilityId: number, room: string) {
+    return this.server.typing(capabilityId, room);
+  }
+
+  setAway(capabilityId: number, reason?: string | null) {
+    return this.server.setAway(capabilityId, reason);
+  }
+
+  beginUpload(capabilityId: number, info: UploadInfo) {
+    return this.server.beginUpload(capabilityId, info);
+  }
+
+  uploadChunk(capabilityId: number, upload: number, data: Uint8Array) {
+    return this.server.uploadChunk(capabilityId, upload, data);
+  }
+
+  finishUpload(capabilityId: number, upload: number) {
+    return this.server.finishUpload(capabilityId, upload);
+  }
+
+  downloadAttachment(capabilityId: number, id: number, chunk?: number) {
+    return this.server.downloadAttachment(capabilityId, id, chunk);
+  }
+
+  getTopic(capabilityId: number, room: string) {
+    return this.server.getTopic(capabilityId, room);
+  }
+
+  kick(capabilityId: number, room: string, nickname: string, reason?: string) {
+    return this.server.kick(capabilityId, room, nickname, reason);
+  }
+
+  ban(capabilityId: number, room: string, mask: string, options?: RestrictionOptions) {
+    return this.server.ban(capabilityId, room, mask, options);
+  }
+
+  unban(capabilityId: number, room: string, mask: string) {
+    return this.server.unban(capabilityId, room, mask);
+  }
+
+  mute(capabilityId: number, room: string, mask: string, options?: RestrictionOptions) {
+    return this.server.mute(capabilityId, room, mask, options);
+  }
+
+  unmute(capabilityId: number, room: string, mask: string) {
+    return this.server.unmute(capabilityId, room, mask);
+  }
+
+  setRole(capabilityId: number, room: string, nickname: string, role: Role) {
+    return this.server.setRole(capabilityId, room, nickname, role);
+  }
+
+  listRestrictions(capabilityId: number, room: string) {
+    return this.server.listRestrictions(capabilityId, room);
+  }
+
+  auditLog(capabilityId: number, room: string) {
+    return this.server.auditLog(capabilityId, room);
+  }
+
+  setTopic(capabilityId: number, room: string, topic: string) {
+    return this.server.setTopic(capabilityId, room, topic);
+  }
+
+  joinRoom(capabilityId: number, room: string) {
+    return this.server.joinRoom(capabilityId, room);
+  }
+
+  partRoom(capabilityId: number, room: string) {
+    return this.server.partRoom(capabilityId, room);
+  }
+
+  listRooms(capabilityId: number) {
+    return this.server.listRooms(capabilityId);
+  }
+
+  whoami(capabilityId: number) {
+    return this.server.whoami(capabilityId);
+  }
+
+  registerNick(capabilityId: number, nickname: string, password: string) {
+    return this.server.registerNick(capabilityId, nickname, password);
+  }
+
+  identifyNick(capabilityId: number, nickname: string, password: string) {
+    return this.server.identifyNick(capabilityId, nickname, password);
+  }
+
+  checkNick(capabilityId: number, nickname: string) {
+    return this.server.checkNick(capabilityId, nickname);
+  }
+
+  listUsers(capabilityId: number) {
+    return this.server.listUsers(capabilityId);
+  }
+
+  log(capabilityId: number, message: string) {
+    return this.server.log(capabilityId, message);
+  }
+
+  storeNickToken(capabilityId: number, token: string) {
+    return this.server.storeNickToken(capabilityId, token);
+  }
+}
+
 export class CapnWebDurable extends RpcTarget {
-  private clients: Set<ChatClientStub> = new Set();
+  private connections: Set<ChatConnection> = new Set();
   private sessions: Map<number, ChatSession> = new Map();
+  // Last time each session capability sent a message, used for idle times.
+  private lastActive: Map<number, number> = new Map();
+  // Away reasons by session capability; like idle times they last only as
+  // long as the connection.
+  private away: Map<number, string> = new Map();
+  // Uploads in progress by upload id. Each id is a capability for one file,
+  // good only for the session that began it and gone with its connection or
+  // after UPLOAD_IDLE_MS without a chunk.
+  private uploads: Map<number, PendingUpload> = new Map();
+  private nextUploadId = 1;
+  private readonly blobs: BlobStore;
 
   constructor(private readonly state: DurableObjectStateWithStorage, private readonly env: Env) {
     super();
+    this.blobs = new StorageBlobStore(state.storage);
     // Store reference for sessions to access
     (globalThis as any).serverInstance = this;
   }
@@ -182,10 +464,11 @@ export class CapnWebDurable extends RpcTarget {
       serverSocket.accept();
 
       console.log('Creating WebSocket RPC session');
-      const clientStub = newWebSocketRpcSession<ChatClientStub>(serverSocket, this);
+      const connection = new ChatConnection(this);
+      const clientStub = newWebSocketRpcSession<ChatClientStub>(serverSocket, connection);
       console.log('Client stub created, registering client');
-      this.registerClient(clientStub);
-      console.log('Client registered, total clients:', this.clients.size);
+      this.registerClient(connection, clientStub);
+      console.log('Client registered, total clients:', this.connections.size);
 
       return new Response(null, {
         status: 101,
@@ -198,87 +481,1141 @@ export class CapnWebDurable extends RpcTarget {
   }
 
   // RPC methods that clients can call
-  async auth(username: string, _password: string) {
+  // Without a password the session is a guest that can identify later. A
+  // password must match the registered nick it is given for, and then the
+  // session starts out identified.
+  async auth(username: string, password: string) {
     const chatState = await loadChatState(this.state);
+    const nickname = typeof username === 'string' ? username.trim() : '';
+    const identifyAs = typeof password === 'string' && password !== '' ? nickname : null;
+    if (identifyAs !== null && chatState.registeredNicks[identifyAs] !== password) {
+      throw new Error('invalid nickname or password');
+    }
 
     let sessionCapId = chatState.nextSessionCapId;
     while (chatState.sessionCaps[String(sessionCapId)]) {
       sessionCapId += 1;
     }
-    chatState.nextSessionCapId = sessionCapId + 1;
+    chatState.nextSessionCapId = sessionCapId + 1;
+
+    const storedUsername = `guest-${sessionCapId}`;
+    chatState.sessionCaps[String(sessionCapId)] = {
+      username: storedUsername,
+      ...(identifyAs !== null ? { displayName: identifyAs } : {}),
+      rooms: [DEFAULT_ROOM],
+    };
+    if (identifyAs !== null) {
+      chatState.nickOwners[identifyAs] = storedUsername;
+    }
+
+    await persistChatState(this.state, chatState);
+
+    return {
+      session: {
+        _type: 'capability',
+        id: sessionCapId,
+      },
+      user: identifyAs ?? storedUsername,
+    };
+  }
+
+  // This method will be called by clients to send messages
+  async sendMessage(capabilityId: number, message: string) {
+    return this.sendRoomMessage(capabilityId, DEFAULT_ROOM, message);
+  }
+
+  async receiveMessages(capabilityId: number) {
+    return this.receiveRoomMessages(capabilityId, DEFAULT_ROOM);
+  }
+
+  async sendRoomMessage(capabilityId: number, room: string, message: string, options: SendOptions = {}) {
+    console.log('Server sendRoomMessage called with capabilityId:', capabilityId, 'room:', room);
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+
+    // Find the user by capability ID
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    if (!sessionRooms(sessionInfo).includes(roomName)) {
+      throw new Error(`not a member of ${roomName}`);
+    }
+    const silenced = this.silenced(chatState, sessionInfo, roomName);
+    if (silenced) {
+      throw new Error(silenced);
+    }
+
+    const from = sessionInfo.displayName ?? sessionInfo.username;
+    const kind = clientMessageKind(options);
+
+    // A reply must point at a message in the same room
+    const replyTo = options.replyTo;
+    if (replyTo !== undefined) {
+      if (typeof replyTo !== 'number') {
+        throw new TypeError('replyTo must be a message id');
+      }
+      const parent = chatState.messages.find((msg) => msg.id === replyTo);
+      if (!parent || parent.room !== roomName) {
+        throw new Error(`no message with id ${replyTo} in ${roomName}`);
+      }
+    }
+
+    const attachment = attachmentOption(chatState, sessionInfo, options);
+    if (attachment) {
+      attachment.room = roomName;
+    }
+
+    const newMessage: StoredMessage = {
+      id: chatState.nextMessageId,
+      from,
+      author: sessionInfo.username,
+      body: message,
+      timestamp: Date.now(),
+      room: roomName,
+      ...(replyTo !== undefined ? { replyTo } : {}),
+      ...(kind !== 'normal' ? { kind } : {}),
+      ...(attachment ? { attachment: attachmentRef(attachment) } : {}),
+    };
+    chatState.nextMessageId += 1;
+
+    chatState.messages.push(newMessage);
+    await persistChatState(this.state, chatState);
+    this.lastActive.set(capabilityId, newMessage.timestamp);
+
+    await this.broadcastMessage(newMessage);
+
+    return { status: 'ok', echo: message, id: newMessage.id };
+  }
+
+  async receiveRoomMessages(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+
+    // Verify the session exists
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      console.log('Session not found for capabilityId:', capabilityId);
+      throw new Error('unknown session capability');
+    }
+    // History is for members only, so a kick or ban also ends reading
+    if (!sessionRooms(sessionInfo).includes(roomName)) {
+      throw new Error(`not a member of ${roomName}`);
+    }
+
+    const messages = chatState.messages.filter((msg) => msg.room === roomName);
+    console.log('Returning', messages.length, 'messages for', roomName);
+    return {
+      room: roomName,
+      messages: messages.map(toWireMessage),
+    };
+  }
+
+  async searchMessages(capabilityId: number, room: string, query: string, options: SearchOptions = {}) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    if (!sessionRooms(sessionInfo).includes(roomName)) {
+      throw new Error(`not a member of ${roomName}`);
+    }
+    if (typeof query !== 'string' || query.length === 0) {
+      throw new Error('search query must not be empty');
+    }
+    if (query.length > SEARCH_QUERY_MAX_LENGTH) {
+      throw new Error(`search query is limited to ${SEARCH_QUERY_MAX_LENGTH} characters`);
+    }
+
+    // Only plain substrings: a client pattern run here could backtrack for
+    // long enough to stall the object for everyone. Regex search happens on
+    // the client, over the room history.
+    if (options.regex) {
+      throw new Error('regex search is done by the client');
+    }
+    let matcher: (body: string) => boolean;
+    if (options.caseSensitive) {
+      matcher = (body) => body.includes(query);
+    } else {
+      const needle = query.toLowerCase();
+      matcher = (body) => body.toLowerCase().includes(needle);
+    }
+
+    const messages = chatState.messages
+      .filter((msg) => msg.room === roomName && matcher(msg.body))
+      .slice(-SEARCH_RESULT_LIMIT);
+    return {
+      room: roomName,
+      messages: messages.map(toWireMessage),
+    };
+  }
+
+  // Only the author may edit or delete: the same account, or whoever holds
+  // the author's identified nickname now.
+  async editMessage(capabilityId: number, id: number, body: string) {
+    if (typeof id !== 'number' || typeof body !== 'string') {
+      throw new TypeError('`editMessage` expects <capabilityId>, <id>, <body>');
+    }
+    if (body.trim().length === 0) {
+      throw new Error('message body must not be empty');
+    }
+
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+
+    const message = chatState.messages.find((msg) => msg.id === id);
+    if (!message) {
+      return { status: 'error', message: `No message with id ${id}` };
+    }
+    if (!this.ownsMessage(chatState, sessionInfo, message)) {
+      return { status: 'error', message: 'You can only edit your own messages' };
+    }
+    // An edit says something new, so it needs the same right as a send
+    if (!sessionRooms(sessionInfo).includes(message.room)) {
+      return { status: 'error', message: `not a member of ${message.room}` };
+    }
+    const silenced = this.silenced(chatState, sessionInfo, message.room);
+    if (silenced) {
+      return { status: 'error', message: silenced };
+    }
+
+    message.body = body;
+    message.editedAt = Date.now();
+    await persistChatState(this.state, chatState);
+    this.lastActive.set(capabilityId, message.editedAt);
+
+    await this.broadcastToRoom(chatState, message.room, (stub) =>
+      stub.receiveMessageEdited?.(toWireMessage(message)),
+    );
+
+    return { status: 'ok', id };
+  }
+
+  async deleteMessage(capabilityId: number, id: number) {
+    if (typeof id !== 'number') {
+      throw new TypeError('`deleteMessage` expects <capabilityId>, <id>');
+    }
+
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+
+    const index = chatState.messages.findIndex((msg) => msg.id === id);
+    if (index === -1) {
+      return { status: 'error', message: `No message with id ${id}` };
+    }
+    const message = chatState.messages[index];
+    if (!this.ownsMessage(chatState, sessionInfo, message)) {
+      return { status: 'error', message: 'You can only delete your own messages' };
+    }
+
+    chatState.messages.splice(index, 1);
+    await persistChatState(this.state, chatState);
+
+    const deleted: DeletedMessage = { id, room: message.room };
+    await this.broadcastToRoom(chatState, message.room, (stub) =>
+      stub.receiveMessageDeleted?.(deleted),
+    );
+
+    return { status: 'ok', id };
+  }
+
+  async react(capabilityId: number, id: number, emoji: string) {
+    return this.updateReaction(capabilityId, id, emoji, true);
+  }
+
+  async unreact(capabilityId: number, id: number, emoji: string) {
+    return this.updateReaction(capabilityId, id, emoji, false);
+  }
+
+  // Reactions are keyed by nickname, so each user counts once per emoji.
+  private async updateReaction(capabilityId: number, id: number, emoji: string, add: boolean) {
+    if (typeof id !== 'number' || typeof emoji !== 'string') {
+      throw new TypeError(`\`${add ? 'react' : 'unreact'}\` expects <capabilityId>, <id>, <emoji>`);
+    }
+    const reaction = emoji.trim();
+    if (!reaction || reaction.length > REACTION_MAX_LENGTH || /\s/.test(reaction)) {
+      throw new Error(`invalid reaction '${emoji}'`);
+    }
+
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+
+    const message = chatState.messages.find((msg) => msg.id === id);
+    if (!message || !sessionRooms(sessionInfo).includes(message.room)) {
+      return { status: 'error', message: `No message with id ${id}` };
+    }
+    const silenced = this.silenced(chatState, sessionInfo, message.room);
+    if (silenced) {
+      return { status: 'error', message: silenced };
+    }
+
+    const nickname = sessionInfo.displayName ?? sessionInfo.username;
+    const reactions = message.reactions ?? {};
+    const users = reactions[reaction] ?? [];
+    if (add === users.includes(nickname)) {
+      return { status: 'ok', id };
+    }
+    if (add) {
+      reactions[reaction] = [...users, nickname];
+    } else if (users.length > 1) {
+      reactions[reaction] = users.filter((user) => user !== nickname);
+    } else {
+      delete reactions[reaction];
+    }
+    message.reactions = reactions;
+    await persistChatState(this.state, chatState);
+
+    const update: ReactionUpdate = { id, room: message.room, reactions: reactionList(message) };
+    await this.broadcastToRoom(chatState, message.room, (stub) => stub.receiveReaction?.(update));
+
+    return { status: 'ok', id };
+  }
+
+  // Direct messages are delivered only to the target's connections and are
+  // never persisted in chat state.
+  async sendDirectMessage(
+    capabilityId: number,
+    nickname: string,
+    message: string,
+    options: SendOptions = {},
+  ) {
+    if (typeof nickname !== 'string' || typeof message !== 'string') {
+      throw new TypeError('`sendDirectMessage` expects <capabilityId>, <nickname>, <message>');
+    }
+
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+
+    const kind = clientMessageKind(options);
+    const attachment = attachmentOption(chatState, sessionInfo, options);
+    const targets = this.connectionsForNick(chatState, nickname);
+    if (targets.length === 0) {
+      return { status: 'error', message: `${nickname} is not online` };
+    }
+    // Recorded before delivery, so the recipient can fetch it straight away
+    if (attachment) {
+      attachment.parties = [sessionInfo, ...this.sessionsForNick(chatState, nickname)].map(
+        attachmentParty,
+      );
+      await persistChatState(this.state, chatState);
+    }
+
+    const directMessage: DirectMessage = {
+      from: sessionInfo.displayName ?? sessionInfo.username,
+      to: nickname,
+      body: message,
+      timestamp: Date.now(),
+      ...(kind !== 'normal' ? { kind } : {}),
+      ...(attachment ? { attachment: attachmentRef(attachment) } : {}),
+    };
+    this.lastActive.set(capabilityId, directMessage.timestamp);
+
+    let delivered = 0;
+    for (const connection of targets) {
+      try {
+        await connection.clientStub?.receiveDirectMessage?.(directMessage);
+        delivered += 1;
+      } catch (error) {
+        console.error('Failed to deliver direct message:', error);
+      }
+    }
+
+    if (delivered === 0) {
+      // Undelivered, so the upload may be sent again
+      if (attachment) {
+        delete attachment.parties;
+        await persistChatState(this.state, chatState);
+      }
+      return { status: 'error', message: `Could not deliver message to ${nickname}` };
+    }
+
+    // Answer for an away user with a notice, which never gets an answer itself
+    const awayReason = this.awayReasonOf(chatState, nickname);
+    if (awayReason !== undefined && kind !== 'notice') {
+      const reply: DirectMessage = {
+        from: nickname,
+        to: directMessage.from,
+        body: `Away: ${awayReason}`,
+        timestamp: Date.now(),
+        kind: 'notice',
+      };
+      for (const connection of this.connectionsForNick(chatState, directMessage.from)) {
+        try {
+          await connection.clientStub?.receiveDirectMessage?.(reply);
+        } catch (error) {
+          console.error('Failed to deliver away reply:', error);
+        }
+      }
+    }
+
+    return { status: 'ok', to: nickname };
+  }
+
+  // Typing notices are fire-and-forget: they are not stored, and clients
+  // expire them on their own.
+  async typing(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    if (!sessionRooms(sessionInfo).includes(roomName)) {
+      return { status: 'error', message: `Not a member of ${roomName}` };
+    }
+
+    const nickname = sessionInfo.displayName ?? sessionInfo.username;
+    await this.broadcastToRoom(chatState, roomName, (stub) =>
+      stub.userTyping?.({ nickname, room: roomName }),
+    );
+    return { status: 'ok' };
+  }
+
+  // A reason marks the session away and null marks it back; everyone is
+  // told through presence.
+  async setAway(capabilityId: number, reason?: string | null) {
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+
+    const away = reason !== null && reason !== undefined;
+    if (away) {
+      this.away.set(capabilityId, cleanReason(reason) ?? 'Away');
+    } else {
+      this.away.delete(capabilityId);
+    }
+    const nickname = sessionInfo.displayName ?? sessionInfo.username;
+    await this.broadcastPresence();
+    await this.broadcastPresenceChange({
+      nickname,
+      online: true,
+      away,
+      ...(away ? { reason: this.away.get(capabilityId) } : {}),
+    });
+    return { status: 'ok', away };
+  }
+
+  // Attachments arrive as a begin, any number of chunks and a finish. The
+  // finished attachment is referenced by id from a room or direct message.
+  async beginUpload(capabilityId: number, info: UploadInfo) {
+    const chatState = await loadChatState(this.state);
+    if (!chatState.sessionCaps[String(capabilityId)]) {
+      throw new Error('unknown session capability');
+    }
+    if (!info || typeof info !== 'object') {
+      throw new TypeError('`beginUpload` expects <capabilityId>, {name, size, mime}');
+    }
+
+    const name = attachmentName(info.name);
+    if (!name) {
+      return { status: 'error', message: 'attachments need a file name' };
+    }
+    const size = info.size;
+    if (typeof size !== 'number' || !Number.isInteger(size) || size <= 0) {
+      return { status: 'error', message: 'attachments must not be empty' };
+    }
+    if (size > ATTACHMENT_MAX_BYTES) {
+      return {
+        status: 'error',
+        message: `attachments are limited to ${ATTACHMENT_MAX_BYTES / (1024 * 1024)} MiB`,
+      };
+    }
+    const mime =
+      typeof info.mime === 'string' && MIME_PATTERN.test(info.mime.toLowerCase())
+        ? info.mime.toLowerCase()
+        : DEFAULT_MIME;
+
+    const inFlight = Array.from(this.uploads.values()).filter(
+      (pending) => pending.owner === capabilityId,
+    ).length;
+    if (inFlight >= UPLOADS_PER_SESSION) {
+      return {
+        status: 'error',
+        message: `at most ${UPLOADS_PER_SESSION} uploads may be in progress at once`,
+      };
+    }
+
+    const upload = this.nextUploadId;
+    this.nextUploadId += 1;
+    this.uploads.set(upload, {
+      owner: capabilityId,
+      name,
+      size,
+      mime,
+      chunks: [],
+      received: 0,
+      lastActive: Date.now(),
+    });
+    this.expireUpload(upload);
+    return {
+      status: 'ok',
+      upload,
+      chunkSize: ATTACHMENT_CHUNK_BYTES,
+      maxBytes: ATTACHMENT_MAX_BYTES,
+    };
+  }
+
+  async uploadChunk(capabilityId: number, upload: number, data: Uint8Array) {
+    const pending = this.uploads.get(upload);
+    if (!pending || pending.owner !== capabilityId) {
+      throw new Error(`unknown upload ${upload}`);
+    }
+    if (!(data instanceof Uint8Array)) {
+      throw new TypeError('upload chunks must be bytes');
+    }
+    if (data.length === 0 || data.length > ATTACHMENT_CHUNK_BYTES) {
+      return { status: 'error', message: `chunks must be 1 to ${ATTACHMENT_CHUNK_BYTES} bytes` };
+    }
+    if (pending.received + data.length > pending.size) {
+      this.uploads.delete(upload);
+      return { status: 'error', message: `${pending.name} is larger than announced` };
+    }
+    pending.chunks.push(data);
+    pending.received += data.length;
+    pending.lastActive = Date.now();
+    return { status: 'ok', received: pending.received };
+  }
+
+  // Drop an upload once it has gone quiet, and check again later while
+  // chunks keep arriving. Sessions that never attach a connection are not
+  // cleaned up on disconnect, so this is what bounds their memory.
+  private expireUpload(upload: number) {
+    const pending = this.uploads.get(upload);
+    if (!pending) {
+      return;
+    }
+    const idle = Date.now() - pending.lastActive;
+    if (idle >= UPLOAD_IDLE_MS) {
+      this.uploads.delete(upload);
+      return;
+    }
+    setTimeout(() => this.expireUpload(upload), UPLOAD_IDLE_MS - idle);
+  }
+
+  async finishUpload(capabilityId: number, upload: number) {
+    const pending = this.uploads.get(upload);
+    if (!pending || pending.owner !== capabilityId) {
+      throw new Error(`unknown upload ${upload}`);
+    }
+    this.uploads.delete(upload);
+    if (pending.received !== pending.size) {
+      return {
+        status: 'error',
+        message: `${pending.name} is incomplete (${pending.received} of ${pending.size} bytes)`,
+      };
+    }
+
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    const id = chatState.nextAttachmentId;
+    chatState.nextAttachmentId += 1;
+    await this.blobs.put(id, pending.chunks);
+    const attachment: AttachmentRef = {
+      id,
+      name: pending.name,
+      size: pending.size,
+      mime: pending.mime,
+    };
+    chatState.attachments[String(id)] = {
+      ...attachment,
+      chunks: pending.chunks.length,
+      uploadedBy: sessionInfo.displayName ?? sessionInfo.username,
+      uploadedAt: Date.now(),
+      uploader: sessionInfo.username,
+    };
+    await persistChatState(this.state, chatState);
+    return { status: 'ok', attachment };
+  }
+
+  // One stored piece of an attachment per call, so no response grows past
+  // ATTACHMENT_CHUNK_BYTES; `chunks` says how many to ask for.
+  // Only the uploader, members of the room it was sent to or the sides of
+  // its direct message may fetch it; to anyone else it does not exist.
+  async downloadAttachment(capabilityId: number, id: number, chunk = 0) {
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    const info = chatState.attachments[String(id)];
+    if (!info || !mayDownload(info, sessionInfo)) {
+      return { status: 'error', message: `No attachment ${id}` };
+    }
+    if (!Number.isInteger(chunk) || chunk < 0 || chunk >= info.chunks) {
+      return { status: 'error', message: `${info.name} has no chunk ${chunk}` };
+    }
+    const data = await this.blobs.get(id, chunk);
+    if (!data) {
+      return { status: 'error', message: `${info.name} is missing from the blob store` };
+    }
+    return {
+      status: 'ok',
+      attachment: attachmentRef(info),
+      chunk,
+      chunks: info.chunks,
+      data,
+    };
+  }
+
+  async getTopic(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    if (!chatState.sessionCaps[String(capabilityId)]) {
+      throw new Error('unknown session capability');
+    }
+    const info = chatState.rooms[roomName];
+    if (!info) {
+      return { status: 'error', message: `No such room ${roomName}` };
+    }
+    return { status: 'ok', ...roomTopic(roomName, info) };
+  }
+
+  // Only identified members of a room may change its topic, so a guest
+  // cannot deface a room they just walked into, and once a room has
+  // operators only they may. An empty topic clears it.
+  async setTopic(capabilityId: number, room: string, topic: string) {
+    if (typeof topic !== 'string') {
+      throw new TypeError('`setTopic` expects <capabilityId>, <room>, <topic>');
+    }
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    const info = chatState.rooms[roomName];
+    if (!info || !sessionRooms(sessionInfo).includes(roomName)) {
+      return { status: 'error', message: `Not a member of ${roomName}` };
+    }
+    const nickname = sessionInfo.displayName;
+    if (!nickname || !this.isIdentified(chatState, sessionInfo)) {
+      return { status: 'error', message: 'Identify with NickServ to change topics' };
+    }
+    if (
+      this.hasOperators(chatState, roomName) &&
+      ROLE_RANK[this.roleOf(chatState, sessionInfo, roomName)] < ROLE_RANK.operator
+    ) {
+      return { status: 'error', message: `Only operators may change the topic of ${roomName}` };
+    }
+
+    const text = topic.trim();
+    if (text.length > TOPIC_MAX_LENGTH || /[\r\n]/.test(text)) {
+      return {
+        status: 'error',
+        message: `Topics are a single line of at most ${TOPIC_MAX_LENGTH} characters`,
+      };
+    }
+    if (text) {
+      info.topic = text;
+      info.topicSetBy = nickname;
+      info.topicSetAt = Date.now();
+    } else {
+      delete info.topic;
+      delete info.topicSetBy;
+      delete info.topicSetAt;
+    }
+    chatState.rooms[roomName] = info;
+    await persistChatState(this.state, chatState);
+
+    const change = roomTopic(roomName, info);
+    await this.broadcastToRoom(chatState, roomName, (stub) => stub.topicChanged?.(change));
+    await this.postSystemMessage(
+      chatState,
+      roomName,
+      text ? `${nickname} changed the topic to: ${text}` : `${nickname} cleared the topic`,
+    );
+    return { status: 'ok', ...change };
+  }
+
+  async kick(capabilityId: number, room: string, nickname: string, reason?: string) {
+    if (typeof nickname !== 'string') {
+      throw new TypeError('`kick` expects <capabilityId>, <room>, <nickname>, [reason]');
+    }
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const actor = this.moderator(chatState, capabilityId, roomName, 'operator');
+    if ('error' in actor) {
+      return { status: 'error', message: actor.error };
+    }
+
+    const targets = this.sessionsInRoom(chatState, roomName).filter(
+      ([, info]) => (info.displayName ?? info.username) === nickname,
+    );
+    if (targets.length === 0) {
+      return { status: 'error', message: `${nickname} is not in ${roomName}` };
+    }
+    if (targets.some(([, info]) => !this.outranks(chatState, actor, info, roomName))) {
+      return { status: 'error', message: `You cannot kick ${nickname}` };
+    }
+
+    const note = cleanReason(reason);
+    await this.removeFromRoom(chatState, roomName, targets, actor.nickname, note);
+    this.audit(chatState, {
+      room: roomName,
+      actor: actor.nickname,
+      action: 'kick',
+      target: nickname,
+      reason: note,
+    });
+    await persistChatState(this.state, chatState);
+    return { status: 'ok' };
+  }
+
+  async ban(capabilityId: number, room: string, mask: string, options: RestrictionOptions = {}) {
+    return this.restrict(capabilityId, room, 'ban', mask, options);
+  }
+
+  async unban(capabilityId: number, room: string, mask: string) {
+    return this.unrestrict(capabilityId, room, 'ban', mask);
+  }
+
+  async mute(capabilityId: number, room: string, mask: string, options: RestrictionOptions = {}) {
+    return this.restrict(capabilityId, room, 'mute', mask, options);
+  }
+
+  async unmute(capabilityId: number, room: string, mask: string) {
+    return this.unrestrict(capabilityId, room, 'mute', mask);
+  }
+
+  // Bans and mutes match a nick, or a pattern with `*` and `?` against
+  // nicks and guest names alike. Operators and owners are never affected,
+  // and voiced users cannot be muted.
+  private async restrict(
+    capabilityId: number,
+    room: string,
+    kind: RestrictionKind,
+    mask: string,
+    options: RestrictionOptions,
+  ) {
+    if (typeof mask !== 'string') {
+      throw new TypeError(`\`${kind}\` expects <capabilityId>, <room>, <mask>, [options]`);
+    }
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const actor = this.moderator(chatState, capabilityId, roomName, 'operator');
+    if ('error' in actor) {
+      return { status: 'error', message: actor.error };
+    }
+    const target = mask.trim();
+    if (!target || target.length > MASK_MAX_LENGTH || /\s/.test(target)) {
+      return { status: 'error', message: `A mask is one word of at most ${MASK_MAX_LENGTH} characters` };
+    }
+    const duration = options.durationSecs;
+    if (duration !== undefined && (typeof duration !== 'number' || !(duration > 0))) {
+      return { status: 'error', message: 'Durations must be a positive number of seconds' };
+    }
+
+    const now = Date.now();
+    const reason = cleanReason(options.reason);
+    const restriction: Restriction = {
+      kind,
+      mask: target,
+      setBy: actor.nickname,
+      setAt: now,
+      ...(duration !== undefined ? { expiresAt: now + Math.floor(duration * 1000) } : {}),
+      ...(reason ? { reason } : {}),
+    };
+    // Setting the same mask again replaces it, which is how a ban is extended
+    chatState.restrictions[roomName] = [
+      ...liveRestrictions(chatState, roomName).filter((r) => r.kind !== kind || r.mask !== target),
+      restriction,
+    ];
+    this.audit(chatState, {
+      room: roomName,
+      actor: actor.nickname,
+      action: kind,
+      target,
+      reason: restriction.reason,
+      expiresAt: restriction.expiresAt,
+    });
+    await persistChatState(this.state, chatState);
+
+    const until = restriction.expiresAt
+      ? ` until ${new Date(restriction.expiresAt).toISOString()}`
+      : '';
+    const why = reason ? `: ${reason}` : '';
+    await this.postSystemMessage(
+      chatState,
+      roomName,
+      `${actor.nickname} ${kind === 'ban' ? 'banned' : 'muted'} ${target}${until}${why}`,
+    );
+
+    if (kind === 'ban') {
+      const banned = this.sessionsInRoom(chatState, roomName).filter(
+        ([, info]) =>
+          maskMatches(target, info) &&
+          ROLE_RANK[this.roleOf(chatState, info, roomName)] < ROLE_RANK.operator,
+      );
+      if (banned.length > 0) {
+        await this.removeFromRoom(chatState, roomName, banned, actor.nickname, reason ?? 'banned');
+        await persistChatState(this.state, chatState);
+      }
+    }
+    return { status: 'ok', restriction };
+  }
+
+  private async unrestrict(capabilityId: number, room: string, kind: RestrictionKind, mask: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const actor = this.moderator(chatState, capabilityId, roomName, 'operator');
+    if ('error' in actor) {
+      return { status: 'error', message: actor.error };
+    }
+    const target = typeof mask === 'string' ? mask.trim() : '';
+    const current = liveRestrictions(chatState, roomName);
+    const remaining = current.filter((r) => r.kind !== kind || r.mask !== target);
+    if (remaining.length === current.length) {
+      return { status: 'error', message: `${target} is not ${kind === 'ban' ? 'banned' : 'muted'} in ${roomName}` };
+    }
+    chatState.restrictions[roomName] = remaining;
+    this.audit(chatState, {
+      room: roomName,
+      actor: actor.nickname,
+      action: kind === 'ban' ? 'unban' : 'unmute',
+      target,
+    });
+    await persistChatState(this.state, chatState);
+    await this.postSystemMessage(
+      chatState,
+      roomName,
+      `${actor.nickname} ${kind === 'ban' ? 'unbanned' : 'unmuted'} ${target}`,
+    );
+    return { status: 'ok' };
+  }
+
+  // Operators hand out voice; only owners make operators and owners.
+  async setRole(capabilityId: number, room: string, nickname: string, role: Role) {
+    if (typeof nickname !== 'string' || !ROLES.includes(role)) {
+      throw new TypeError('`setRole` expects <capabilityId>, <room>, <nickname>, <owner|operator|voice|user>');
+    }
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const needed: Role = ROLE_RANK[role] >= ROLE_RANK.operator ? 'owner' : 'operator';
+    const actor = this.moderator(chatState, capabilityId, roomName, needed);
+    if ('error' in actor) {
+      return { status: 'error', message: actor.error };
+    }
+    if (!chatState.registeredNicks[nickname]) {
+      return { status: 'error', message: `Roles belong to registered nicks; ${nickname} is not one` };
+    }
+    const current = nickRole(this.env, chatState, nickname, roomName);
+    if (nickname === this.env.CHAT_OWNER || (current === 'owner' && actor.role !== 'owner')) {
+      return { status: 'error', message: `You cannot change the role of ${nickname}` };
+    }
+    if (ROLE_RANK[current] >= ROLE_RANK[actor.role] && actor.role !== 'owner') {
+      return { status: 'error', message: `You cannot change the role of ${nickname}` };
+    }
+
+    const roles = { ...(chatState.roles[roomName] ?? {}) };
+    if (role === 'user') {
+      delete roles[nickname];
+    } else {
+      roles[nickname] = role;
+    }
+    chatState.roles[roomName] = roles;
+    this.audit(chatState, {
+      room: roomName,
+      actor: actor.nickname,
+      action: 'role',
+      target: nickname,
+      role,
+    });
+    await persistChatState(this.state, chatState);
+    await this.postSystemMessage(
+      chatState,
+      roomName,
+      role === 'user'
+        ? `${actor.nickname} removed ${current} from ${nickname}`
+        : `${actor.nickname} made ${nickname} ${role === 'voice' ? 'a voiced user' : `an ${role}`}`,
+    );
+    await this.broadcastPresence();
+    return { status: 'ok', role };
+  }
+
+  async listRestrictions(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    if (!chatState.sessionCaps[String(capabilityId)]) {
+      throw new Error('unknown session capability');
+    }
+    return { status: 'ok', room: roomName, restrictions: liveRestrictions(chatState, roomName) };
+  }
+
+  async auditLog(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const actor = this.moderator(chatState, capabilityId, roomName, 'operator');
+    if ('error' in actor) {
+      return { status: 'error', message: actor.error };
+    }
+    const entries = chatState.audit.filter((entry) => entry.room === roomName).slice(-AUDIT_PAGE_SIZE);
+    return { status: 'ok', room: roomName, entries };
+  }
+
+  private isIdentified(chatState: ChatState, sessionInfo: SessionInfo) {
+    const nickname = sessionInfo.displayName;
+    return (
+      !!nickname &&
+      !!chatState.registeredNicks[nickname] &&
+      chatState.nickOwners[nickname] === sessionInfo.username
+    );
+  }
+
+  // System messages belong to no one, and messages without an author
+  // predate ownership tracking. A session identified as the sending nick
+  // owns the message too.
+  private ownsMessage(chatState: ChatState, sessionInfo: SessionInfo, message: StoredMessage) {
+    if (message.kind === 'system' || !message.author) {
+      return false;
+    }
+    if (message.author === sessionInfo.username) {
+      return true;
+    }
+    return sessionInfo.displayName === message.from && this.isIdentified(chatState, sessionInfo);
+  }
+
+  // Roles belong to registered nicks, so a session only holds one while it
+  // is identified as that nick.
+  private roleOf(chatState: ChatState, sessionInfo: SessionInfo, room: string): Role {
+    if (!sessionInfo.displayName || !this.isIdentified(chatState, sessionInfo)) {
+      return 'user';
+    }
+    return nickRole(this.env, chatState, sessionInfo.displayName, room);
+  }
+
+  private hasOperators(chatState: ChatState, room: string) {
+    return (
+      !!this.env.CHAT_OWNER ||
+      Object.values(chatState.roles[room] ?? {}).some((role) => ROLE_RANK[role] >= ROLE_RANK.operator)
+    );
+  }
+
+  // The acting session when it holds at least `needed` in `room`.
+  private moderator(
+    chatState: ChatState,
+    capabilityId: number,
+    room: string,
+    needed: Role,
+  ): { nickname: string; role: Role } | { error: string } {
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+    if (!chatState.rooms[room]) {
+      return { error: `No such room ${room}` };
+    }
+    const role = this.roleOf(chatState, sessionInfo, room);
+    if (ROLE_RANK[role] < ROLE_RANK[needed]) {
+      return { error: `You need to be ${needed === 'owner' ? 'an owner' : 'an operator'} of ${room}` };
+    }
+    return { nickname: sessionInfo.displayName ?? sessionInfo.username, role };
+  }
+
+  private outranks(
+    chatState: ChatState,
+    actor: { nickname: string; role: Role },
+    target: SessionInfo,
+    room: string,
+  ) {
+    const role = this.roleOf(chatState, target, room);
+    if (actor.role === 'owner') {
+      return role !== 'owner';
+    }
+    return ROLE_RANK[role] < ROLE_RANK[actor.role];
+  }
+
+  private activeRestriction(
+    chatState: ChatState,
+    room: string,
+    kind: RestrictionKind,
+    sessionInfo: SessionInfo,
+  ): Restriction | undefined {
+    return liveRestrictions(chatState, room).find(
+      (restriction) => restriction.kind === kind && maskMatches(restriction.mask, sessionInfo),
+    );
+  }
+
+  // Why the session may not speak in `room`, or null when it may.
+  private silenced(chatState: ChatState, sessionInfo: SessionInfo, room: string): string | null {
+    const rank = ROLE_RANK[this.roleOf(chatState, sessionInfo, room)];
+    if (rank >= ROLE_RANK.operator) {
+      return null;
+    }
+    const ban = this.activeRestriction(chatState, room, 'ban', sessionInfo);
+    if (ban) {
+      return `you are banned from ${room}${ban.reason ? `: ${ban.reason}` : ''}`;
+    }
+    const mute = rank < ROLE_RANK.voice ? this.activeRestriction(chatState, room, 'mute', sessionInfo) : undefined;
+    if (mute) {
+      return `you are muted in ${room}${mute.reason ? `: ${mute.reason}` : ''}`;
+    }
+    return null;
+  }
+
+  private sessionsInRoom(chatState: ChatState, room: string): [number, SessionInfo][] {
+    return Object.entries(chatState.sessionCaps)
+      .filter(([, info]) => sessionRooms(info).includes(room))
+      .map(([key, info]) => [Number(key), info]);
+  }
+
+  // Take `targets` out of `room`, telling the room first so the targets
+  // see why, then telling their clients to close the room.
+  private async removeFromRoom(
+    chatState: ChatState,
+    room: string,
+    targets: [number, SessionInfo][],
+    by: string,
+    reason: string | undefined,
+  ) {
+    const nicknames = Array.from(new Set(targets.map(([, info]) => info.displayName ?? info.username)));
+    for (const nickname of nicknames) {
+      await this.postSystemMessage(
+        chatState,
+        room,
+        `${nickname} was removed from ${room} by ${by}${reason ? ` (${reason})` : ''}`,
+      );
+    }
+    for (const [capabilityId, info] of targets) {
+      info.rooms = sessionRooms(info).filter((name) => name !== room);
+      chatState.sessionCaps[String(capabilityId)] = info;
+      for (const connection of this.connections) {
+        if (!connection.sessionIds.has(capabilityId) || !connection.clientStub) {
+          continue;
+        }
+        try {
+          await connection.clientStub.kicked?.({ room, by, ...(reason ? { reason } : {}) });
+        } catch (error) {
+          console.error('Failed to notify kicked client:', error);
+        }
+      }
+    }
+    await persistChatState(this.state, chatState);
+    for (const nickname of nicknames) {
+      await this.broadcastToRoom(chatState, room, (stub) => stub.userLeft?.({ nickname, room }));
+    }
+    await this.broadcastPresence();
+  }
+
+  private audit(chatState: ChatState, entry: Omit<AuditEntry, 'at'>) {
+    const clean = Object.fromEntries(
+      Object.entries(entry).filter(([, value]) => value !== undefined),
+    ) as Omit<AuditEntry, 'at'>;
+    chatState.audit.push({ at: Date.now(), ...clean });
+    if (chatState.audit.length > AUDIT_LOG_LIMIT) {
+      chatState.audit.splice(0, chatState.audit.length - AUDIT_LOG_LIMIT);
+    }
+    console.log(`AUDIT ${entry.room}: ${entry.actor} ${entry.action} ${entry.target}`);
+  }
+
+  async joinRoom(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    if (!sessionInfo) {
+      throw new Error('unknown session capability');
+    }
+
+    if (!chatState.rooms[roomName]) {
+      chatState.rooms[roomName] = {
+        createdAt: Date.now(),
+        createdBy: sessionInfo.displayName ?? sessionInfo.username,
+      };
+      // Whoever creates a room while identified owns it
+      if (sessionInfo.displayName && this.isIdentified(chatState, sessionInfo)) {
+        chatState.roles[roomName] = { [sessionInfo.displayName]: 'owner' };
+      }
+    } else {
+      const ban = this.activeRestriction(chatState, roomName, 'ban', sessionInfo);
+      if (ban && ROLE_RANK[this.roleOf(chatState, sessionInfo, roomName)] < ROLE_RANK.operator) {
+        throw new Error(`you are banned from ${roomName}${ban.reason ? `: ${ban.reason}` : ''}`);
+      }
+    }
 
-    const storedUsername = `guest-${sessionCapId}`;
-    chatState.sessionCaps[String(sessionCapId)] = {
-      username: storedUsername,
-    };
+    const rooms = sessionRooms(sessionInfo);
+    const joined = !rooms.includes(roomName);
+    if (joined) {
+      sessionInfo.rooms = [...rooms, roomName];
+    }
+    chatState.sessionCaps[String(capabilityId)] = sessionInfo;
 
     await persistChatState(this.state, chatState);
+    if (joined) {
+      const nickname = sessionInfo.displayName ?? sessionInfo.username;
+      await this.broadcastToRoom(chatState, roomName, (stub) =>
+        stub.userJoined?.({ nickname, room: roomName }),
+      );
+      await this.postSystemMessage(chatState, roomName, `${nickname} joined ${roomName}`);
+    }
+    await this.broadcastPresence();
 
-    return {
-      session: {
-        _type: 'capability',
-        id: sessionCapId,
-      },
-      user: storedUsername,
-    };
+    return { status: 'ok', room: roomName };
   }
 
-  // This method will be called by clients to send messages
-  async sendMessage(capabilityId: number, message: string) {
-    console.log('Server sendMessage called with capabilityId:', capabilityId, 'message:', message);
+  async partRoom(capabilityId: number, room: string) {
+    const roomName = normalizeRoomName(room);
     const chatState = await loadChatState(this.state);
-
-    // Find the user by capability ID
     const sessionInfo = chatState.sessionCaps[String(capabilityId)];
     if (!sessionInfo) {
       throw new Error('unknown session capability');
     }
 
-    const from = sessionInfo.displayName ?? sessionInfo.username;
+    const rooms = sessionRooms(sessionInfo);
+    if (!rooms.includes(roomName)) {
+      return { status: 'error', message: `Not a member of ${roomName}` };
+    }
 
-    const newMessage = {
-      from,
-      body: message,
-      timestamp: Date.now(),
-    };
+    sessionInfo.rooms = rooms.filter((name) => name !== roomName);
+    chatState.sessionCaps[String(capabilityId)] = sessionInfo;
 
-    chatState.messages.push(newMessage);
     await persistChatState(this.state, chatState);
+    // Sent after leaving, so only the members who remain hear about it
+    const nickname = sessionInfo.displayName ?? sessionInfo.username;
+    await this.broadcastToRoom(chatState, roomName, (stub) =>
+      stub.userLeft?.({ nickname, room: roomName }),
+    );
+    await this.postSystemMessage(chatState, roomName, `${nickname} left ${roomName}`);
+    await this.broadcastPresence();
 
-    // Broadcast to all connected clients
-    for (const clientStub of Array.from(this.clients)) {
-      try {
-        // Call the receiveMessage method on each client
-        await clientStub.receiveMessage(newMessage);
-      } catch (error) {
-        console.error('Failed to send message to client:', error);
-        this.clients.delete(clientStub);
-      }
-    }
-
-    return { status: 'ok', echo: message };
+    return { status: 'ok', room: roomName };
   }
 
-  async receiveMessages(capabilityId: number) {
-    console.log('Server receiveMessages called with capabilityId:', capabilityId);
+  async listRooms(capabilityId: number) {
     const chatState = await loadChatState(this.state);
-    console.log('Loaded chat state with', chatState.messages.length, 'messages');
-
-    // Verify the session exists
     const sessionInfo = chatState.sessionCaps[String(capabilityId)];
     if (!sessionInfo) {
-      console.log('Session not found for capabilityId:', capabilityId);
       throw new Error('unknown session capability');
     }
 
-    console.log('Returning', chatState.messages.length, 'messages');
-    return {
-      messages: chatState.messages.map(msg => ({
-        from: msg.from,
-        body: msg.body,
-        timestamp: msg.timestamp,
-      })),
-    };
+    const online = this.presenceSnapshot(chatState);
+    const joined = sessionRooms(sessionInfo);
+    const rooms = Object.keys(chatState.rooms)
+      .sort()
+      .map((name) => ({
+        name,
+        members: online.filter((user) => user.rooms.includes(name)).length,
+        joined: joined.includes(name),
+      }));
+
+    return { rooms };
   }
 
   async whoami(capabilityId: number) {
@@ -314,10 +1651,13 @@ export class CapnWebDurable extends RpcTarget {
 
     chatState.registeredNicks[nickname] = password;
     chatState.nickOwners[nickname] = sessionInfo.username;
+    const previous = sessionInfo.displayName ?? sessionInfo.username;
     sessionInfo.displayName = nickname;
     chatState.sessionCaps[String(capabilityId)] = sessionInfo;
 
     await persistChatState(this.state, chatState);
+    await this.announceNickChange(chatState, sessionInfo, previous);
+    await this.broadcastPresence();
 
     return {
       status: 'ok',
@@ -357,10 +1697,13 @@ export class CapnWebDurable extends RpcTarget {
     }
 
     chatState.nickOwners[nickname] = sessionInfo.username;
+    const previous = sessionInfo.displayName ?? sessionInfo.username;
     sessionInfo.displayName = nickname;
     chatState.sessionCaps[String(capabilityId)] = sessionInfo;
 
     await persistChatState(this.state, chatState);
+    await this.announceNickChange(chatState, sessionInfo, previous);
+    await this.broadcastPresence();
 
     return {
       status: 'ok',
@@ -385,37 +1728,267 @@ export class CapnWebDurable extends RpcTarget {
     };
   }
 
-  async broadcastMessage(message: { from: string; body: string; timestamp: number }) {
-    console.log(`Broadcasting message to ${this.clients.size} clients:`, message);
-    // Broadcast to all connected clients
-    for (const clientStub of Array.from(this.clients)) {
+  async listUsers(capabilityId: number) {
+    const chatState = await loadChatState(this.state);
+    if (!chatState.sessionCaps[String(capabilityId)]) {
+      throw new Error('unknown session capability');
+    }
+
+    return { users: this.presenceSnapshot(chatState) };
+  }
+
+  async broadcastMessage(message: StoredMessage) {
+    console.log(`Broadcasting message to ${this.connections.size} clients:`, message);
+    const chatState = await loadChatState(this.state);
+    // Broadcast to every connection holding a session that joined the room
+    for (const connection of Array.from(this.connections)) {
+      if (!connection.clientStub || !this.connectionInRoom(chatState, connection, message.room)) {
+        continue;
+      }
       try {
-        console.log('Calling receiveMessage on client stub');
         // Call the receiveMessage method on each client
-        // The clientStub should be the client's RPC target
-        await clientStub.receiveMessage(message);
-        console.log('Successfully called receiveMessage on client');
+        await connection.clientStub.receiveMessage(toWireMessage(message));
       } catch (error) {
         console.error('Failed to send message to client:', error);
-        this.clients.delete(clientStub);
+        this.dropConnection(connection);
+      }
+    }
+  }
+
+  // Deliver an optional client call to every connection in `room`. Older
+  // clients without the method are left connected.
+  private async broadcastToRoom(
+    chatState: ChatState,
+    room: string,
+    call: (stub: ChatClientStub) => Promise<void> | void,
+  ) {
+    for (const connection of Array.from(this.connections)) {
+      if (!connection.clientStub || !this.connectionInRoom(chatState, connection, room)) {
+        continue;
+      }
+      try {
+        await call(connection.clientStub);
+      } catch (error) {
+        console.error('Failed to send update to client:', error);
+      }
+    }
+  }
+
+  // Store and broadcast an event such as a join as a `system` message, so
+  // it shows up in history like any other message.
+  private async postSystemMessage(chatState: ChatState, room: string, body: string) {
+    const message: StoredMessage = {
+      id: chatState.nextMessageId,
+      from: 'System',
+      body,
+      timestamp: Date.now(),
+      room,
+      kind: 'system',
+    };
+    chatState.nextMessageId += 1;
+    chatState.messages.push(message);
+    await persistChatState(this.state, chatState);
+    await this.broadcastMessage(message);
+  }
+
+  private async announceNickChange(chatState: ChatState, sessionInfo: SessionInfo, previous: string) {
+    const nickname = sessionInfo.displayName ?? sessionInfo.username;
+    if (nickname === previous) {
+      return;
+    }
+    for (const room of sessionRooms(sessionInfo)) {
+      await this.postSystemMessage(chatState, room, `${previous} is now known as ${nickname}`);
+    }
+  }
+
+  async attachSession(connection: ChatConnection, capabilityId: number) {
+    connection.sessionIds.add(capabilityId);
+    this.lastActive.set(capabilityId, Date.now());
+    const chatState = await loadChatState(this.state);
+    const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+    await this.broadcastPresence();
+    if (sessionInfo) {
+      await this.broadcastPresenceChange({
+        nickname: sessionInfo.displayName ?? sessionInfo.username,
+        online: true,
+        away: false,
+      });
+    }
+  }
+
+  // A single user's change, for clients that announce arrivals and
+  // departures instead of diffing `receivePresence` snapshots.
+  private async broadcastPresenceChange(change: PresenceChange) {
+    for (const connection of Array.from(this.connections)) {
+      if (!connection.clientStub) {
+        continue;
+      }
+      try {
+        await connection.clientStub.presenceChanged?.(change);
+      } catch (error) {
+        console.error('Failed to send presence change to client:', error);
+      }
+    }
+  }
+
+  // Nicks whose last connection was `connection`, which just went away.
+  private async departedNicks(connection: ChatConnection): Promise<string[]> {
+    const chatState = await loadChatState(this.state);
+    const nickOf = (capabilityId: number) => {
+      const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+      return sessionInfo ? sessionInfo.displayName ?? sessionInfo.username : null;
+    };
+    const remaining = new Set<string>();
+    for (const other of this.connections) {
+      for (const capabilityId of other.sessionIds) {
+        const nickname = nickOf(capabilityId);
+        if (nickname) {
+          remaining.add(nickname);
+        }
+      }
+    }
+    const departed = new Set<string>();
+    for (const capabilityId of connection.sessionIds) {
+      const nickname = nickOf(capabilityId);
+      if (nickname && !remaining.has(nickname)) {
+        departed.add(nickname);
+      }
+    }
+    return Array.from(departed);
+  }
+
+  // Presence is optional for clients, so a peer that does not implement
+  // `receivePresence` is left connected when the call fails.
+  async broadcastPresence() {
+    const chatState = await loadChatState(this.state);
+    const users = this.presenceSnapshot(chatState);
+    for (const connection of Array.from(this.connections)) {
+      if (!connection.clientStub) {
+        continue;
+      }
+      try {
+        await connection.clientStub.receivePresence?.(users);
+      } catch (error) {
+        console.error('Failed to send presence to client:', error);
+      }
+    }
+  }
+
+  private presenceSnapshot(chatState: ChatState): PresenceEntry[] {
+    const users: PresenceEntry[] = [];
+    for (const connection of this.connections) {
+      for (const capabilityId of connection.sessionIds) {
+        const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+        if (!sessionInfo) {
+          continue;
+        }
+        const nickname = sessionInfo.displayName ?? sessionInfo.username;
+        const awayReason = this.away.get(capabilityId);
+        users.push({
+          nickname,
+          identified: !!sessionInfo.displayName && !!chatState.registeredNicks[nickname],
+          away: awayReason !== undefined,
+          ...(awayReason !== undefined ? { awayReason } : {}),
+          lastActive: this.lastActive.get(capabilityId) ?? Date.now(),
+          rooms: sessionRooms(sessionInfo),
+        });
+      }
+    }
+    return users.sort((a, b) => a.nickname.localeCompare(b.nickname));
+  }
+
+  // The reason of a nick that is away on every connected session, since
+  // one active session is enough to read a message.
+  private awayReasonOf(chatState: ChatState, nickname: string): string | undefined {
+    let reason: string | undefined;
+    for (const connection of this.connectionsForNick(chatState, nickname)) {
+      for (const capabilityId of connection.sessionIds) {
+        const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+        if (!sessionInfo || (sessionInfo.displayName ?? sessionInfo.username) !== nickname) {
+          continue;
+        }
+        const away = this.away.get(capabilityId);
+        if (away === undefined) {
+          return undefined;
+        }
+        reason ??= away;
+      }
+    }
+    return reason;
+  }
+
+  private connectionsForNick(chatState: ChatState, nickname: string): ChatConnection[] {
+    return Array.from(this.connections).filter((connection) =>
+      Array.from(connection.sessionIds).some((capabilityId) => {
+        const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+        return !!sessionInfo && (sessionInfo.displayName ?? sessionInfo.username) === nickname;
+      }),
+    );
+  }
+
+  private sessionsForNick(chatState: ChatState, nickname: string): SessionInfo[] {
+    const sessions: SessionInfo[] = [];
+    for (const connection of this.connections) {
+      for (const capabilityId of connection.sessionIds) {
+        const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+        if (sessionInfo && (sessionInfo.displayName ?? sessionInfo.username) === nickname) {
+          sessions.push(sessionInfo);
+        }
+      }
+    }
+    return sessions;
+  }
+
+  private connectionInRoom(chatState: ChatState, connection: ChatConnection, room: string) {
+    for (const capabilityId of connection.sessionIds) {
+      const sessionInfo = chatState.sessionCaps[String(capabilityId)];
+      if (sessionInfo && sessionRooms(sessionInfo).includes(room)) {
+        return true;
       }
     }
+    return false;
   }
 
-  private registerClient(clientStub: ChatClientStub) {
+  private registerClient(connection: ChatConnection, clientStub: ChatClientStub) {
     console.log('Registering client stub');
-    this.clients.add(clientStub);
-    console.log('Client added to set, total clients:', this.clients.size);
+    connection.clientStub = clientStub;
+    this.connections.add(connection);
+    console.log('Client added to set, total clients:', this.connections.size);
 
     if (typeof clientStub.onRpcBroken === 'function') {
       console.log('Setting up RPC broken handler');
       clientStub.onRpcBroken(() => {
         console.log('RPC connection broken, removing client');
-        this.clients.delete(clientStub);
+        this.dropConnection(connection);
+        this.broadcastPresence()
+          .then(() => this.departedNicks(connection))
+          .then((nicknames) =>
+            Promise.all(
+              nicknames.map((nickname) =>
+                this.broadcastPresenceChange({ nickname, online: false, away: false }),
+              ),
+            ),
+          )
+          .catch((error) => {
+            console.error('Failed to broadcast presence after disconnect:', error);
+          });
       });
     }
   }
 
+  private dropConnection(connection: ChatConnection) {
+    this.connections.delete(connection);
+    for (const capabilityId of connection.sessionIds) {
+      this.lastActive.delete(capabilityId);
+      this.away.delete(capabilityId);
+    }
+    for (const [upload, pending] of this.uploads) {
+      if (connection.sessionIds.has(pending.owner)) {
+        this.uploads.delete(upload);
+      }
+    }
+  }
+
   async log(capabilityId: number, message: string) {
     console.log(`DEBUG: log method called with capabilityId: ${capabilityId}, message: ${message}`);
     
@@ -530,6 +2103,7 @@ export class CapnWebDurable extends RpcTarget {
     chatState.sessionCaps[String(sessionCapId)] = {
       username,
       ...(displayName ? { displayName } : {}),
+      rooms: [DEFAULT_ROOM],
     };
     return sessionCapId;
   }
@@ -612,6 +2186,168 @@ async function readDurableStats(state: DurableObjectStateWithStorage) {
 type SessionInfo = {
   username: string;
   displayName?: string;
+  rooms?: string[];
+};
+
+// Room roles, strongest last. `user` is never stored.
+type Role = 'user' | 'voice' | 'operator' | 'owner';
+
+const ROLES: Role[] = ['user', 'voice', 'operator', 'owner'];
+const ROLE_RANK: Record<Role, number> = { user: 0, voice: 1, operator: 2, owner: 3 };
+
+type RestrictionKind = 'ban' | 'mute';
+
+type Restriction = {
+  kind: RestrictionKind;
+  // A nick, or a pattern where `*` and `?` are wildcards
+  mask: string;
+  setBy: string;
+  setAt: number;
+  // Absent for permanent restrictions
+  expiresAt?: number;
+  reason?: string;
+};
+
+type RestrictionOptions = {
+  durationSecs?: number;
+  reason?: string;
+};
+
+type AuditEntry = {
+  at: number;
+  room: string;
+  actor: string;
+  action: 'kick' | 'ban' | 'unban' | 'mute' | 'unmute' | 'role';
+  target: string;
+  reason?: string;
+  expiresAt?: number;
+  role?: Role;
+};
+
+// `normal` when absent. Clients may send the first three kinds; `system`
+// and `error` messages only come from the server.
+type MessageKind = 'normal' | 'action' | 'notice' | 'system' | 'error';
+
+const MESSAGE_KINDS: MessageKind[] = ['normal', 'action', 'notice', 'system', 'error'];
+const CLIENT_MESSAGE_KINDS: MessageKind[] = ['normal', 'action', 'notice'];
+
+type StoredMessage = {
+  id: number;
+  from: string;
+  // Account that sent the message, for ownership checks
+  author?: string;
+  body: string;
+  timestamp: number;
+  room: string;
+  editedAt?: number;
+  // Id of the message this one replies to
+  replyTo?: number;
+  // Emoji to the nicknames that reacted with it, in the order first used
+  reactions?: Record<string, string[]>;
+  kind?: MessageKind;
+  attachment?: AttachmentRef;
+};
+
+// What clients see of a stored message.
+type WireMessage = {
+  id: number;
+  from: string;
+  body: string;
+  timestamp: number;
+  room: string;
+  editedAt?: number;
+  replyTo?: number;
+  reactions?: Reaction[];
+  kind?: MessageKind;
+  attachment?: AttachmentRef;
+};
+
+// A finished upload as messages refer to it.
+type AttachmentRef = {
+  id: number;
+  name: string;
+  size: number;
+  mime: string;
+};
+
+type AttachmentInfo = AttachmentRef & {
+  // Pieces in the blob store, each at most ATTACHMENT_CHUNK_BYTES
+  chunks: number;
+  uploadedBy: string;
+  uploadedAt: number;
+  // Account that uploaded it, the only one that may send it
+  uploader: string;
+  // Set when it is sent: the room whose members may download it, or the
+  // sides of the direct message that carried it
+  room?: string;
+  parties?: AttachmentParty[];
+};
+
+// An account, and its identified nick, so a direct message's attachment
+// follows the nick to a new session the way message ownership does.
+type AttachmentParty = {
+  account: string;
+  nick?: string;
+};
+
+type UploadInfo = {
+  name: string;
+  size: number;
+  mime?: string;
+};
+
+type PendingUpload = {
+  owner: number;
+  name: string;
+  size: number;
+  mime: string;
+  chunks: Uint8Array[];
+  received: number;
+  // Time of the last chunk, or of the begin
+  lastActive: number;
+};
+
+// Where attachment bytes live. This stand-in keeps them in Durable Object
+// storage beside the chat state, so attachments work offline under
+// `wrangler dev`; an object store can take its place without touching the RPCs.
+interface BlobStore {
+  put(id: number, chunks: Uint8Array[]): Promise<void>;
+  get(id: number, chunk: number): Promise<Uint8Array | undefined>;
+}
+
+class StorageBlobStore implements BlobStore {
+  constructor(private readonly storage: DurableObjectStateWithStorage['storage']) {}
+
+  async put(id: number, chunks: Uint8Array[]) {
+    for (const [index, chunk] of chunks.entries()) {
+      await this.storage.put(`blob:${id}:${index}`, chunk);
+    }
+  }
+
+  async get(id: number, chunk: number) {
+    return this.storage.get<Uint8Array>(`blob:${id}:${chunk}`);
+  }
+}
+
+type RoomInfo = {
+  createdAt: number;
+  createdBy: string;
+  topic?: string;
+  topicSetBy?: string;
+  topicSetAt?: number;
+};
+
+type SendOptions = {
+  replyTo?: number;
+  kind?: MessageKind;
+  // Id of a finished upload to attach
+  attachment?: number;
+};
+
+type SearchOptions = {
+  // Refused; kept so older clients get an error rather than wrong results
+  regex?: boolean;
+  caseSensitive?: boolean;
 };
 
 type NickTokenInfo = {
@@ -623,33 +2359,58 @@ type NickTokenInfo = {
 
 type ChatState = {
   credentials: Record<string, string>;
-  messages: Array<{ from: string; body: string; timestamp: number }>;
+  messages: StoredMessage[];
+  nextMessageId: number;
+  rooms: Record<string, RoomInfo>;
   nextSessionCapId: number;
   sessionCaps: Record<string, SessionInfo>;
   registeredNicks: Record<string, string>; // nickname -> password
   nickOwners: Record<string, string>; // nickname -> username
   nickTokens: Record<string, NickTokenInfo>;
+  // Room -> registered nick -> role
+  roles: Record<string, Record<string, Role>>;
+  restrictions: Record<string, Restriction[]>;
+  // Moderation actions, oldest first, capped at AUDIT_LOG_LIMIT
+  audit: AuditEntry[];
+  attachments: Record<string, AttachmentInfo>;
+  nextAttachmentId: number;
 };
 
 const DEFAULT_CHAT_STATE: ChatState = {
   credentials: {},
   messages: [],
+  nextMessageId: 1,
+  rooms: {
+    [DEFAULT_ROOM]: { createdAt: 0, createdBy: 'server' },
+  },
   nextSessionCapId: SESSION_CAPABILITY_START,
   sessionCaps: {},
   registeredNicks: {},
   nickOwners: {},
   nickTokens: {},
+  roles: {},
+  restrictions: {},
+  audit: [],
+  attachments: {},
+  nextAttachmentId: 1,
 };
 
 function cloneDefaultChatState(): ChatState {
   return {
     credentials: { ...DEFAULT_CHAT_STATE.credentials },
     messages: [...DEFAULT_CHAT_STATE.messages],
+    nextMessageId: DEFAULT_CHAT_STATE.nextMessageId,
+    rooms: { ...DEFAULT_CHAT_STATE.rooms },
     nextSessionCapId: DEFAULT_CHAT_STATE.nextSessionCapId,
     sessionCaps: { ...DEFAULT_CHAT_STATE.sessionCaps },
     registeredNicks: { ...DEFAULT_CHAT_STATE.registeredNicks },
     nickOwners: { ...DEFAULT_CHAT_STATE.nickOwners },
     nickTokens: { ...DEFAULT_CHAT_STATE.nickTokens },
+    roles: {},
+    restrictions: {},
+    audit: [],
+    attachments: {},
+    nextAttachmentId: DEFAULT_CHAT_STATE.nextAttachmentId,
   };
 }
 
@@ -671,6 +2432,11 @@ function normalizeChatState(parsed: unknown): ChatState {
     }
   }
 
+  let nextMessageId = base.nextMessageId;
+  if (typeof source.nextMessageId === "number" && Number.isFinite(source.nextMessageId)) {
+    nextMessageId = Math.max(nextMessageId, Math.floor(source.nextMessageId));
+  }
+
   const messages: ChatState["messages"] = [];
   if (Array.isArray(source.messages)) {
     for (const entry of source.messages) {
@@ -679,14 +2445,66 @@ function normalizeChatState(parsed: unknown): ChatState {
         const from = typeof record.from === "string" ? record.from : null;
         const body = typeof record.body === "string" ? record.body : null;
         const timestamp = typeof record.timestamp === "number" ? record.timestamp : Date.now();
+        const room = typeof record.room === "string" ? record.room : DEFAULT_ROOM;
+        const author = typeof record.author === "string" ? record.author : undefined;
+        const editedAt = typeof record.editedAt === "number" ? record.editedAt : undefined;
+        const replyTo = typeof record.replyTo === "number" ? record.replyTo : undefined;
+        const kind = MESSAGE_KINDS.find((known) => known === record.kind);
+        const attachment = normalizeAttachmentRef(record.attachment);
+        const reactions: Record<string, string[]> = {};
+        if (record.reactions && typeof record.reactions === "object") {
+          for (const [emoji, users] of Object.entries(record.reactions as Record<string, unknown>)) {
+            if (Array.isArray(users)) {
+              const names = users.filter((user): user is string => typeof user === "string");
+              if (names.length > 0) {
+                reactions[emoji] = names;
+              }
+            }
+          }
+        }
+        // Messages stored before ids existed get one on load
+        let id = typeof record.id === "number" ? record.id : null;
+        if (id === null) {
+          id = nextMessageId;
+          nextMessageId += 1;
+        }
         if (from && body) {
-          messages.push({ from, body, timestamp });
+          messages.push({
+            id,
+            from,
+            ...(author ? { author } : {}),
+            body,
+            timestamp,
+            room,
+            ...(editedAt !== undefined ? { editedAt } : {}),
+            ...(replyTo !== undefined ? { replyTo } : {}),
+            ...(Object.keys(reactions).length > 0 ? { reactions } : {}),
+            ...(kind && kind !== "normal" ? { kind } : {}),
+            ...(attachment ? { attachment } : {}),
+          });
         }
       }
     }
   } else {
     messages.push(...base.messages);
   }
+  nextMessageId = messages.reduce((next, msg) => Math.max(next, msg.id + 1), nextMessageId);
+
+  const rooms: Record<string, RoomInfo> = { ...base.rooms };
+  if (source.rooms && typeof source.rooms === "object") {
+    for (const [key, value] of Object.entries(source.rooms as Record<string, unknown>)) {
+      if (value && typeof value === "object") {
+        const entry = value as Record<string, unknown>;
+        rooms[key] = {
+          createdAt: typeof entry.createdAt === "number" ? entry.createdAt : Date.now(),
+          createdBy: typeof entry.createdBy === "string" ? entry.createdBy : "unknown",
+          ...(typeof entry.topic === "string" && entry.topic ? { topic: entry.topic } : {}),
+          ...(typeof entry.topicSetBy === "string" ? { topicSetBy: entry.topicSetBy } : {}),
+          ...(typeof entry.topicSetAt === "number" ? { topicSetAt: entry.topicSetAt } : {}),
+        };
+      }
+    }
+  }
 
   let nextSessionCapId = base.nextSessionCapId;
   if (typeof source.nextSessionCapId === "number" && Number.isFinite(source.nextSessionCapId)) {
@@ -702,10 +2520,14 @@ function normalizeChatState(parsed: unknown): ChatState {
       if (value && typeof value === "object") {
         const username = (value as Record<string, unknown>).username;
         const displayName = (value as Record<string, unknown>).displayName;
+        const sessionRoomList = (value as Record<string, unknown>).rooms;
         if (typeof username === "string") {
           sessionCaps[key] = {
             username,
             ...(typeof displayName === 'string' ? { displayName } : {}),
+            ...(Array.isArray(sessionRoomList)
+              ? { rooms: sessionRoomList.filter((room): room is string => typeof room === "string") }
+              : {}),
           };
         }
       }
@@ -756,14 +2578,106 @@ function normalizeChatState(parsed: unknown): ChatState {
     }
   }
 
+  const roles: ChatState["roles"] = {};
+  if (source.roles && typeof source.roles === "object") {
+    for (const [room, entries] of Object.entries(source.roles as Record<string, unknown>)) {
+      if (!entries || typeof entries !== "object") {
+        continue;
+      }
+      const roomRoles: Record<string, Role> = {};
+      for (const [nickname, value] of Object.entries(entries as Record<string, unknown>)) {
+        const role = ROLES.find((known) => known === value);
+        if (role && role !== "user") {
+          roomRoles[nickname] = role;
+        }
+      }
+      roles[room] = roomRoles;
+    }
+  }
+
+  const restrictions: ChatState["restrictions"] = {};
+  if (source.restrictions && typeof source.restrictions === "object") {
+    for (const [room, list] of Object.entries(source.restrictions as Record<string, unknown>)) {
+      if (!Array.isArray(list)) {
+        continue;
+      }
+      restrictions[room] = list.flatMap((value): Restriction[] => {
+        const entry = value as Record<string, unknown>;
+        if (
+          !entry ||
+          (entry.kind !== "ban" && entry.kind !== "mute") ||
+          typeof entry.mask !== "string" ||
+          typeof entry.setBy !== "string" ||
+          typeof entry.setAt !== "number"
+        ) {
+          return [];
+        }
+        return [{
+          kind: entry.kind,
+          mask: entry.mask,
+          setBy: entry.setBy,
+          setAt: entry.setAt,
+          ...(typeof entry.expiresAt === "number" ? { expiresAt: entry.expiresAt } : {}),
+          ...(typeof entry.reason === "string" ? { reason: entry.reason } : {}),
+        }];
+      });
+    }
+  }
+
+  const audit: AuditEntry[] = Array.isArray(source.audit)
+    ? (source.audit as AuditEntry[])
+        .filter((entry) => entry && typeof entry.at === "number" && typeof entry.room === "string")
+        .slice(-AUDIT_LOG_LIMIT)
+    : [];
+
+  const attachments: ChatState["attachments"] = {};
+  if (source.attachments && typeof source.attachments === "object") {
+    for (const [key, value] of Object.entries(source.attachments as Record<string, unknown>)) {
+      const ref = normalizeAttachmentRef(value);
+      const entry = value as Record<string, unknown>;
+      if (ref && typeof entry.chunks === "number" && entry.chunks > 0) {
+        // Without a recorded uploader nobody may send or fetch it
+        const parties = Array.isArray(entry.parties)
+          ? (entry.parties as AttachmentParty[]).filter(
+              (party) => party && typeof party.account === "string",
+            )
+          : undefined;
+        attachments[key] = {
+          ...ref,
+          chunks: entry.chunks,
+          uploadedBy: typeof entry.uploadedBy === "string" ? entry.uploadedBy : "unknown",
+          uploadedAt: typeof entry.uploadedAt === "number" ? entry.uploadedAt : 0,
+          uploader: typeof entry.uploader === "string" ? entry.uploader : "",
+          ...(typeof entry.room === "string" ? { room: entry.room } : {}),
+          ...(parties ? { parties } : {}),
+        };
+      }
+    }
+  }
+  let nextAttachmentId = base.nextAttachmentId;
+  if (typeof source.nextAttachmentId === "number" && Number.isFinite(source.nextAttachmentId)) {
+    nextAttachmentId = Math.max(nextAttachmentId, Math.floor(source.nextAttachmentId));
+  }
+  nextAttachmentId = Object.values(attachments).reduce(
+    (next, info) => Math.max(next, info.id + 1),
+    nextAttachmentId,
+  );
+
   return {
     credentials,
     messages,
+    nextMessageId,
+    rooms,
     nextSessionCapId,
     sessionCaps,
     registeredNicks,
     nickOwners,
     nickTokens,
+    roles,
+    restrictions,
+    audit,
+    attachments,
+    nextAttachmentId,
   };
 }
 
@@ -783,3 +2697,166 @@ async function loadChatState(state: DurableObjectStateWithStorage): Promise<Chat
 async function persistChatState(state: DurableObjectStateWithStorage, chatState: ChatState) {
   await state.storage.put("chatState", JSON.stringify(chatState));
 }
+
+function normalizeRoomName(room: string): string {
+  if (typeof room !== 'string') {
+    throw new TypeError('room name must be a string');
+  }
+  const trimmed = room.trim();
+  const name = trimmed.startsWith('#') ? trimmed : `#${trimmed}`;
+  if (!ROOM_NAME_PATTERN.test(name)) {
+    throw new Error(`invalid room name '${room}'`);
+  }
+  return name;
+}
+
+function toWireMessage(message: StoredMessage): WireMessage {
+  return {
+    id: message.id,
+    from: message.from,
+    body: message.body,
+    timestamp: message.timestamp,
+    room: message.room,
+    ...(message.editedAt !== undefined ? { editedAt: message.editedAt } : {}),
+    ...(message.replyTo !== undefined ? { replyTo: message.replyTo } : {}),
+    ...(message.reactions ? { reactions: reactionList(message) } : {}),
+    ...(message.kind && message.kind !== 'normal' ? { kind: message.kind } : {}),
+    ...(message.attachment ? { attachment: message.attachment } : {}),
+  };
+}
+
+// The attachment a send refers to, which must be a finished upload of the
+// sender's that has not been sent before. Callers record where it went.
+function attachmentOption(
+  chatState: ChatState,
+  sessionInfo: SessionInfo,
+  options: SendOptions,
+): AttachmentInfo | undefined {
+  if (options.attachment === undefined) {
+    return undefined;
+  }
+  const info = chatState.attachments[String(options.attachment)];
+  if (!info || info.uploader !== sessionInfo.username) {
+    throw new Error(`no attachment with id ${options.attachment}`);
+  }
+  if (info.room !== undefined || info.parties !== undefined) {
+    throw new Error(`attachment ${options.attachment} has already been sent`);
+  }
+  return info;
+}
+
+function attachmentRef(info: AttachmentInfo): AttachmentRef {
+  return { id: info.id, name: info.name, size: info.size, mime: info.mime };
+}
+
+function attachmentParty(sessionInfo: SessionInfo): AttachmentParty {
+  return {
+    account: sessionInfo.username,
+    ...(sessionInfo.displayName ? { nick: sessionInfo.displayName } : {}),
+  };
+}
+
+function mayDownload(info: AttachmentInfo, sessionInfo: SessionInfo): boolean {
+  if (info.uploader === sessionInfo.username) {
+    return true;
+  }
+  if (info.room !== undefined) {
+    return sessionRooms(sessionInfo).includes(info.room);
+  }
+  return (info.parties ?? []).some(
+    (party) =>
+      party.account === sessionInfo.username ||
+      (!!party.nick && party.nick === sessionInfo.displayName),
+  );
+}
+
+// The last path component, on one line and within the length limit.
+function attachmentName(name: unknown): string | undefined {
+  if (typeof name !== 'string') {
+    return undefined;
+  }
+  const base = name.split(/[\\/]/).pop() ?? '';
+  const clean = base.replace(/[\u0000-\u001f\u007f]/g, '').trim();
+  return clean.slice(0, ATTACHMENT_NAME_MAX_LENGTH) || undefined;
+}
+
+function normalizeAttachmentRef(value: unknown): AttachmentRef | undefined {
+  if (!value || typeof value !== 'object') {
+    return undefined;
+  }
+  const entry = value as Record<string, unknown>;
+  if (
+    typeof entry.id !== 'number' ||
+    typeof entry.name !== 'string' ||
+    typeof entry.size !== 'number' ||
+    typeof entry.mime !== 'string'
+  ) {
+    return undefined;
+  }
+  return { id: entry.id, name: entry.name, size: entry.size, mime: entry.mime };
+}
+
+function nickRole(env: Env, chatState: ChatState, nickname: string, room: string): Role {
+  if (env.CHAT_OWNER && nickname === env.CHAT_OWNER) {
+    return 'owner';
+  }
+  return chatState.roles[room]?.[nickname] ?? 'user';
+}
+
+// Restrictions on `room` that have not expired.
+function liveRestrictions(chatState: ChatState, room: string): Restriction[] {
+  const now = Date.now();
+  return (chatState.restrictions[room] ?? []).filter(
+    (restriction) => restriction.expiresAt === undefined || restriction.expiresAt > now,
+  );
+}
+
+// `*` matches any run of characters and `?` any one, ignoring case, against
+// both the session's nick and its guest name.
+function maskMatches(mask: string, sessionInfo: SessionInfo): boolean {
+  const source = mask
+    .split('')
+    .map((c) => (c === '*' ? '.*' : c === '?' ? '.' : c.replace(/[.+^${}()|[\]\\]/g, '\\$&')))
+    .join('');
+  const pattern = new RegExp(`^${source}$`, 'i');
+  return [sessionInfo.displayName, sessionInfo.username].some(
+    (name) => name !== undefined && pattern.test(name),
+  );
+}
+
+function cleanReason(reason: unknown): string | undefined {
+  if (typeof reason !== 'string') {
+    return undefined;
+  }
+  const text = reason.replace(/\s+/g, ' ').trim().slice(0, REASON_MAX_LENGTH);
+  return text || undefined;
+}
+
+function roomTopic(room: string, info: RoomInfo): RoomTopic {
+  if (!info.topic) {
+    return { room, topic: null };
+  }
+  return {
+    room,
+    topic: info.topic,
+    ...(info.topicSetBy ? { setBy: info.topicSetBy } : {}),
+    ...(info.topicSetAt !== undefined ? { setAt: info.topicSetAt } : {}),
+  };
+}
+
+function clientMessageKind(options: SendOptions): MessageKind {
+  const kind = options.kind ?? 'normal';
+  if (!CLIENT_MESSAGE_KINDS.includes(kind)) {
+    throw new Error(`cannot send ${String(kind)} messages`);
+  }
+  return kind;
+}
+
+function reactionList(message: StoredMessage): Reaction[] {
+  return Object.entries(message.reactions ?? {}).map(([emoji, users]) => ({ emoji, users }));
+}
+
+// Sessions created before rooms existed are members of the default room only.
+function sessionRooms(sessionInfo: SessionInfo): string[] {
+  return sessionInfo.rooms ?? [DEFAULT_ROOM];
+}